@@ -1,25 +1,37 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use actix_cors::Cors;
 use actix_web::{
+    dev::Service,
     get,
-    http::StatusCode,
-    post,
-    web::{self, Json, Path},
-    App, HttpResponse, HttpServer, Responder, ResponseError, Result,
+    http::{header, StatusCode},
+    patch, post,
+    web::{self, Bytes, Json, Path},
+    App, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError, Result,
 };
-use poise::serenity_prelude::{GuildId, Mutex};
+use poise::serenity_prelude::GuildId;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use serde_json::Value;
+use tracing::{info, info_span, instrument, warn, Instrument};
 
 use crate::{
-    client::bot::State,
+    client::{
+        bot::State,
+        chapters,
+        limits::Limit,
+        player::{GuildPlayer, Request},
+        settings::GuildSettings,
+        sources::lazy::QueueEntry,
+    },
     error::{self, AppError},
 };
 
-use super::types::Track;
+use super::{
+    feed, health, library, routing,
+    types::{Lyrics, Track},
+};
 
-type DataState = web::Data<Arc<Mutex<State>>>;
+type DataState = web::Data<State>;
 
 #[derive(Debug, Serialize)]
 struct AppErrorResponse {
@@ -30,6 +42,11 @@ impl ResponseError for AppError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self.error_type {
             error::AppErrorType::NotFound => StatusCode::NOT_FOUND,
+            error::AppErrorType::InvalidInput => StatusCode::BAD_REQUEST,
+            error::AppErrorType::Forbidden => StatusCode::FORBIDDEN,
+            error::AppErrorType::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            error::AppErrorType::LimitExceeded(Limit::Cooldown(_)) => StatusCode::TOO_MANY_REQUESTS,
+            error::AppErrorType::LimitExceeded(_) => StatusCode::FORBIDDEN,
             error::AppErrorType::SongbirdError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -53,88 +70,261 @@ async fn ping() -> impl Responder {
 
 #[get("/guilds/with_queues")]
 async fn guilds_with_queues(state: DataState) -> Result<Json<Vec<GuildId>>> {
-    let state = state.lock().await;
-    let queues = state.queues.lock().await;
-    let guild_ids = queues.keys().cloned().collect();
-
-    Ok(Json(guild_ids))
+    Ok(Json(state.queues.guild_ids()))
 }
 
 #[get("/queues/queue/{guild_id}")]
-async fn queue(state: DataState, guild_id: Path<u64>) -> Result<Json<Vec<Track>>> {
+#[instrument(skip_all, fields(guild_id = *guild_id))]
+async fn queue(
+    state: DataState,
+    request: HttpRequest,
+    guild_id: Path<u64>,
+) -> Result<HttpResponse> {
     let guild_id = GuildId(*guild_id);
-    let state = state.lock().await;
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild_id).ok_or(AppError::not_found())?;
+    if let Some(response) = forward_to_owner(&state, &request, guild_id, Bytes::new()).await? {
+        return Ok(response);
+    }
+
+    let queue = state.queues.get(guild_id).ok_or(AppError::not_found())?;
 
-    let tracks = queue
-        .current_queue()
-        .into_iter()
-        .map(Track::from)
-        .collect::<Vec<_>>();
+    let mut tracks = vec![];
+    for track in queue.read().current_queue() {
+        let chapters = chapters::of_track(&state.resolvers, &track).await;
+        tracks.push(Track::new(track, &chapters));
+    }
 
-    Ok(Json(tracks))
+    Ok(HttpResponse::Ok().json(tracks))
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct TrackUrl {
     track_url: String,
 }
 
 #[post("/queues/queue/{guild_id}/add-song")]
+#[instrument(skip_all, fields(guild_id = *guild_id, url = track_url.track_url))]
 async fn add_song_to_queue(
     state: DataState,
+    request: HttpRequest,
     guild_id: Path<u64>,
     track_url: Json<TrackUrl>,
-) -> Result<Json<Track>> {
+) -> Result<HttpResponse> {
     let guild_id = GuildId(*guild_id);
-    let state = state.lock().await;
-    let queues = state.queues.lock().await;
-    let this_queue = queues.get(&guild_id).ok_or(AppError::not_found())?;
+    let body = Bytes::from(serde_json::to_vec(&*track_url)?);
+    if let Some(response) = forward_to_owner(&state, &request, guild_id, body).await? {
+        return Ok(response);
+    }
+
+    let player =
+        GuildPlayer::get(&state.clone().into_inner(), guild_id).ok_or(AppError::not_found())?;
 
-    let voice = state.songbird_instance.clone();
-    let handler = voice.get(guild_id).ok_or(AppError::not_found())?;
+    let settings = player.settings().await;
+    let limits = &settings.limits;
+    settings.check_source(&track_url.track_url)?;
+    limits.check_request(&track_url.track_url)?;
+    limits
+        .check_queue(Some(player.queue().read()), None)
+        .await?;
 
-    let source = songbird::ytdl(track_url.0.track_url)
+    // looked up before locking the queue, yt-dlp can take a while
+    let entry = QueueEntry::lookup(&state.resolvers, &track_url.track_url, limits).await?;
+    let handle = player.enqueue(entry, Request::default()).await?;
+
+    let chapters = chapters::of_track(&state.resolvers, &handle).await;
+
+    Ok(HttpResponse::Ok().json(Track::new(handle, &chapters)))
+}
+
+/// Forwards the request to another node if that one is in a call in `guild_id`.
+async fn forward_to_owner(
+    state: &State,
+    request: &HttpRequest,
+    guild_id: GuildId,
+    body: Bytes,
+) -> Result<Option<HttpResponse>, AppError> {
+    let (Some(cluster), Some(node)) = (
+        &state.cluster,
+        routing::owner(state, request, guild_id).await?,
+    ) else {
+        return Ok(None);
+    };
+
+    routing::forward(cluster, &node, request, body)
         .await
-        .map_err(AppError::from)?;
+        .map(Some)
+}
 
-    let mut handler = handler.lock().await;
+/// The lyrics of the current track, with the line being sung if they are synced.
+#[get("/queues/queue/{guild_id}/lyrics")]
+#[instrument(skip_all, fields(guild_id = *guild_id))]
+async fn lyrics(
+    state: DataState,
+    request: HttpRequest,
+    guild_id: Path<u64>,
+) -> Result<HttpResponse> {
+    let guild_id = GuildId(*guild_id);
+    if let Some(response) = forward_to_owner(&state, &request, guild_id, Bytes::new()).await? {
+        return Ok(response);
+    }
 
-    let track = this_queue.add_source(source, &mut handler);
+    let guild_queue = state.queues.get(guild_id).ok_or(AppError::not_found())?;
+    let current = guild_queue.read().current().ok_or(AppError::not_found())?;
+    let lyrics = state
+        .lyrics
+        .of_track(&current)
+        .await
+        .ok_or(AppError::not_found())?;
+    // fails if the track ended in the meantime
+    let position = match current.get_info().await {
+        Ok(info) => info.position,
+        Err(_) => Duration::ZERO,
+    };
 
-    Ok(Json(track.into()))
+    Ok(HttpResponse::Ok().json(Lyrics::at(&lyrics, position)))
 }
 
-pub async fn api_server(state: Arc<Mutex<State>>) {
-    if std::env::var("DISABLE_WEB_API").is_ok() {
-        info!("Not starting api-server because env variable DISABLE_WEB_API is set");
+#[get("/guilds/{guild_id}/settings")]
+#[instrument(skip_all, fields(guild_id = *guild_id))]
+async fn guild_settings(state: DataState, guild_id: Path<u64>) -> Result<Json<GuildSettings>> {
+    let guild_id = GuildId(*guild_id);
+
+    Ok(Json(state.settings.get(guild_id).await))
+}
+
+/// Changes settings like `/settings set` does, which needs `Authorization: Bearer <api.token>`.
+#[patch("/guilds/{guild_id}/settings")]
+#[instrument(skip_all, fields(guild_id = *guild_id))]
+async fn patch_guild_settings(
+    state: DataState,
+    request: HttpRequest,
+    guild_id: Path<u64>,
+    body: Bytes,
+) -> Result<HttpResponse> {
+    let guild_id = GuildId(*guild_id);
+    check_token(&state, &request)?;
+    if let Some(response) = forward_to_owner(&state, &request, guild_id, body.clone()).await? {
+        return Ok(response);
+    }
+
+    let patch: Value =
+        serde_json::from_slice(&body).map_err(|err| AppError::invalid_input(err.to_string()))?;
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            *settings = settings.patch(patch)?;
+
+            Ok(())
+        })
+        .await
+        .map_err(|err| match err.downcast::<AppError>() {
+            Ok(app_error) => *app_error,
+            Err(err) => AppError::internal(err.to_string()),
+        })?;
+
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// Checks that `request` carries the configured api token.
+fn check_token(state: &State, request: &HttpRequest) -> Result<(), AppError> {
+    let Some(token) = &state.config.api.token else {
+        return Err(AppError::forbidden(
+            "Settings can't be changed through the api without an api token, use /settings",
+        ));
+    };
+
+    let sent = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match sent {
+        Some(sent) if sent == token => Ok(()),
+        _ => Err(AppError::forbidden("Missing or wrong api token")),
+    }
+}
+
+pub async fn api_server(state: Arc<State>) {
+    let (config, shutdown) = (state.config.clone(), state.shutdown.clone());
+    let timeout = config.shutdown.timeout_secs;
+    let config = config.api.clone();
+
+    if !config.enabled {
+        info!("Not starting api-server because it is disabled in the config");
         return;
     }
 
-    let host = std::env::var("API_HOST").unwrap_or("0.0.0.0".to_owned());
-    let port = std::env::var("API_PORT")
-        .unwrap_or("8080".to_owned())
-        .parse()
-        .expect("Incorrect value for env variable API_PORT");
+    let cors_origins = config.cors_origins.clone();
 
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+    let server = HttpServer::new(move || {
+        let cors = match cors_origins.is_empty() {
+            true => Cors::permissive(),
+            false => cors_origins
+                .iter()
+                .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+                .allow_any_method()
+                .allow_any_header(),
+        };
 
         App::new()
+            .wrap_fn(|request, service| {
+                let span = info_span!(
+                    "request",
+                    method = %request.method(),
+                    path = request.path(),
+                );
+
+                let response = service.call(request);
+                async move {
+                    let response = response.await;
+                    match &response {
+                        Ok(response) => {
+                            info!(status = response.status().as_u16(), "Handled request")
+                        }
+                        Err(err) => warn!("Request failed: {err}"),
+                    }
+
+                    response
+                }
+                .instrument(span)
+            })
             .wrap(cors)
-            .app_data(DataState::new(state.clone()))
+            .app_data(DataState::from(state.clone()))
+            .service(health::healthz)
+            .service(health::readyz)
+            .service(health::metrics)
             .service(
                 web::scope("/api")
                     .service(ping)
                     .service(guilds_with_queues)
                     .service(queue)
-                    .service(add_song_to_queue),
+                    .service(add_song_to_queue)
+                    .service(lyrics)
+                    .service(guild_settings)
+                    .service(patch_guild_settings)
+                    .service(feed::feed)
+                    .service(routing::cluster_nodes)
+                    .service(library::library_tracks)
+                    .service(library::library_albums)
+                    .service(library::library_album)
+                    .service(library::library_artists)
+                    .service(library::library_artist)
+                    .service(library::library_cover),
             )
     })
-    .bind((host, port))
+    // shutdown is coordinated in main, together with the bot
+    .disable_signals()
+    .shutdown_timeout(timeout)
+    .bind((config.host, config.port))
     .expect("Could not bind port")
-    .run()
-    .await
-    .expect("Could not start server");
+    .run();
+
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown.wait().await;
+
+        info!("Stopping api-server");
+        handle.stop(true).await;
+    });
+
+    server.await.expect("Could not start server");
 }
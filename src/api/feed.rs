@@ -0,0 +1,99 @@
+use actix_web::{
+    get,
+    web::{self, Payload, Query},
+    HttpRequest, HttpResponse, Result,
+};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use poise::serenity_prelude::GuildId;
+use serde::Deserialize;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{debug, warn};
+
+use crate::{
+    client::{bot::State, feed::FeedEvent},
+    shutdown::Shutdown,
+};
+
+type DataState = web::Data<State>;
+
+#[derive(Deserialize)]
+struct FeedFilter {
+    guild_id: Option<u64>,
+}
+
+/// Streams every [`FeedEvent`] as a json text message over a websocket, only the ones of
+/// `?guild_id=` if it is given. Clients only get answers to pings and closes.
+#[get("/feed")]
+async fn feed(
+    state: DataState,
+    request: HttpRequest,
+    body: Payload,
+    filter: Query<FeedFilter>,
+) -> Result<HttpResponse> {
+    let (response, session, messages) = actix_ws::handle(&request, body)?;
+
+    let guild_id = filter.guild_id.map(GuildId);
+    let events = state.feed.subscribe();
+    actix_web::rt::spawn(stream_feed(
+        session,
+        messages,
+        events,
+        state.shutdown.clone(),
+        guild_id,
+    ));
+
+    Ok(response)
+}
+
+async fn stream_feed(
+    mut session: Session,
+    mut messages: MessageStream,
+    mut events: Receiver<FeedEvent>,
+    shutdown: Shutdown,
+    guild_id: Option<GuildId>,
+) {
+    let reason = loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if guild_id.is_none_or(|guild_id| event.guild_id() == guild_id) => {
+                    let json =
+                        serde_json::to_string(&event).expect("feed events can always be serialized");
+                    // the client is gone
+                    if session.text(json).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        skipped,
+                        "A feed client is too slow, dropped some events for it"
+                    )
+                }
+                Err(RecvError::Closed) => break None,
+            },
+            message = messages.recv() => match message {
+                Some(Ok(Message::Ping(bytes))) => {
+                    // the client is gone
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(Message::Close(reason))) => break reason,
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    debug!("A feed client broke the websocket protocol: {err}");
+                    break Some(CloseCode::Protocol.into());
+                }
+                // the client is gone
+                None => return,
+            },
+            () = shutdown.wait() => break Some(CloseReason::from(CloseCode::Restart)),
+        }
+    };
+
+    // fails if the client closed the connection first, which is fine
+    if session.close(reason).await.is_err() {
+        debug!("The feed websocket was closed already");
+    }
+}
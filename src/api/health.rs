@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use serenity::gateway::ConnectionStage;
+
+use crate::{
+    client::bot::{Gateway, State},
+    metrics::METRICS,
+};
+
+type DataState = web::Data<State>;
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    checks: BTreeMap<&'static str, String>,
+}
+
+/// The process is up and serving requests, nothing else is checked.
+#[get("/healthz")]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Ready once discord is connected, songbird can join calls and storage is writable.
+#[get("/readyz")]
+async fn readyz(state: DataState) -> impl Responder {
+    let mut checks = BTreeMap::new();
+
+    let gateway_state = state.gateway.lock().await;
+    let gateway = match &*gateway_state {
+        Gateway::Disabled => Ok("disabled".to_string()),
+        Gateway::Starting => Err("starting".to_string()),
+        Gateway::Running { shard_manager, .. } => {
+            let shard_manager = shard_manager.lock().await;
+            let runners = shard_manager.runners.lock().await;
+
+            let disconnected = runners
+                .iter()
+                .filter(|(_, runner)| runner.stage != ConnectionStage::Connected)
+                .map(|(id, runner)| format!("shard {id} is {}", runner.stage))
+                .collect::<Vec<_>>();
+
+            match (runners.is_empty(), disconnected.is_empty()) {
+                (true, _) => Err("no shards are running".to_string()),
+                (false, true) => Ok(format!("{} shards connected", runners.len())),
+                (false, false) => Err(disconnected.join(", ")),
+            }
+        }
+    };
+    let voice_gateway = &state.voice_gateway;
+    let voice = match (&*gateway_state, voice_gateway.shards().as_slice()) {
+        (Gateway::Disabled, _) => Ok("disabled".to_string()),
+        _ if !voice_gateway.is_initialised() => Err("not initialised".to_string()),
+        (_, []) => Err("not registered with any shard".to_string()),
+        (_, shards) => Ok(format!("registered with {} shards", shards.len())),
+    };
+    drop(gateway_state);
+    let storage = match state.storage.check().await {
+        Ok(()) => Ok("ok".to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    let mut ready = true;
+    for (name, check) in [("gateway", gateway), ("voice", voice), ("storage", storage)] {
+        ready &= check.is_ok();
+        checks.insert(name, check.unwrap_or_else(|err| err));
+    }
+
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "not ready" },
+        checks,
+    };
+
+    match ready {
+        true => HttpResponse::Ok().json(readiness),
+        false => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
+
+#[get("/metrics")]
+async fn metrics(state: DataState) -> impl Responder {
+    METRICS.queued_tracks.reset();
+    let mut voice_connections = 0;
+    for (guild_id, queue) in state.queues.all() {
+        METRICS
+            .queued_tracks
+            .with_label_values(&[&guild_id.to_string()])
+            .set(queue.read().len() as i64);
+
+        if let Some(call) = state.songbird_instance.get(guild_id) {
+            if call.lock().await.current_connection().is_some() {
+                voice_connections += 1;
+            }
+        }
+    }
+    METRICS.voice_connections.set(voice_connections);
+    METRICS
+        .cache_entries
+        .set(state.metadata_cache.len().await as i64);
+
+    if let Gateway::Running { shard_manager, .. } = &*state.gateway.lock().await {
+        let shard_manager = shard_manager.lock().await;
+        for (id, runner) in shard_manager.runners.lock().await.iter() {
+            METRICS.set_gateway_latency(id.0, runner.latency);
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(METRICS.render())
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use actix_web::{
+    get,
+    web::{self, Json, Path, Query},
+    HttpResponse, Result,
+};
+use serde::Deserialize;
+
+use crate::{
+    client::bot::State,
+    error::AppError,
+    library::{Album, Artist, Library, LibraryTrack},
+};
+
+type DataState = web::Data<State>;
+
+/// Used when `/library/tracks` is called without a limit.
+const DEFAULT_LIMIT: usize = 50;
+
+#[derive(Deserialize)]
+struct TrackSearch {
+    #[serde(default)]
+    q: String,
+    limit: Option<usize>,
+}
+
+async fn media_library(state: &DataState) -> Result<Arc<Library>> {
+    let library = state.library.clone();
+
+    Ok(library.ok_or_else(AppError::not_found)?)
+}
+
+/// Tracks matching `q`, or all tracks if it is empty.
+#[get("/library/tracks")]
+async fn library_tracks(
+    state: DataState,
+    search: Query<TrackSearch>,
+) -> Result<Json<Vec<LibraryTrack>>> {
+    let limit = search.limit.unwrap_or(DEFAULT_LIMIT);
+
+    Ok(Json(
+        media_library(&state).await?.search(&search.q, limit).await,
+    ))
+}
+
+#[get("/library/albums")]
+async fn library_albums(state: DataState) -> Result<Json<Vec<Album>>> {
+    Ok(Json(media_library(&state).await?.albums().await))
+}
+
+#[get("/library/albums/{album}")]
+async fn library_album(state: DataState, album: Path<String>) -> Result<Json<Vec<LibraryTrack>>> {
+    let tracks = media_library(&state).await?.album(&album).await;
+
+    match tracks.is_empty() {
+        true => Err(AppError::not_found().into()),
+        false => Ok(Json(tracks)),
+    }
+}
+
+#[get("/library/artists")]
+async fn library_artists(state: DataState) -> Result<Json<Vec<Artist>>> {
+    Ok(Json(media_library(&state).await?.artists().await))
+}
+
+#[get("/library/artists/{artist}")]
+async fn library_artist(state: DataState, artist: Path<String>) -> Result<Json<Vec<LibraryTrack>>> {
+    let tracks = media_library(&state).await?.artist(&artist).await;
+
+    match tracks.is_empty() {
+        true => Err(AppError::not_found().into()),
+        false => Ok(Json(tracks)),
+    }
+}
+
+/// The cover art embedded in a track, `path` is the path of the track in the library.
+#[get("/library/covers/{path:.*}")]
+async fn library_cover(state: DataState, path: Path<String>) -> Result<HttpResponse> {
+    let cover = media_library(&state)
+        .await?
+        .cover(&path)
+        .await
+        .ok_or_else(AppError::not_found)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(cover.mime_type)
+        .body(cover.data))
+}
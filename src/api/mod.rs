@@ -1,2 +1,6 @@
 pub mod endpoints;
+pub mod feed;
+pub mod health;
+pub mod library;
+pub mod routing;
 pub mod types;
@@ -0,0 +1,121 @@
+//! Sends api requests for a guild to the node that is in a call there, see [`crate::cluster`].
+
+use actix_web::{
+    get,
+    http::header,
+    web::{self, Bytes, Json, Query},
+    HttpRequest, HttpResponse, Result,
+};
+use poise::serenity_prelude::GuildId;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    client::bot::State,
+    cluster::{Cluster, Node},
+    error::AppError,
+};
+
+type DataState = web::Data<State>;
+
+/// Set on forwarded requests, which are always handled by the node they were sent to.
+const FORWARDED_HEADER: &str = "x-oxo-forwarded-by";
+
+#[derive(Deserialize)]
+struct NodeQuery {
+    node: Option<String>,
+}
+
+/// Every node of the cluster and the calls it is in.
+#[get("/cluster/nodes")]
+async fn cluster_nodes(state: DataState) -> Result<Json<Vec<Node>>> {
+    let cluster = state.cluster.as_ref().ok_or(AppError::not_found())?;
+    let nodes = cluster.nodes().await.map_err(internal)?;
+
+    Ok(Json(nodes))
+}
+
+/// The node that should handle `request` for `guild_id`, `None` if it is this one.
+///
+/// `?node=<node_id>` picks a node, e.g. if several bots are in a call in the guild. Otherwise
+/// this node handles it if it is in a call there, or else any node that is.
+pub async fn owner(
+    state: &State,
+    request: &HttpRequest,
+    guild_id: GuildId,
+) -> Result<Option<Node>, AppError> {
+    let Some(cluster) = &state.cluster else {
+        return Ok(None);
+    };
+    if request.headers().contains_key(FORWARDED_HEADER) {
+        return Ok(None);
+    }
+
+    let node_id = Query::<NodeQuery>::from_query(request.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().node);
+    match node_id {
+        Some(node_id) if node_id == cluster.node_id() => Ok(None),
+        Some(node_id) => {
+            let nodes = cluster.nodes().await.map_err(internal)?;
+            match nodes.into_iter().find(|node| node.node_id == node_id) {
+                Some(node) => Ok(Some(node)),
+                None => Err(AppError::invalid_input(format!(
+                    "There is no node `{node_id}` running"
+                ))),
+            }
+        }
+        None if in_a_call(state, guild_id).await => Ok(None),
+        None => {
+            let owners = cluster.owners(guild_id).await.map_err(internal)?;
+            Ok(owners.into_iter().next())
+        }
+    }
+}
+
+/// Sends `request` with `body` on to `node` and hands back whatever it responded.
+pub async fn forward(
+    cluster: &Cluster,
+    node: &Node,
+    request: &HttpRequest,
+    body: Bytes,
+) -> Result<HttpResponse, AppError> {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or(request.path(), |path| path.as_str());
+    let url = format!("{}{path}", node.api_url.trim_end_matches('/'));
+    debug!(node_id = node.node_id, url, "Forwarding the request");
+
+    let mut forwarded = reqwest::Client::new()
+        .request(request.method().clone(), &url)
+        .header(FORWARDED_HEADER, cluster.node_id())
+        .body(body);
+    for name in [header::CONTENT_TYPE, header::AUTHORIZATION] {
+        if let Some(value) = request.headers().get(&name) {
+            forwarded = forwarded.header(name, value.clone());
+        }
+    }
+
+    let unreachable =
+        |err: reqwest::Error| AppError::internal(format!("Could not reach {url}: {err}"));
+    let response = forwarded.send().await.map_err(unreachable)?;
+
+    let mut forwarded_response = HttpResponse::build(response.status());
+    if let Some(content_type) = response.headers().get(header::CONTENT_TYPE) {
+        forwarded_response.insert_header((header::CONTENT_TYPE, content_type.clone()));
+    }
+
+    Ok(forwarded_response.body(response.bytes().await.map_err(unreachable)?))
+}
+
+async fn in_a_call(state: &State, guild_id: GuildId) -> bool {
+    match state.songbird_instance.get(guild_id) {
+        Some(call) => call.lock().await.current_channel().is_some(),
+        None => false,
+    }
+}
+
+fn internal(err: crate::error::Error) -> AppError {
+    AppError::internal(err.to_string())
+}
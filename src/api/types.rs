@@ -2,7 +2,6 @@ use serde::Serialize;
 
 /// THIS STRUCT DOES NOT CONTAIN INFORMATION ABOUT THE ALREADY PLAYED TIME
 /// WE WILL MAKE USE OF A SEPERATE ENDPOINT/STRUCT WITH SOCKETS TO HAVE A LIVE-UPDATE OF EACH TRACK
-/// HAVE A LOOK AT `TrackUpdate`
 #[derive(Serialize)]
 pub struct Track {
     pub title: Option<String>,
@@ -10,6 +9,15 @@ pub struct Track {
     pub thumbnail: Option<String>,
     pub length_secs: Option<u64>,
     pub url: Option<String>,
+    /// Empty if the track has none
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Serialize)]
+pub struct Chapter {
+    pub title: String,
+    pub start_secs: f64,
+    pub end_secs: f64,
 }
 
 #[derive(Serialize)]
@@ -18,7 +26,20 @@ pub struct Author {
     pub icon_url: Option<String>,
 }
 
+/// The lyrics of the current track, see `GET /queues/queue/{guild_id}/lyrics`.
+#[derive(Serialize)]
+pub struct Lyrics {
+    /// Where they were found, e.g. `lrclib`
+    pub source: &'static str,
+    pub synced: bool,
+    pub lines: Vec<LyricsLine>,
+    /// The line being sung right now, only known for synced lyrics
+    pub current_line: Option<usize>,
+    pub position_ms: u64,
+}
+
 #[derive(Serialize)]
-pub struct TrackUpdate {
-    pub position_secs: u64,
+pub struct LyricsLine {
+    pub time_ms: Option<u64>,
+    pub text: String,
 }
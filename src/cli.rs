@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use poise::serenity_prelude::{Command as SlashCommand, GuildId, Http};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{
+        commands::commands,
+        registration::{sync_commands, Scope},
+        settings::{GuildSettings, SettingsStore},
+        sources::ytdl::ytdl_metadata,
+    },
+    config::{ClusterConfig, Config},
+    error::{AppError, Error},
+    storage::Storage,
+};
+
+/// Prefix of the documents `run` keeps while it runs, holding its process id.
+const RUNNING_DOCUMENT: &str = "running";
+
+/// A blazingly fast Discord music bot
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// The config file to use, defaults to `OXO_CONFIG` or `oxo.toml`
+    #[arg(long, short, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Same as `check-config`, kept for setups that still pass the flag
+    #[arg(long)]
+    pub check_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the bot and the api server (the default)
+    Run {
+        /// Don't start the api server
+        #[arg(long)]
+        no_api: bool,
+        /// Don't start the bot, only serve the api
+        #[arg(long)]
+        no_bot: bool,
+    },
+    /// Register the slash commands with discord
+    RegisterCommands {
+        /// Only register them in this guild, which takes effect immediately
+        #[arg(long)]
+        guild: Option<u64>,
+    },
+    /// Remove all registered slash commands
+    UnregisterCommands {
+        /// Only remove the ones registered in this guild
+        #[arg(long)]
+        guild: Option<u64>,
+    },
+    /// Validate the config and exit, without requiring the discord token
+    CheckConfig,
+    /// Write all persisted state as json to a file or stdout
+    ExportState {
+        /// Defaults to stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Replace all persisted state with a file written by `export-state`
+    ImportState {
+        file: PathBuf,
+        /// Import even though oxo seems to be running, which overwrites the import again
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the metadata oxo extracts from a url
+    Resolve { url: String },
+}
+
+impl Command {
+    /// Whether the command talks to discord and so needs the token.
+    pub fn needs_token(&self) -> bool {
+        match self {
+            Command::Run { no_bot, .. } => !no_bot,
+            Command::RegisterCommands { .. } | Command::UnregisterCommands { .. } => true,
+            Command::CheckConfig
+            | Command::ExportState { .. }
+            | Command::ImportState { .. }
+            | Command::Resolve { .. } => false,
+        }
+    }
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Run {
+            no_api: false,
+            no_bot: false,
+        }
+    }
+}
+
+/// Everything oxo persists, in the format of `export-state` and `import-state`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct StateExport {
+    guild_settings: HashMap<GuildId, GuildSettings>,
+}
+
+async fn http(config: &Config) -> Result<Http, Error> {
+    let http = Http::new(config.discord_token());
+    let application = http.get_current_application_info().await?;
+    http.set_application_id(application.id.0);
+
+    Ok(http)
+}
+
+pub async fn register_commands(config: &Config, guild: Option<u64>) -> Result<(), Error> {
+    let http = http(config).await?;
+    let commands = commands()?;
+    let scope = match guild {
+        Some(guild_id) => Scope::Guild(GuildId(guild_id)),
+        None => Scope::Global,
+    };
+
+    match sync_commands(&http, &commands, scope).await? {
+        true => println!("Registered {} commands in {scope:?}", commands.len()),
+        false => println!("The commands in {scope:?} are already up to date"),
+    }
+    if let Scope::Global = scope {
+        println!("Global changes may take up to an hour until they show up everywhere");
+    }
+
+    Ok(())
+}
+
+pub async fn unregister_commands(config: &Config, guild: Option<u64>) -> Result<(), Error> {
+    let http = http(config).await?;
+
+    match guild {
+        Some(guild_id) => {
+            GuildId(guild_id)
+                .set_application_commands(&http, |commands| commands)
+                .await?;
+            println!("Removed all commands from guild {guild_id}");
+        }
+        None => {
+            SlashCommand::set_global_application_commands(&http, |commands| commands).await?;
+            println!("Removed all global commands");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn export_state(settings: &SettingsStore, output: Option<PathBuf>) -> Result<(), Error> {
+    let export = StateExport {
+        guild_settings: settings.all().await,
+    };
+    let json = serde_json::to_string_pretty(&export)?;
+
+    match output {
+        Some(path) => tokio::fs::write(path, json).await?,
+        None => println!("{json}"),
+    }
+
+    Ok(())
+}
+
+pub async fn import_state(
+    settings: &SettingsStore,
+    storage: &Storage,
+    file: PathBuf,
+    force: bool,
+) -> Result<(), Error> {
+    let running = running_pids(storage).await?;
+    if !running.is_empty() {
+        let pids = running.iter().map(u32::to_string).collect::<Vec<_>>();
+        let message = format!(
+            "oxo is running on this storage (pid {}) and would overwrite the imported state",
+            pids.join(", ")
+        );
+        match force {
+            true => eprintln!("Warning: {message}"),
+            false => {
+                return Err(AppError::invalid_input(format!(
+                    "{message}, stop it first or pass --force"
+                ))
+                .into())
+            }
+        }
+    }
+
+    let export: StateExport = serde_json::from_slice(&tokio::fs::read(file).await?)?;
+    let guilds = export.guild_settings.len();
+
+    settings.replace_all(export.guild_settings).await?;
+    println!("Imported the settings of {guilds} guilds");
+
+    Ok(())
+}
+
+/// Records that this process serves `storage`, until the returned document is removed again.
+pub async fn mark_running(storage: &Storage, cluster: &ClusterConfig) -> Result<String, Error> {
+    let document = cluster.own_document(RUNNING_DOCUMENT);
+    storage.save(&document, &std::process::id()).await?;
+
+    Ok(document)
+}
+
+/// The ids of the processes that currently run oxo on `storage`. Documents left behind by
+/// processes that died without cleaning up are ignored.
+async fn running_pids(storage: &Storage) -> Result<Vec<u32>, Error> {
+    let mut pids = vec![];
+    for name in storage.names(RUNNING_DOCUMENT).await? {
+        if let Some(pid) = storage.load::<Option<u32>>(&name).await? {
+            if is_alive(pid) {
+                pids.push(pid);
+            }
+        }
+    }
+
+    Ok(pids)
+}
+
+fn is_alive(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    // Without procfs there is no cheap way to tell, so assume the worst
+    !proc.exists() || proc.join(pid.to_string()).exists()
+}
+
+pub async fn resolve(config: &Config, url: &str) -> Result<(), Error> {
+    let metadata = ytdl_metadata(&config.ytdl, url).await?;
+
+    let fields = [
+        ("Title", metadata.title),
+        ("Artist", metadata.artist),
+        ("Track", metadata.track),
+        ("Channel", metadata.channel),
+        ("Date", metadata.date),
+        ("Duration", metadata.duration.map(format_duration)),
+        ("Source", metadata.source_url),
+        ("Thumbnail", metadata.thumbnail),
+    ];
+
+    for (name, value) in fields {
+        println!("{name:<10} {}", value.as_deref().unwrap_or("-"));
+    }
+
+    Ok(())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,34 @@
+use rand::seq::SliceRandom;
+use songbird::input::Metadata;
+use tracing::warn;
+
+use crate::{client::sources::ytdl::ytdl_search, config::YtdlConfig};
+
+/// How many search results to pick the next track from.
+const CANDIDATES: usize = 10;
+
+/// Finds a track related to `metadata` by searching for more tracks of the same artist.
+pub async fn related_track(config: &YtdlConfig, metadata: &Metadata) -> Option<String> {
+    let query = match (&metadata.artist, &metadata.title) {
+        (Some(artist), _) => artist.clone(),
+        (None, Some(title)) => title.clone(),
+        (None, None) => return None,
+    };
+
+    let candidates = match ytdl_search(config, &query, CANDIDATES).await {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            warn!("Could not search for related tracks: {err}");
+            return None;
+        }
+    };
+
+    let candidates = candidates
+        .iter()
+        .filter(|url| Some(url.as_str()) != metadata.source_url.as_deref())
+        .collect::<Vec<_>>();
+
+    candidates
+        .choose(&mut rand::thread_rng())
+        .map(|url| url.to_string())
+}
@@ -1,37 +1,164 @@
-use std::env::VarError;
-use std::fs;
 use std::{collections::HashMap, sync::Arc};
 
-use enum_assoc::Assoc;
-use poise::serenity_prelude::{self as serenity, GuildId, Mutex};
+use poise::serenity_prelude::{
+    self as serenity, Cache, GuildId, Http, Mutex, ShardManager, UserId,
+};
+use serde::{Deserialize, Serialize};
+use songbird::SerenityInit;
 use songbird::Songbird;
-use songbird::{tracks::TrackQueue, SerenityInit};
-use tracing::error;
+use tracing::{error, info};
 
 use crate::client::commands::commands;
+use crate::client::feed::Feed;
+use crate::client::limits::Cooldowns;
+use crate::client::lyrics::LyricsProviders;
+use crate::client::notifications::Notifier;
+use crate::client::queues::Queues;
+use crate::client::reconnect::Reconnects;
+use crate::client::registration::{sync_commands, Scope};
+use crate::client::resume::{drain, resume};
+use crate::client::segments::SegmentProviders;
+use crate::client::settings::SettingsStore;
+use crate::client::sources::{cache::MetadataCache, Resolvers};
+use crate::client::voice::VoiceGateway;
+use crate::cluster::Cluster;
+use crate::config::{Config, ShardRange};
 use crate::error::{on_error, Error};
+use crate::library::Library;
+use crate::metrics::METRICS;
+use crate::shutdown::Shutdown;
+use crate::storage::Storage;
 
-pub type Context<'a> = poise::Context<'a, Arc<Mutex<State>>, Error>;
+pub type Context<'a> = poise::Context<'a, Arc<State>, Error>;
 
-pub type Queues = Arc<Mutex<HashMap<GuildId, TrackQueue>>>;
+/// Loop-modes that were explicitly set, guilds without one use their `loop_default` setting
+pub type LoopModes = Arc<Mutex<HashMap<GuildId, LoopMode>>>;
+/// Queue-modes set with `/queuemode`, guilds without one play their queue in order
+pub type QueueModes = Arc<Mutex<HashMap<GuildId, QueueMode>>>;
+/// Shared by every command, event handler and api request as an `Arc<State>`.
+///
+/// There is no lock around it, everything that changes has a lock of its own, see [`Queues`].
 #[derive(Debug)]
 pub struct State {
     pub queues: Queues,
-    pub loop_mode: Arc<Mutex<LoopMode>>,
+    pub loop_modes: LoopModes,
+    pub queue_modes: QueueModes,
+    pub settings: Arc<SettingsStore>,
+    pub config: Arc<Config>,
+    pub resolvers: Arc<Resolvers>,
+    pub metadata_cache: Arc<MetadataCache>,
+    /// Only set if a media directory is configured
+    pub library: Option<Arc<Library>>,
+    pub lyrics: Arc<LyricsProviders>,
+    pub segments: Arc<SegmentProviders>,
+    pub cooldowns: Arc<Mutex<Cooldowns>>,
+    pub storage: Storage,
+    pub shutdown: Shutdown,
+    pub gateway: Mutex<Gateway>,
     pub songbird_instance: Arc<Songbird>,
+    /// Passes the voice events of the gateway on to `songbird_instance`
+    pub voice_gateway: Arc<VoiceGateway>,
+    pub reconnects: Reconnects,
+    pub feed: Feed,
+    pub notifier: Notifier,
+    /// Only set if this is one of several nodes
+    pub cluster: Option<Arc<Cluster>>,
 }
 
-impl Default for State {
-    fn default() -> Self {
+/// How far the connection to discord is, for the readiness check.
+#[derive(Debug, Default)]
+pub enum Gateway {
+    #[default]
+    Starting,
+    /// The bot was not started (`--no-bot`)
+    Disabled,
+    /// Set once the bot received its ready event
+    Running {
+        shard_manager: Arc<Mutex<ShardManager>>,
+        http: Arc<Http>,
+        cache: Arc<Cache>,
+        bot_id: UserId,
+    },
+}
+
+impl State {
+    pub fn new(
+        config: Config,
+        storage: Storage,
+        settings: SettingsStore,
+        metadata_cache: MetadataCache,
+    ) -> Self {
+        let metadata_cache = Arc::new(metadata_cache);
+        let library = config
+            .sources
+            .media_dir
+            .clone()
+            .map(Library::new)
+            .map(Arc::new);
+
+        let songbird_instance = Songbird::serenity();
+
         Self {
             queues: Default::default(),
-            loop_mode: Default::default(),
-            songbird_instance: Songbird::serenity(),
+            loop_modes: Default::default(),
+            queue_modes: Default::default(),
+            settings: Arc::new(settings),
+            resolvers: Arc::new(Resolvers::from_config(&config, metadata_cache.clone())),
+            metadata_cache,
+            lyrics: Arc::new(LyricsProviders::from_config(&config, library.clone())),
+            library,
+            segments: Arc::new(SegmentProviders::from_config(&config)),
+            cluster: Cluster::new(&config.cluster, storage.clone()).map(Arc::new),
+            config: Arc::new(config),
+            cooldowns: Default::default(),
+            storage,
+            shutdown: Default::default(),
+            gateway: Default::default(),
+            voice_gateway: Arc::new(VoiceGateway::new(songbird_instance.clone())),
+            songbird_instance,
+            reconnects: Default::default(),
+            feed: Default::default(),
+            notifier: Default::default(),
+        }
+    }
+
+    /// The http client of the bot, once it is connected to discord.
+    pub async fn http(&self) -> Option<Arc<Http>> {
+        match &*self.gateway.lock().await {
+            Gateway::Running { http, .. } => Some(http.clone()),
+            _ => None,
+        }
+    }
+
+    /// What the bot knows about discord without asking it, once it is connected.
+    pub async fn cache(&self) -> Option<Arc<Cache>> {
+        match &*self.gateway.lock().await {
+            Gateway::Running { cache, .. } => Some(cache.clone()),
+            _ => None,
         }
     }
+
+    /// The bot user, once it is connected to discord.
+    pub async fn bot_id(&self) -> Option<UserId> {
+        match &*self.gateway.lock().await {
+            Gateway::Running { bot_id, .. } => Some(*bot_id),
+            _ => None,
+        }
+    }
+
+    /// The queue-mode of `guild_id`. Copied out, so the lock is never held while a queue is.
+    pub async fn queue_mode(&self, guild_id: GuildId) -> QueueMode {
+        self.queue_modes
+            .lock()
+            .await
+            .get(&guild_id)
+            .copied()
+            .unwrap_or_default()
+    }
 }
 
-#[derive(Debug, poise::ChoiceParameter, Clone, Default)]
+#[derive(Debug, poise::ChoiceParameter, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LoopMode {
     #[default]
     Off,
@@ -39,78 +166,103 @@ pub enum LoopMode {
     Queue,
 }
 
-#[derive(Debug, poise::ChoiceParameter, Clone, Default, Assoc)]
-#[func(pub fn is_24_7(&self) -> bool)]
-#[func(pub fn url(&self) -> &str)]
-pub enum LofiSong {
+#[derive(Debug, poise::ChoiceParameter, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueMode {
     #[default]
-    #[assoc(is_24_7 = true)]
-    #[assoc(url = "https://www.youtube.com/watch?v=jfKfPfyJRdk")]
-    LofiGirl,
-
-    #[assoc(is_24_7 = false)]
-    #[assoc(url = "https://www.youtube.com/watch?v=A7vMrjsBMTI")]
-    Undertale,
-
-    #[assoc(is_24_7 = false)]
-    #[assoc(url = "https://www.youtube.com/watch?v=-z3RRwk2rdU")]
-    Zelda,
-
-    #[assoc(is_24_7 = false)]
-    #[assoc(url = "https://www.youtube.com/watch?v=GNWLILeztaI")]
-    AnimeOps,
-
-    #[assoc(is_24_7 = false)]
-    #[assoc(url = "https://www.youtube.com/watch?v=83PnFc6eh-4")]
-    Metal,
-
-    #[assoc(is_24_7 = false)]
-    #[assoc(url = "https://www.youtube.com/watch?v=1XFtipo7v0Y")]
-    Djent,
-
-    #[assoc(is_24_7 = false)]
-    #[assoc(url = "https://www.youtube.com/watch?v=gnKZqk-CqBs")]
-    Berserk,
-
-    #[assoc(is_24_7 = false)]
-    #[assoc(url = "https://www.youtube.com/watch?v=ZBGui2nZ0c0")]
-    BigIron,
+    Normal,
+    /// Every requester gets their own sub-queue, which are played round-robin
+    Fair,
 }
 
-pub async fn start_bot(state: Arc<Mutex<State>>) {
-    let token = get_discord_token();
-
-    let state_clone = state.clone();
-    let state_clone = state_clone.lock().await;
-    let songbird_instance = state_clone.songbird_instance.clone();
+pub async fn start_bot(state: Arc<State>) {
+    let songbird_instance = state.songbird_instance.clone();
+    let voice_gateway = state.voice_gateway.clone();
+    let token = state.config.discord_token().to_owned();
+    let discord_config = state.config.discord.clone();
+    let resume_queues = state.config.shutdown.resume;
+    let shutdown = state.shutdown.clone();
+    let shards = state.config.cluster.shards;
 
-    drop(state_clone);
+    let drain_state = state.clone();
 
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands: commands().unwrap(),
             on_error: |err| Box::pin(on_error(err)),
+            pre_command: |ctx| {
+                Box::pin(async move {
+                    METRICS
+                        .commands
+                        .with_label_values(&[&ctx.command().qualified_name])
+                        .inc();
+                })
+            },
             ..Default::default()
         })
         .token(token)
         .intents(serenity::GatewayIntents::non_privileged())
-        .setup(|ctx, _ready, framework| {
+        .setup(move |ctx, ready, framework| {
             Box::pin(async move {
-                poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                let commands = &framework.options().commands;
+                // the commands that are registered already keep working, so this is no reason to stop
+                let mut scopes = discord_config
+                    .dev_guilds
+                    .into_iter()
+                    .map(Scope::Guild)
+                    .collect::<Vec<_>>();
+                if discord_config.register_globally {
+                    scopes.insert(0, Scope::Global);
+                }
+                for scope in scopes {
+                    if let Err(err) = sync_commands(&ctx.http, commands, scope).await {
+                        error!("Could not register the commands in {scope:?}: {err}");
+                    }
+                }
+
+                *state.gateway.lock().await = Gateway::Running {
+                    shard_manager: framework.shard_manager().clone(),
+                    http: ctx.http.clone(),
+                    cache: ctx.cache.clone(),
+                    bot_id: ready.user.id,
+                };
+
+                if resume_queues {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = resume(&state).await {
+                            error!("Could not resume queues: {err}");
+                        }
+                    });
+                }
+
                 Ok(state.clone())
             })
         })
-        .client_settings(move |builder| builder.register_songbird_with(songbird_instance))
+        .client_settings(move |builder| {
+            builder
+                .register_songbird_with(songbird_instance)
+                .voice_manager_arc(voice_gateway)
+        })
         .build()
         .await
         .unwrap();
 
-    let status = framework.clone().start();
+    let shard_manager = framework.shard_manager().clone();
+
+    let status = framework.clone().start_with(|mut client| async move {
+        match shards {
+            Some(ShardRange { first, last, total }) => {
+                info!("Starting shards {first} to {last} of {total}");
+                client.start_shard_range([first, last], total).await
+            }
+            None => client.start().await,
+        }
+    });
     tokio::spawn(async move {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Could not register ctrl+c handler");
-        let shard_manager = framework.shard_manager();
+        shutdown.wait().await;
+
+        info!("Leaving all calls");
+        drain(&drain_state).await;
         shard_manager.lock().await.shutdown_all().await;
     });
 
@@ -118,17 +270,3 @@ pub async fn start_bot(state: Arc<Mutex<State>>) {
         error!("Client error: {:?}", why);
     }
 }
-
-fn get_discord_token() -> String {
-    fn read_token_from_file(_: VarError) -> Result<String, Error> {
-        let path = std::env::var("DISCORD_TOKEN_FILE")?;
-        let content = fs::read_to_string(path)?;
-
-        // No, we cannot just return fs::read_to_string because of Box<...> Shenenigans
-        Ok(content)
-    }
-
-    std::env::var("DISCORD_TOKEN")
-        .or_else(read_token_from_file)
-        .expect("missing DISCORD_TOKEN")
-}
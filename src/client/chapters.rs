@@ -0,0 +1,192 @@
+//! Chapters of long videos (mixes, compilations, albums uploaded as one video), as yt-dlp
+//! reports them.
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use songbird::tracks::TrackHandle;
+use tracing::warn;
+
+use crate::{client::sources::Resolvers, error::AppError};
+
+/// A part of a track, in seconds from its start.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Chapter {
+    pub fn start(&self) -> Duration {
+        Duration::from_secs_f64(self.start.max(0.0))
+    }
+}
+
+/// The chapters of a track, kept in its typemap so they are only looked up once.
+struct TrackChapters;
+
+impl songbird::typemap::TypeMapKey for TrackChapters {
+    type Value = Arc<Vec<Chapter>>;
+}
+
+/// Reads the `chapters` of the output of `yt-dlp --print-json`.
+pub fn from_ytdl_output(value: &Value) -> Vec<Chapter> {
+    let chapters = value["chapters"].as_array().into_iter().flatten();
+
+    chapters
+        .enumerate()
+        .filter_map(|(i, chapter)| {
+            let title = match chapter["title"].as_str().map(str::trim) {
+                Some(title) if !title.is_empty() => title.to_string(),
+                _ => format!("Chapter {}", i + 1),
+            };
+
+            Some(Chapter {
+                title,
+                start: chapter["start_time"].as_f64()?,
+                end: chapter["end_time"].as_f64()?,
+            })
+        })
+        .collect()
+}
+
+/// The chapters of `track`, looked up the first time they are needed.
+pub async fn of_track(resolvers: &Resolvers, track: &TrackHandle) -> Arc<Vec<Chapter>> {
+    if let Some(chapters) = track.typemap().read().await.get::<TrackChapters>() {
+        return chapters.clone();
+    }
+
+    let chapters = match track.metadata().source_url.as_deref() {
+        Some(url) => resolvers.chapters(url).await.unwrap_or_else(|err| {
+            warn!(url, "Could not look up the chapters of a track: {err}");
+            vec![]
+        }),
+        None => vec![],
+    };
+    let chapters = Arc::new(chapters);
+    track
+        .typemap()
+        .write()
+        .await
+        .insert::<TrackChapters>(chapters.clone());
+
+    chapters
+}
+
+/// The index of the chapter playing at `position`.
+pub fn current(chapters: &[Chapter], position: Duration) -> Option<usize> {
+    chapters
+        .iter()
+        .rposition(|chapter| chapter.start() <= position)
+}
+
+/// Where `/chapter` jumps to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChapterJump {
+    Next,
+    Previous,
+    /// Counting from 1, like `/chapters` lists them
+    Number(usize),
+}
+
+impl FromStr for ChapterJump {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "next" => Ok(Self::Next),
+            "prev" | "previous" => Ok(Self::Previous),
+            number => number.parse().map(Self::Number).map_err(|_| {
+                AppError::invalid_input(format!(
+                    "`{s}` is not a chapter, expected next, prev or its number"
+                ))
+            }),
+        }
+    }
+}
+
+impl ChapterJump {
+    /// The index of the chapter to jump to from `position`.
+    pub fn target(self, chapters: &[Chapter], position: Duration) -> Result<usize, AppError> {
+        if chapters.is_empty() {
+            return Err(AppError::invalid_input("This track has no chapters"));
+        }
+        let current = current(chapters, position);
+
+        let target = match self {
+            Self::Next => current.map_or(Some(0), |current| Some(current + 1)),
+            Self::Previous => current.and_then(|current| current.checked_sub(1)),
+            Self::Number(number) => number.checked_sub(1),
+        };
+
+        match target.filter(|target| *target < chapters.len()) {
+            Some(target) => Ok(target),
+            None if self == Self::Next => Err(AppError::invalid_input("This is the last chapter")),
+            None if self == Self::Previous => {
+                Err(AppError::invalid_input("This is the first chapter"))
+            }
+            None => Err(AppError::invalid_input(format!(
+                "There are only {} chapters",
+                chapters.len()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapters() -> Vec<Chapter> {
+        ["Intro", "Verse", "Outro"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, title)| Chapter {
+                title: title.into(),
+                start: i as f64 * 60.0,
+                end: (i + 1) as f64 * 60.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chapters_are_read_from_ytdl_output() {
+        let output = serde_json::json!({
+            "chapters": [
+                {"start_time": 0.0, "end_time": 60.0, "title": "Intro"},
+                {"start_time": 60.0, "end_time": 120.0, "title": " "},
+                {"start_time": 120.0, "title": "Broken"},
+            ]
+        });
+
+        let chapters = from_ytdl_output(&output);
+
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title, "Intro");
+        assert_eq!(chapters[1].title, "Chapter 2");
+        assert!(from_ytdl_output(&serde_json::json!({"chapters": null})).is_empty());
+    }
+
+    #[test]
+    fn jumps_are_relative_to_the_current_chapter() {
+        let chapters = chapters();
+        let at = Duration::from_secs;
+        let jump = |jump: &str, position| {
+            jump.parse::<ChapterJump>()
+                .and_then(|jump| jump.target(&chapters, position))
+                .ok()
+        };
+
+        assert_eq!(current(&chapters, at(90)), Some(1));
+        assert_eq!(jump("next", at(90)), Some(2));
+        assert_eq!(jump("prev", at(90)), Some(0));
+        assert_eq!(jump("3", at(0)), Some(2));
+        assert_eq!(jump("next", at(150)), None);
+        assert_eq!(jump("prev", at(30)), None);
+        assert_eq!(jump("4", at(0)), None);
+        assert_eq!(jump("0", at(0)), None);
+        assert_eq!(jump("later", at(0)), None);
+    }
+}
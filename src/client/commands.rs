@@ -1,20 +1,35 @@
-use std::sync::Arc;
+use std::{fmt::Display, sync::Arc, time::Duration};
 
 use poise::{
-    serenity_prelude::{Colour, Mutex, Timestamp},
-    Command,
+    serenity_prelude::{
+        Attachment, ChannelId, Colour, CreateEmbed, GuildChannel, Message, Timestamp,
+    },
+    AutocompleteChoice, Command,
 };
 
 use rand::{seq::SliceRandom, thread_rng};
-use songbird::{Event, TrackEvent};
+use songbird::tracks::{Queued, TrackHandle};
 use tracing::warn;
 
-use crate::{error::Error, client::bot::LofiSong};
+use crate::{
+    error::{AppError, Error},
+    library::{Library, LibraryTrack},
+};
 
 use crate::client::{
-    bot::{Context, LoopMode, State},
-    embed_ext::CreateEmbedExt,
-    events::EndEventHandler,
+    bot::{Context, LoopMode, QueueMode, State},
+    chapters::{self, ChapterJump},
+    embed_ext::{duration_format, CreateEmbedExt},
+    limits::{check_attachment, Limits},
+    lyrics::{view::LyricsView, LyricsQuery},
+    notifications::{Notice, NotificationMode, Personality, PreviousNotification},
+    player::{GuildPlayer, Request},
+    queue_ext::{apply_queue_mode, discard, parse_positions, Requester, TrackQueueExt},
+    queues::GuildQueue,
+    segments::skipped_segments,
+    settings::{GuildSettings, SettingKey},
+    sources::{lazy::QueueEntry, local::LOCAL_PREFIX},
+    spans::instrument,
 };
 
 pub type CmdRes = Result<(), Error>;
@@ -25,14 +40,17 @@ macro_rules! commands {
             $(#[$header])* async fn $name ($($args)*) -> CmdRes $blk
         )*
 
-        pub type PoiseCommand = Command<Arc<Mutex<State>>, Box<(dyn std::error::Error + Send + Sync + 'static)>>;
+        pub type PoiseCommand = Command<Arc<State>, Error>;
 
         pub fn commands() -> Result<Vec<PoiseCommand>, Error> {
-            return Ok(vec![
+            let mut commands = vec![
                 $(
                     $name(),
                 )*
-            ])
+            ];
+            commands.iter_mut().for_each(instrument);
+
+            return Ok(commands)
         }
     };
 }
@@ -41,97 +59,254 @@ commands! {
 
 /// Study 'n Chill
 #[poise::command(slash_command)]
-async fn lofi(ctx: Context<'_>, lofi_song: LofiSong) -> CmdRes {    
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let channel_id = guild
-        .voice_states
-        .get(&ctx.author().id)
-        .and_then(|vs| vs.channel_id)
-        .unwrap();
-
-    if let Err(warn) = ctx.say("Loading your track...").await {
-        warn!("{warn}");
-    }
+async fn lofi(
+    ctx: Context<'_>,
+    #[description = "The station to tune in to"]
+    #[autocomplete = "autocomplete_station"]
+    station: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let station = state.config.stations.find(&station).ok_or_else(|| {
+        let stations = state.config.stations.0.iter().map(|s| s.name.as_str());
+        AppError::invalid_input(format!(
+            "There is no station called `{station}`, try one of {}",
+            stations.collect::<Vec<_>>().join(", ")
+        ))
+    })?;
+    let loading = match station.is_24_7 {
+        true => format!("Tuning in to {}, it plays 24/7...", station.name),
+        false => LOADING.to_string(),
+    };
 
-    let manager = state.songbird_instance.clone();
+    play_url(ctx, state, &station.url, &loading).await
+}
 
-    let source = songbird::ytdl(lofi_song.url()).await?;
 
-    let (handler, _) = manager.join(guild.id, channel_id).await;
-    let mut handler_lock = handler.lock().await;
+/// And it goes on and on and on and on and ...
+#[poise::command(slash_command, guild_only, check = "is_dj", rename = "loop")]
+async fn loop_mode(ctx: Context<'_>, loop_mode: LoopMode) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
 
-    let mut queues = state.queues.lock().await;
-    let queue = queues.entry(guild.id).or_default();
+    ctx.send(|create| create.embed(|e| e.info_embed(format!("Set loop-mode to {loop_mode}"))))
+        .await?;
 
-    let track = queue.add_source(source, &mut handler_lock);
+    state.loop_modes.lock().await.insert(guild_id, loop_mode);
 
-    let metadata = track.metadata();
-    let track_info = track.get_info().await?;
+    Ok(())
+}
 
-    ctx.send(|create| create.embed(|e| e.song_embed(metadata, &track_info)))
-        .await?;
+/// House rules
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("limits_view", "limits_set", "limits_block", "limits_unblock", "limits_reset")
+)]
+async fn limits(_ctx: Context<'_>) -> CmdRes {
+    Ok(())
+}
 
-    track.add_event(
-        Event::Track(TrackEvent::End),
-        EndEventHandler::new(ctx, &state, handler.clone(), guild.id),
-    )?;
+/// Make yourself at home
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("settings_view", "settings_set", "settings_reset")
+)]
+async fn settings(_ctx: Context<'_>) -> CmdRes {
+    Ok(())
+}
 
+/// Who hears about what
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands(
+        "notifications_view",
+        "notifications_set",
+        "notifications_template",
+        "notifications_reset"
+    )
+)]
+async fn notifications(_ctx: Context<'_>) -> CmdRes {
     Ok(())
 }
 
+/// The good stuff, no ads included
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("library_search", "library_play", "library_album", "library_artist")
+)]
+async fn library(_ctx: Context<'_>) -> CmdRes {
+    Ok(())
+}
 
-/// And it goes on and on and on and on and ...
-#[poise::command(slash_command, rename = "loop")]
-async fn loop_mode(ctx: Context<'_>, loop_mode: LoopMode) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let mut global_loop_mode = state.loop_mode.lock().await;
-    *global_loop_mode = loop_mode;
+/// Sharing is caring
+#[poise::command(slash_command, guild_only, check = "is_dj")]
+async fn queuemode(ctx: Context<'_>, queue_mode: QueueMode) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    state.queue_modes.lock().await.insert(guild_id, queue_mode);
 
-    ctx.send(|create| create.embed(|e| e.info_embed(format!("Set loop-mode to {global_loop_mode}"))))
+    if let Some(queue) = state.queues.get(guild_id) {
+        apply_queue_mode(&*queue.lock().await, queue.pinned(), queue_mode).await;
+    }
+
+    ctx.send(|create| create.embed(|e| e.info_embed(format!("Set queue-mode to {queue_mode}"))))
         .await?;
 
     Ok(())
 }
 
 /// In the beninging
-#[poise::command(slash_command)]
+#[poise::command(slash_command, check = "is_dj")]
 async fn playtop(
     ctx: Context<'_>,
-    #[description = "The track to send to the front"] track_number: usize,
+    #[description = "The track to play next"] track_number: usize,
 ) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild.id).unwrap();
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let guild_queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = guild_queue.lock().await;
+    let pinned = guild_queue.pinned();
 
-    if track_number <= 1 {
-        return Ok(());
-    }
+    queue.move_position(pinned, track_number, pinned + 1)?;
 
-    queue.pause()?;
-    queue.modify_queue(|q| q.swap(0, track_number - 1));
-    queue.resume()?;
+    ctx.send(|create| create.embed(|e| e.info_embed(format!("Track {track_number} is up next"))))
+        .await?;
+
+    Ok(())
+}
+
+/// Begone
+#[poise::command(slash_command, check = "is_dj")]
+async fn remove(
+    ctx: Context<'_>,
+    #[description = "The track to remove, or a range like 3-7"] tracks: String,
+) -> CmdRes {
+    let positions = parse_positions(&tracks)?;
+
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let guild_queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = guild_queue.lock().await;
+    let pinned = guild_queue.pinned();
+
+    let removed = queue.remove_positions(pinned, positions)?;
+    let description = format!("Removed {} from the queue", track_list(&removed));
+    discard(removed).await;
+
+    ctx.send(|create| create.embed(|e| e.info_embed(description)))
+        .await?;
+
+    Ok(())
+}
+
+/// Rearrange the furniture
+#[poise::command(slash_command, check = "is_dj", rename = "move")]
+async fn move_track(
+    ctx: Context<'_>,
+    #[description = "The track to move"] from: usize,
+    #[description = "Its new position"] to: usize,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let guild_queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = guild_queue.lock().await;
+    let pinned = guild_queue.pinned();
+
+    queue.move_position(pinned, from, to)?;
+
+    ctx.send(|create| create.embed(|e| e.info_embed(format!("Moved track {from} to position {to}"))))
+        .await?;
+
+    Ok(())
+}
+
+/// Fast forward
+#[poise::command(slash_command, check = "is_dj")]
+async fn skipto(
+    ctx: Context<'_>,
+    #[description = "The track to jump to"] track_number: usize,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let guild_queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = guild_queue.lock().await;
+    let pinned = guild_queue.pinned();
+
+    let skipped = queue.remove_until(pinned, track_number)?;
+    let count = skipped.len() + 1;
+    discard(skipped).await;
+    queue.skip()?;
+
+    ctx.send(|create| create.embed(|e| e.info_embed(format!("Skipped {count} tracks"))))
+        .await?;
+
+    Ok(())
+}
+
+/// Tabula rasa
+#[poise::command(slash_command, check = "is_dj")]
+async fn clear(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let guild_queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = guild_queue.lock().await;
+    let pinned = guild_queue.pinned();
+
+    let removed = queue.clear_upcoming(pinned);
+    let count = removed.len();
+    discard(removed).await;
+
+    ctx.send(|create| create.embed(|e| e.info_embed(format!("Removed {count} upcoming tracks"))))
+        .await?;
+
+    Ok(())
+}
+
+/// Déjà vu
+#[poise::command(slash_command, check = "is_dj")]
+async fn removedupes(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let guild_queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = guild_queue.lock().await;
+    let pinned = guild_queue.pinned();
+
+    let removed = queue.remove_duplicates(pinned);
+    let description = match removed.len() {
+        0 => "No duplicates found".to_string(),
+        _ => format!("Removed {}", track_list(&removed)),
+    };
+    discard(removed).await;
+
+    ctx.send(|create| create.embed(|e| e.info_embed(description)))
+        .await?;
 
     Ok(())
 }
 
 /// Harlem shake
-#[poise::command(slash_command)]
+#[poise::command(slash_command, check = "is_dj")]
 async fn shuffle(ctx: Context<'_>) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild.id).unwrap();
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let queue_mode = state.queue_mode(guild_id).await;
+    let guild_queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = guild_queue.lock().await;
+    let pinned = guild_queue.pinned();
 
     queue.modify_queue(|q| {
         let mut rng = thread_rng();
-        let [_, rest@..] = q.make_contiguous() else {
-                return;
-            };
-
-        rest.shuffle(&mut rng);
+        if let Some(upcoming) = q.make_contiguous().get_mut(pinned..) {
+            upcoming.shuffle(&mut rng);
+        }
     });
+    apply_queue_mode(&queue, pinned, queue_mode).await;
 
     Ok(())
 }
@@ -139,27 +314,23 @@ async fn shuffle(ctx: Context<'_>) -> CmdRes {
 /// I WANT 'EM ALL - I WANT 'EM NOW
 #[poise::command(slash_command)]
 async fn queue(ctx: Context<'_>) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild.id).unwrap();
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = queue.read();
 
     let list = queue.current_queue();
 
-    let formatted_list = list
-        .iter()
-        .map(|track| {
-            format!(
-                "▷ {}",
-                track
-                    .metadata()
-                    .title
-                    .clone()
-                    .unwrap_or_else(|| "N/A".into())
-            )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
+    let mut formatted_list = vec![];
+    for (i, track) in list.iter().enumerate() {
+        let title = track.metadata().title.as_deref().unwrap_or("N/A");
+        let line = match track.typemap().read().await.get::<Requester>() {
+            Some(requester) => format!("▷ `{}` {} (<@{}>)", i + 1, title, requester),
+            None => format!("▷ `{}` {}", i + 1, title),
+        };
+        formatted_list.push(line);
+    }
+    let formatted_list = formatted_list.join("\n");
 
     ctx.send(|create| {
         create.embed(|e| {
@@ -179,29 +350,128 @@ async fn queue(ctx: Context<'_>) -> CmdRes {
 /// Who asked?
 #[poise::command(slash_command)]
 async fn now_playing(ctx: Context<'_>) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild.id).unwrap();
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = queue.read();
 
-    let current = queue.current().unwrap();
+    let current = queue.current().ok_or_else(nothing_queued)?;
 
     let metadata = current.metadata();
     let track_info = current.get_info().await?;
+    let skipped = skipped_segments(&current).await;
+    let chapters = chapters::of_track(&state.resolvers, &current).await;
+
+    ctx.send(|create| {
+        create.embed(|e| {
+            e.song_embed(metadata, &track_info, &chapters)
+                .skipped_segments(&skipped)
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Table of contents
+#[poise::command(slash_command, guild_only)]
+async fn chapters(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let current = state.queues.get(guild_id).and_then(|queue| queue.read().current());
+    let current = current.ok_or_else(nothing_queued)?;
+
+    let position = current.get_info().await?.position;
+    let chapters = chapters::of_track(&state.resolvers, &current).await;
+    if chapters.is_empty() {
+        return Err(AppError::invalid_input("This track has no chapters").into());
+    }
+    let playing = chapters::current(&chapters, position);
+
+    let mut description = String::new();
+    for (i, chapter) in chapters.iter().enumerate() {
+        let start = duration_format(&chapter.start());
+        let line = match playing == Some(i) {
+            true => format!("▶ `{}` `{start}` **{}**\n", i + 1, chapter.title),
+            false => format!("`{}` `{start}` {}\n", i + 1, chapter.title),
+        };
+        // discord allows at most 4096 characters in a description
+        if description.len() + line.len() > CHAPTER_LIST_LEN {
+            description += &format!("…and {} more", chapters.len() - i);
+            break;
+        }
+        description += &line;
+    }
+
+    let title = current.metadata().title.clone().unwrap_or_else(|| "Chapters".into());
+    ctx.send(|create| create.embed(|e| e.normal_styling().title(title).description(description)))
+        .await?;
+
+    Ok(())
+}
+
+/// Skip to the good part
+#[poise::command(slash_command, guild_only, check = "is_dj")]
+async fn chapter(
+    ctx: Context<'_>,
+    #[description = "next, prev or the number of a chapter"] to: String,
+) -> CmdRes {
+    let jump: ChapterJump = to.parse()?;
+
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let current = state.queues.get(guild_id).and_then(|queue| queue.read().current());
+    let current = current.ok_or_else(nothing_queued)?;
+
+    let position = current.get_info().await?.position;
+    let chapters = chapters::of_track(&state.resolvers, &current).await;
+    let target = jump.target(&chapters, position)?;
+    let chapter = &chapters[target];
 
-    ctx.send(|create| create.embed(|e| e.song_embed(metadata, &track_info)))
+    current.seek_time(chapter.start())?;
+
+    let description = format!("Jumped to chapter {}: `{}`", target + 1, chapter.title);
+    ctx.send(|create| create.embed(|e| e.info_embed(description)))
         .await?;
 
     Ok(())
 }
 
+/// Sing along
+#[poise::command(slash_command, guild_only)]
+async fn lyrics(
+    ctx: Context<'_>,
+    #[description = "The song to look for, defaults to the current track"] query: Option<String>,
+) -> CmdRes {
+    let state = ctx.data();
+    // yt-dlp and lrclib can take a few seconds
+    ctx.defer().await?;
+
+    let (title, lyrics, track) = match query {
+        Some(query) => {
+            let lyrics = state.lyrics.find(&LyricsQuery::search(&query)).await;
+            (query, lyrics.map(Arc::new), None)
+        }
+        None => {
+            let queue = state.queues.get(ctx.guild_id().unwrap()).ok_or_else(nothing_queued)?;
+            let current = queue.read().current().ok_or_else(nothing_queued)?;
+            let title = current.metadata().title.clone().unwrap_or_else(|| "N/A".into());
+            (title, state.lyrics.of_track(&current).await, Some(current))
+        }
+    };
+    let lyrics = lyrics.ok_or_else(|| {
+        AppError::invalid_input(format!("I couldn't find any lyrics for `{title}`"))
+    })?;
+
+    LyricsView::new(ctx, &title, lyrics, track).show(ctx).await
+}
+
 /// Hol' up
-#[poise::command(slash_command)]
+#[poise::command(slash_command, check = "is_dj")]
 async fn pause(ctx: Context<'_>) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild.id).unwrap();
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
 
     queue.pause()?;
 
@@ -209,12 +479,11 @@ async fn pause(ctx: Context<'_>) -> CmdRes {
 }
 
 /// Keep going
-#[poise::command(slash_command)]
+#[poise::command(slash_command, check = "is_dj")]
 async fn resume(ctx: Context<'_>) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild.id).unwrap();
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
 
     queue.resume()?;
 
@@ -222,56 +491,986 @@ async fn resume(ctx: Context<'_>) -> CmdRes {
 }
 
 /// Don't care
-#[poise::command(slash_command)]
+#[poise::command(slash_command, check = "is_dj")]
 async fn skip(ctx: Context<'_>) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let queues = state.queues.lock().await;
-    let queue = queues.get(&guild.id).unwrap();
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let queue = state.queues.get(guild_id).ok_or_else(nothing_queued)?;
+    let queue = queue.read();
 
     queue.skip()?;
 
     Ok(())
 }
 
+/// Come over here
+#[poise::command(slash_command, guild_only, check = "is_dj")]
+async fn join(
+    ctx: Context<'_>,
+    #[description = "Where to go, defaults to your voice channel"]
+    #[channel_types("Voice", "Stage")]
+    channel: Option<GuildChannel>,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let channel_id = match channel {
+        Some(channel) => channel.id,
+        None => author_voice_channel(ctx)?,
+    };
+
+    GuildPlayer::join(state, guild_id, channel_id).await?;
+
+    ctx.send(|create| create.embed(|e| e.info_embed(format!("Joined <#{channel_id}>"))))
+        .await?;
+
+    Ok(())
+}
+
+/// See ya
+#[poise::command(slash_command, guild_only, check = "is_dj")]
+async fn leave(ctx: Context<'_>) -> CmdRes {
+    let player = GuildPlayer::get(ctx.data(), ctx.guild_id().unwrap()).ok_or_else(not_in_a_call)?;
+
+    // joining again resumes it
+    if let Err(err) = player.queue().pause() {
+        warn!("Could not pause the queue: {err}");
+    }
+    player.leave().await;
+
+    let description = match player.queue().read().is_empty() {
+        true => "Left the channel".to_string(),
+        false => "Left the channel, `/join` or `/play` picks up where we left off".to_string(),
+    };
+    ctx.send(|create| create.embed(|e| e.info_embed(description)))
+        .await?;
+
+    Ok(())
+}
+
+/// Party's over
+#[poise::command(slash_command, guild_only, check = "is_dj")]
+async fn stop(ctx: Context<'_>) -> CmdRes {
+    let player = GuildPlayer::get(ctx.data(), ctx.guild_id().unwrap()).ok_or_else(not_in_a_call)?;
+
+    let count = player.stop().await;
+
+    let description = format!("Removed {count} tracks and left the channel");
+    ctx.send(|create| create.embed(|e| e.info_embed(description)))
+        .await?;
+
+    Ok(())
+}
+
 /// Jamming
 #[poise::command(slash_command)]
-async fn play(ctx: Context<'_>, #[description = "URL"] url: String) -> CmdRes {
-    let state = ctx.data().lock().await;
-    let guild = ctx.guild().unwrap();
-    let channel_id = guild
-        .voice_states
-        .get(&ctx.author().id)
-        .and_then(|vs| vs.channel_id)
-        .unwrap();
-
-    if let Err(warn) = ctx.say("Loading your track...").await {
-        warn!("{warn}");
-    }
+async fn play(
+    ctx: Context<'_>,
+    #[description = "URL"] url: Option<String>,
+    #[description = "An audio file to play instead"] file: Option<Attachment>,
+) -> CmdRes {
+    let state = ctx.data();
+    let url = match (url, file) {
+        (Some(url), None) => url,
+        (None, Some(file)) => {
+            check_attachment(&state.config.sources.attachments, &file)?;
+            file.url
+        }
+        (None, None) => {
+            return Err(AppError::invalid_input("Give me a url or a file to play").into())
+        }
+        (Some(_), Some(_)) => {
+            return Err(AppError::invalid_input("Give me either a url or a file, not both").into())
+        }
+    };
+
+    play_url(ctx, state, &url, LOADING).await
+}
 
-    let manager = state.songbird_instance.clone();
+/// Plays the first audio file attached to a message
+#[poise::command(context_menu_command = "Play this file", guild_only)]
+async fn play_file(ctx: Context<'_>, message: Message) -> CmdRes {
+    let state = ctx.data();
+    let config = &state.config.sources.attachments;
 
-    let source = songbird::ytdl(&url).await?;
+    let mut attachments = message.attachments.iter();
+    let file = match attachments.find(|file| check_attachment(config, file).is_ok()) {
+        Some(file) => file,
+        // explain what is wrong with the first one
+        None => match message.attachments.first() {
+            Some(file) => return Err(check_attachment(config, file).unwrap_err().into()),
+            None => {
+                return Err(AppError::invalid_input("There is no file attached to this message").into())
+            }
+        },
+    };
+
+    play_url(ctx, state, &file.url, LOADING).await
+}
+}
 
-    let (handler, _) = manager.join(guild.id, channel_id).await;
-    let mut handler_lock = handler.lock().await;
+const LOADING: &str = "Loading your track...";
+
+/// Everything `/play` does once it knows what to play, saying `loading` while it looks it up.
+async fn play_url(ctx: Context<'_>, state: &State, url: &str, loading: &str) -> CmdRes {
+    let guild_id = ctx.guild_id().unwrap();
+    let channel_id = author_voice_channel(ctx)?;
+
+    let settings = state.settings.get(guild_id).await;
+    let limits = &settings.limits;
+    settings.check_source(url)?;
+    limits.check_request(url)?;
+    limits
+        .check_queue(
+            state.queues.get(guild_id).as_deref().map(GuildQueue::read),
+            Some(ctx.author().id),
+        )
+        .await?;
+    state
+        .cooldowns
+        .lock()
+        .await
+        .check(guild_id, ctx.author().id, limits.play_cooldown)?;
 
-    let mut queues = state.queues.lock().await;
-    let queue = queues.entry(guild.id).or_default();
+    if let Err(warn) = ctx.say(loading).await {
+        warn!("{warn}");
+    }
 
-    let track = queue.add_source(source, &mut handler_lock);
+    let entry = QueueEntry::lookup(&state.resolvers, url, limits).await?;
+    let track = enqueue(ctx, channel_id, entry).await?;
+    state
+        .cooldowns
+        .lock()
+        .await
+        .record(guild_id, ctx.author().id);
 
     let metadata = track.metadata();
     let track_info = track.get_info().await?;
+    let chapters = chapters::of_track(&state.resolvers, &track).await;
 
-    ctx.send(|create| create.embed(|e| e.song_embed(metadata, &track_info)))
+    ctx.send(|create| create.embed(|e| e.song_embed(metadata, &track_info, &chapters)))
         .await?;
 
-    track.add_event(
-        Event::Track(TrackEvent::End),
-        EndEventHandler::new(ctx, &state, handler.clone(), guild.id),
-    )?;
+    Ok(())
+}
+
+/// The voice channel the author of `ctx` is in, that's where oxo joins.
+fn author_voice_channel(ctx: Context<'_>) -> Result<ChannelId, AppError> {
+    ctx.guild()
+        .and_then(|guild| guild.voice_states.get(&ctx.author().id)?.channel_id)
+        .ok_or_else(|| AppError::invalid_input("Join a voice channel first"))
+}
+
+/// Joins `channel_id` if oxo isn't there yet and queues `entry` for the author of `ctx`.
+async fn enqueue(
+    ctx: Context<'_>,
+    channel_id: ChannelId,
+    entry: QueueEntry,
+) -> Result<TrackHandle, Error> {
+    let player = GuildPlayer::join(ctx.data(), ctx.guild_id().unwrap(), channel_id).await?;
+    let request = Request::new(ctx.author().id, ctx.channel_id());
+
+    Ok(player.enqueue(entry, request).await?)
+}
+
+/// Leaves room in the description of `/chapters` for how many chapters were left out.
+const CHAPTER_LIST_LEN: usize = 4000;
+
+fn nothing_queued() -> AppError {
+    AppError::invalid_input("There is nothing queued right now")
+}
+
+fn not_in_a_call() -> AppError {
+    AppError::invalid_input("I'm not in a voice channel right now")
+}
+
+fn track_list(tracks: &[Queued]) -> String {
+    tracks
+        .iter()
+        .map(|track| format!("`{}`", track.metadata().title.as_deref().unwrap_or("N/A")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Show the limits of this server
+#[poise::command(slash_command, rename = "view")]
+async fn limits_view(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let settings = state.settings.get(guild_id).await;
+
+    ctx.send(|create| create.embed(|e| limits_embed(e, &settings.limits)))
+        .await?;
+
+    Ok(())
+}
+
+/// Change the limits of this server, 0 removes a limit
+#[poise::command(slash_command, rename = "set")]
+async fn limits_set(
+    ctx: Context<'_>,
+    #[description = "Max. tracks in the queue"] max_queue_len: Option<usize>,
+    #[description = "Max. tracks per user in the queue"] max_tracks_per_user: Option<usize>,
+    #[description = "Max. track duration in minutes"] max_track_minutes: Option<u64>,
+    #[description = "Cooldown on /play in seconds"] play_cooldown_secs: Option<u64>,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            let limits = &mut settings.limits;
+
+            if let Some(n) = max_queue_len {
+                limits.max_queue_len = Some(n).filter(|n| *n > 0);
+            }
+            if let Some(n) = max_tracks_per_user {
+                limits.max_tracks_per_user = Some(n).filter(|n| *n > 0);
+            }
+            if let Some(n) = max_track_minutes {
+                limits.max_track_duration = Some(n)
+                    .filter(|n| *n > 0)
+                    .map(|n| Duration::from_secs(n * 60));
+            }
+            if let Some(n) = play_cooldown_secs {
+                limits.play_cooldown = Some(n).filter(|n| *n > 0).map(Duration::from_secs);
+            }
+
+            Ok(())
+        })
+        .await?;
+
+    ctx.send(|create| create.embed(|e| limits_embed(e, &settings.limits)))
+        .await?;
 
     Ok(())
 }
+
+/// Block a domain or keyword
+#[poise::command(slash_command, rename = "block")]
+async fn limits_block(
+    ctx: Context<'_>,
+    #[description = "e.g. soundcloud.com or earrape"] pattern: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            if !settings.limits.blocked.contains(&pattern) {
+                settings.limits.blocked.push(pattern);
+            }
+
+            Ok(())
+        })
+        .await?;
+
+    ctx.send(|create| create.embed(|e| limits_embed(e, &settings.limits)))
+        .await?;
+
+    Ok(())
+}
+
+/// Unblock a domain or keyword
+#[poise::command(slash_command, rename = "unblock")]
+async fn limits_unblock(
+    ctx: Context<'_>,
+    #[description = "A previously blocked domain or keyword"] pattern: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            if !settings.limits.blocked.contains(&pattern) {
+                return Err(AppError::invalid_input(format!(
+                    "`{pattern}` is not blocked"
+                )));
+            }
+            settings.limits.blocked.retain(|p| *p != pattern);
+
+            Ok(())
+        })
+        .await?;
+
+    ctx.send(|create| create.embed(|e| limits_embed(e, &settings.limits)))
+        .await?;
+
+    Ok(())
+}
+
+/// Remove all limits of this server
+#[poise::command(slash_command, rename = "reset")]
+async fn limits_reset(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            settings.limits = Limits::default();
+
+            Ok(())
+        })
+        .await?;
+
+    ctx.send(|create| create.embed(|e| limits_embed(e, &settings.limits)))
+        .await?;
+
+    Ok(())
+}
+
+fn limits_embed<'a>(create: &'a mut CreateEmbed, limits: &Limits) -> &'a mut CreateEmbed {
+    fn or_unlimited(limit: Option<impl Display>) -> String {
+        limit.map_or_else(|| "unlimited".into(), |l| l.to_string())
+    }
+
+    let blocked = match limits.blocked.as_slice() {
+        [] => "nothing".to_string(),
+        blocked => blocked
+            .iter()
+            .map(|p| format!("`{p}`"))
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+
+    create
+        .normal_styling()
+        .title("Limits")
+        .field(
+            "Max. queue length",
+            or_unlimited(limits.max_queue_len),
+            true,
+        )
+        .field(
+            "Max. tracks per user",
+            or_unlimited(limits.max_tracks_per_user),
+            true,
+        )
+        .field(
+            "Max. track duration",
+            or_unlimited(
+                limits
+                    .max_track_duration
+                    .map(|d| format!("{} min", d.as_secs() / 60)),
+            ),
+            true,
+        )
+        .field(
+            "/play cooldown",
+            or_unlimited(limits.play_cooldown.map(|d| format!("{}s", d.as_secs()))),
+            true,
+        )
+        .field("Blocked", blocked, false)
+}
+
+/// Show the settings of this server
+#[poise::command(slash_command, rename = "view")]
+async fn settings_view(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let settings = state.settings.get(guild_id).await;
+
+    ctx.send(|create| create.embed(|e| settings_embed(e, &settings)))
+        .await?;
+
+    Ok(())
+}
+
+/// Change a setting of this server
+#[poise::command(slash_command, rename = "set")]
+async fn settings_set(
+    ctx: Context<'_>,
+    #[description = "The setting to change"] setting: SettingKey,
+    #[description = "Its new value, none clears it"] value: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| settings.set(setting, &value))
+        .await?;
+
+    ctx.send(|create| create.embed(|e| settings_embed(e, &settings)))
+        .await?;
+
+    Ok(())
+}
+
+/// Reset one or all settings of this server
+#[poise::command(slash_command, rename = "reset")]
+async fn settings_reset(
+    ctx: Context<'_>,
+    #[description = "The setting to reset, leave empty to reset everything"] setting: Option<
+        SettingKey,
+    >,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = match setting {
+        Some(setting) => {
+            state
+                .settings
+                .update(guild_id, |settings| {
+                    settings.reset(setting, state.settings.defaults());
+
+                    Ok(())
+                })
+                .await?
+        }
+        None => {
+            state.settings.reset(guild_id).await?;
+            state.settings.get(guild_id).await
+        }
+    };
+
+    ctx.send(|create| create.embed(|e| settings_embed(e, &settings)))
+        .await?;
+
+    Ok(())
+}
+
+fn settings_embed<'a>(
+    create: &'a mut CreateEmbed,
+    settings: &GuildSettings,
+) -> &'a mut CreateEmbed {
+    fn or_none(value: Option<String>) -> String {
+        value.unwrap_or_else(|| "none".into())
+    }
+
+    let allowed_sources = match settings.allowed_sources.as_slice() {
+        [] => "everything".to_string(),
+        sources => sources.join(", "),
+    };
+
+    create
+        .normal_styling()
+        .title("Settings")
+        .field(
+            "default_volume",
+            format!("{}%", settings.default_volume),
+            true,
+        )
+        .field(
+            "dj_role",
+            or_none(settings.dj_role.map(|r| format!("<@&{r}>"))),
+            true,
+        )
+        .field(
+            "announce_channel",
+            or_none(settings.announce_channel.map(|c| format!("<#{c}>"))),
+            true,
+        )
+        .field(
+            "idle_timeout_secs",
+            or_none(settings.idle_timeout.map(|d| d.as_secs().to_string())),
+            true,
+        )
+        .field("loop_default", &settings.loop_default, true)
+        .field(
+            "crossfade_secs",
+            or_none(settings.crossfade.map(|d| d.as_secs().to_string())),
+            true,
+        )
+        .field(
+            "autoplay",
+            if settings.autoplay { "on" } else { "off" },
+            true,
+        )
+        .field(
+            "skip_segments",
+            match settings.skip_segments.as_slice() {
+                [] => "none".to_string(),
+                categories => categories
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            },
+            true,
+        )
+        .field(
+            "max_queue",
+            or_none(settings.limits.max_queue_len.map(|n| n.to_string())),
+            true,
+        )
+        .field("allowed_sources", allowed_sources, true)
+        .field("locale", &settings.locale, true)
+}
+
+/// Show how this server is notified
+#[poise::command(slash_command, rename = "view")]
+async fn notifications_view(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+    let settings = state.settings.get(guild_id).await;
+
+    ctx.send(|create| create.embed(|e| notifications_embed(e, &settings)))
+        .await?;
+
+    Ok(())
+}
+
+/// Change how this server is notified
+#[poise::command(slash_command, rename = "set")]
+async fn notifications_set(
+    ctx: Context<'_>,
+    #[description = "Post them, post them silently or not at all"] mode: Option<NotificationMode>,
+    #[description = "Post the embed of every track that starts"] now_playing: Option<bool>,
+    #[description = "What happens to the previous one"] previous: Option<PreviousNotification>,
+    #[description = "The default wording"] personality: Option<Personality>,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            let notifications = &mut settings.notifications;
+
+            if let Some(mode) = mode {
+                notifications.mode = mode;
+            }
+            if let Some(now_playing) = now_playing {
+                notifications.now_playing = now_playing;
+            }
+            if let Some(previous) = previous {
+                notifications.previous = previous;
+            }
+            if let Some(personality) = personality {
+                notifications.personality = personality;
+            }
+
+            Ok(())
+        })
+        .await?;
+
+    ctx.send(|create| create.embed(|e| notifications_embed(e, &settings)))
+        .await?;
+
+    Ok(())
+}
+
+/// Reword a notification, e.g. `Finished {title}`
+#[poise::command(slash_command, rename = "template")]
+async fn notifications_template(
+    ctx: Context<'_>,
+    #[description = "The notification to reword"] notice: Notice,
+    #[description = "Its new wording, leave empty to use the default"] template: Option<String>,
+) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            settings.notifications.set_template(notice, template)
+        })
+        .await?;
+
+    ctx.send(|create| create.embed(|e| notifications_embed(e, &settings)))
+        .await?;
+
+    Ok(())
+}
+
+/// Reset the notifications of this server to the defaults of the bot
+#[poise::command(slash_command, rename = "reset")]
+async fn notifications_reset(ctx: Context<'_>) -> CmdRes {
+    let state = ctx.data();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let settings = state
+        .settings
+        .update(guild_id, |settings| {
+            settings.notifications = state.settings.defaults().notifications.clone();
+
+            Ok(())
+        })
+        .await?;
+
+    ctx.send(|create| create.embed(|e| notifications_embed(e, &settings)))
+        .await?;
+
+    Ok(())
+}
+
+/// How much of every template `/notifications view` shows.
+const TEMPLATE_PREVIEW_LEN: usize = 200;
+
+fn notifications_embed<'a>(
+    create: &'a mut CreateEmbed,
+    settings: &GuildSettings,
+) -> &'a mut CreateEmbed {
+    let notifications = &settings.notifications;
+
+    let templates = Notice::ALL
+        .iter()
+        .map(|notice| {
+            let template = notifications.template(*notice);
+            let preview = match template.chars().count() > TEMPLATE_PREVIEW_LEN {
+                true => format!(
+                    "{}...",
+                    template
+                        .chars()
+                        .take(TEMPLATE_PREVIEW_LEN)
+                        .collect::<String>()
+                ),
+                false => template.to_string(),
+            };
+            let preview = match preview.is_empty() {
+                true => "*nothing*".to_string(),
+                false => preview,
+            };
+            let custom = match notifications.templates.contains_key(notice) {
+                true => " (custom)",
+                false => "",
+            };
+
+            format!("**{notice}**{custom}: {preview}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    create
+        .normal_styling()
+        .title("Notifications")
+        .field(
+            "channel",
+            settings.announce_channel.map_or_else(
+                || "where the track was requested".into(),
+                |c| format!("<#{c}>"),
+            ),
+            true,
+        )
+        .field("mode", notifications.mode, true)
+        .field(
+            "now_playing",
+            if notifications.now_playing {
+                "on"
+            } else {
+                "off"
+            },
+            true,
+        )
+        .field("previous", notifications.previous, true)
+        .field("personality", notifications.personality, true)
+        .description(templates)
+}
+
+/// How many tracks `/library search` shows.
+const LIBRARY_RESULTS: usize = 10;
+
+fn media_library(state: &State) -> Result<Arc<Library>, AppError> {
+    state
+        .library
+        .clone()
+        .ok_or_else(|| AppError::invalid_input("There is no local library on this bot"))
+}
+
+/// Search the local library
+#[poise::command(slash_command, rename = "search")]
+async fn library_search(
+    ctx: Context<'_>,
+    #[description = "Title, artist or album"] query: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let found = media_library(state)?.search(&query, LIBRARY_RESULTS).await;
+
+    let description = match found.as_slice() {
+        [] => format!("Nothing in the library matches `{query}`"),
+        found => found
+            .iter()
+            .map(library_line)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    ctx.send(|create| {
+        create.embed(|e| e.normal_styling().title("Library").description(description))
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Play a track from the local library
+#[poise::command(slash_command, rename = "play")]
+async fn library_play(
+    ctx: Context<'_>,
+    #[description = "The track to play"]
+    #[autocomplete = "autocomplete_library_track"]
+    track: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let library = media_library(state)?;
+
+    // autocompleted tracks are sent as their path, anything else is searched for
+    let track = match library.get(&track).await {
+        Some(track) => track,
+        None => library
+            .search(&track, 1)
+            .await
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                AppError::invalid_input(format!("Nothing in the library matches `{track}`"))
+            })?,
+    };
+
+    queue_library_tracks(ctx, state, &track.title.clone(), vec![track]).await
+}
+
+/// Queue a whole album from the local library
+#[poise::command(slash_command, rename = "album")]
+async fn library_album(
+    ctx: Context<'_>,
+    #[description = "The album to queue"]
+    #[autocomplete = "autocomplete_library_album"]
+    album: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let tracks = media_library(state)?.album(&album).await;
+
+    queue_library_tracks(ctx, state, &album, tracks).await
+}
+
+/// Queue everything by an artist from the local library
+#[poise::command(slash_command, rename = "artist")]
+async fn library_artist(
+    ctx: Context<'_>,
+    #[description = "The artist to queue"]
+    #[autocomplete = "autocomplete_library_artist"]
+    artist: String,
+) -> CmdRes {
+    let state = ctx.data();
+    let tracks = media_library(state)?.artist(&artist).await;
+
+    queue_library_tracks(ctx, state, &artist, tracks).await
+}
+
+/// Queues `tracks` in order, until the queue limits of the server are reached. Tracks that
+/// can't be played are skipped and counted.
+async fn queue_library_tracks(
+    ctx: Context<'_>,
+    state: &State,
+    name: &str,
+    tracks: Vec<LibraryTrack>,
+) -> CmdRes {
+    let guild_id = ctx.guild_id().unwrap();
+    let channel_id = author_voice_channel(ctx)?;
+    if tracks.is_empty() {
+        return Err(AppError::invalid_input(format!(
+            "There is nothing called `{name}` in the library"
+        ))
+        .into());
+    }
+
+    let settings = state.settings.get(guild_id).await;
+    let limits = &settings.limits;
+    settings.check_source(LOCAL_PREFIX)?;
+
+    let loading = match tracks.len() {
+        1 => "Loading your track...".to_string(),
+        n => format!("Loading {n} tracks..."),
+    };
+    if let Err(warn) = ctx.say(loading).await {
+        warn!("{warn}");
+    }
+
+    let total = tracks.len();
+    let mut queued = vec![];
+    let mut failed: Vec<Error> = vec![];
+    let mut stopped_by = None;
+    for track in tracks {
+        let queue_full = limits
+            .check_queue(
+                state.queues.get(guild_id).as_deref().map(GuildQueue::read),
+                Some(ctx.author().id),
+            )
+            .await;
+        if let Err(err) = queue_full {
+            stopped_by = Some(err);
+            break;
+        }
+
+        // the library knows the tags already, nothing has to be looked up
+        let query = track.query();
+        let metadata = track.metadata();
+        let checked = limits
+            .check_request(&query)
+            .and_then(|()| limits.check_metadata(&metadata));
+        let entry = match checked {
+            Ok(()) => QueueEntry::new(state.resolvers.clone(), &query, metadata).await,
+            Err(err) => Err(err),
+        };
+        let enqueued = match entry {
+            Ok(entry) => enqueue(ctx, channel_id, entry).await,
+            Err(err) => Err(err.into()),
+        };
+        match enqueued {
+            Ok(handle) => queued.push(handle),
+            // a single track is reported like /play would, albums skip what can't be played
+            Err(err) if total == 1 => return Err(err),
+            Err(err) => {
+                warn!("Skipping {} from the library: {err}", track.path);
+                failed.push(err);
+            }
+        }
+    }
+
+    match (queued.as_slice(), stopped_by) {
+        ([], Some(err)) => return Err(err.into()),
+        // every track failed, which is reported like the first one
+        ([], None) => return Err(failed.remove(0)),
+        ([track], None) if total == 1 => {
+            let track_info = track.get_info().await?;
+            let chapters = chapters::of_track(&state.resolvers, track).await;
+            ctx.send(|create| {
+                create.embed(|e| e.song_embed(track.metadata(), &track_info, &chapters))
+            })
+            .await?;
+        }
+        (queued, stopped_by) => {
+            let mut description =
+                format!("Queued {} of {total} tracks from `{name}`", queued.len());
+            if !failed.is_empty() {
+                description.push_str(&format!(", {} couldn't be played", failed.len()));
+            }
+            if let Some(err) = stopped_by {
+                description.push_str(&format!(", the rest didn't fit: {}", err.message()));
+            }
+
+            ctx.send(|create| create.embed(|e| e.info_embed(description)))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn library_line(track: &LibraryTrack) -> String {
+    let mut line = format!("▷ {}", track.display_name());
+    if let Some(album) = &track.album {
+        line.push_str(&format!(" · *{album}*"));
+    }
+    if let Some(secs) = track.duration_secs {
+        line.push_str(&format!(" `{}:{:02}`", secs / 60, secs % 60));
+    }
+
+    line
+}
+
+/// Only lets members with the DJ role through, if the server has one.
+async fn is_dj(ctx: Context<'_>) -> Result<bool, Error> {
+    let Some(guild) = ctx.guild() else {
+        return Ok(true);
+    };
+
+    let state = ctx.data();
+    let Some(dj_role) = state.settings.get(guild.id).await.dj_role else {
+        return Ok(true);
+    };
+
+    let is_dj = match ctx.author_member().await {
+        Some(member) => member.roles.contains(&dj_role),
+        None => false,
+    };
+
+    if is_dj || guild.owner_id == ctx.author().id {
+        Ok(true)
+    } else {
+        Err(AppError::forbidden(format!(
+            "Only members with the <@&{dj_role}> role can do this"
+        ))
+        .into())
+    }
+}
+
+async fn autocomplete_station<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let state = ctx.data();
+    let partial = partial.to_lowercase();
+
+    state
+        .config
+        .stations
+        .0
+        .iter()
+        .map(|station| station.name.clone())
+        .filter(move |name| name.to_lowercase().contains(&partial))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// Discord allows at most this many characters in autocomplete choices.
+const MAX_CHOICE_LEN: usize = 100;
+
+fn truncate_choice(name: String) -> String {
+    match name.chars().count() > MAX_CHOICE_LEN {
+        true => name.chars().take(MAX_CHOICE_LEN - 1).chain(['…']).collect(),
+        false => name,
+    }
+}
+
+async fn autocomplete_library_track(
+    ctx: Context<'_>,
+    partial: &str,
+) -> impl Iterator<Item = AutocompleteChoice<String>> {
+    let library = ctx.data().library.clone();
+    let found = match library {
+        Some(library) => library.search(partial, 25).await,
+        None => vec![],
+    };
+
+    found
+        .into_iter()
+        // the path is sent back as the value, which can't be cut short
+        .filter(|track| track.path.len() <= MAX_CHOICE_LEN)
+        .map(|track| AutocompleteChoice {
+            name: truncate_choice(track.display_name()),
+            value: track.path,
+        })
+}
+
+async fn autocomplete_library_album(
+    ctx: Context<'_>,
+    partial: &str,
+) -> impl Iterator<Item = String> {
+    let library = ctx.data().library.clone();
+    let albums = match library {
+        Some(library) => library.albums().await,
+        None => vec![],
+    };
+    let partial = partial.to_lowercase();
+
+    albums
+        .into_iter()
+        .map(|album| album.name)
+        .filter(move |name| name.to_lowercase().contains(&partial) && name.len() <= MAX_CHOICE_LEN)
+        .take(25)
+}
+
+async fn autocomplete_library_artist(
+    ctx: Context<'_>,
+    partial: &str,
+) -> impl Iterator<Item = String> {
+    let library = ctx.data().library.clone();
+    let artists = match library {
+        Some(library) => library.artists().await,
+        None => vec![],
+    };
+    let partial = partial.to_lowercase();
+
+    artists
+        .into_iter()
+        .map(|artist| artist.name)
+        .filter(move |name| name.to_lowercase().contains(&partial) && name.len() <= MAX_CHOICE_LEN)
+        .take(25)
 }
@@ -0,0 +1,199 @@
+//! Fades from one track into the next for guilds with a `crossfade_secs` setting.
+//!
+//! The next track starts playing a little before the current one ends, while the volume of the
+//! current one ramps down and that of the next one ramps up. Both ramps are relative to the
+//! volume of the guild, which is read again on every step so that changing it mid-fade sticks.
+//! Pausing the queue pauses both tracks while they overlap, and reordering it leaves the next
+//! track where it is.
+
+use std::time::Duration;
+
+use poise::async_trait;
+use songbird::{
+    tracks::{PlayMode, TrackError, TrackHandle},
+    Event, EventContext, EventHandler,
+};
+use tracing::{debug, warn};
+
+use crate::client::{bot::LoopMode, player::GuildPlayer};
+
+/// How often the fade checks whether it is time to start.
+const START_INTERVAL: Duration = Duration::from_millis(250);
+/// How often the volumes are adjusted during a fade.
+const STEP: Duration = Duration::from_millis(100);
+
+/// Fades `track` into whatever comes after it in the queue of `player`, `crossfade` before it
+/// ends.
+///
+/// Tracks that are too short or whose duration is unknown (streams, plain files) are not
+/// faded, neither are tracks that loop.
+pub fn watch(player: &GuildPlayer, track: &TrackHandle, crossfade: Duration) {
+    let too_short = track
+        .metadata()
+        .duration
+        .is_none_or(|duration| duration < crossfade * 2);
+    if crossfade.is_zero() || too_short {
+        return;
+    }
+
+    let start = FadeStart {
+        player: player.clone(),
+        crossfade,
+    };
+    if let Err(err) = track.add_event(Event::Periodic(START_INTERVAL, None), start) {
+        warn!("Could not watch the end of a track for crossfading: {err}");
+    }
+}
+
+/// Starts the next track once the current one has less than `crossfade` left. Checks the
+/// position instead of waiting for a fixed time, seeking moves the end.
+struct FadeStart {
+    player: GuildPlayer,
+    crossfade: Duration,
+}
+
+#[async_trait]
+impl EventHandler for FadeStart {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let [(track_state, handle)] = **tracks else {
+            return None;
+        };
+
+        let duration = handle.metadata().duration?;
+        if duration.saturating_sub(track_state.position) > self.crossfade {
+            return None;
+        }
+
+        // a looping track is queued again right after it, once it ended
+        let settings = self.player.settings().await;
+        if matches!(self.player.loop_mode(&settings).await, LoopMode::Track) {
+            return None;
+        }
+
+        // only the track that is playing right now fades, and only into the one after it. Nobody
+        // may reorder the queue while the fade starts, the next track is pinned once it did
+        let queue = self.player.queue().lock().await;
+        let tracks = queue.current_queue();
+        if tracks.first().map(TrackHandle::uuid) != Some(handle.uuid()) {
+            return Some(Event::Cancel);
+        }
+        let next = tracks.get(1)?;
+        match next.get_info().await {
+            Ok(next_state) if next_state.playing == PlayMode::Pause => {
+                let fade = Fade {
+                    player: self.player.clone(),
+                    outgoing: handle.clone(),
+                    length: duration.saturating_sub(track_state.position),
+                };
+                fade.start(next, settings.volume());
+            }
+            Ok(_) => {}
+            // the next track is gone already
+            Err(_) => return None,
+        }
+
+        Some(Event::Cancel)
+    }
+}
+
+/// Ramps the volumes while the next track plays its first `length`.
+struct Fade {
+    player: GuildPlayer,
+    outgoing: TrackHandle,
+    length: Duration,
+}
+
+impl Fade {
+    fn start(self, next: &TrackHandle, volume: f32) {
+        debug!(title = next.metadata().title, "Fading into the next track");
+
+        let player = self.player.clone();
+        player.queue().set_fading_in(Some(next.clone()));
+        let started = next
+            .set_volume(0.0)
+            .and_then(|()| next.play())
+            .and_then(|()| next.add_event(Event::Periodic(STEP, None), self));
+
+        if let Err(err) = started {
+            warn!("Could not fade into the next track: {err}");
+            player.queue().set_fading_in(None);
+            if let Err(err) = next.set_volume(volume) {
+                warn!("Could not reset the volume of the next track: {err}");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Fade {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let [(track_state, handle)] = **tracks else {
+            return None;
+        };
+
+        let volume = self.player.settings().await.volume();
+        let progress = progress(track_state.position, self.length);
+        let (fade_out, fade_in) = gains(progress);
+        match self.outgoing.set_volume(volume * fade_out) {
+            // the outgoing track ended already, which is fine
+            Ok(()) | Err(TrackError::Finished) => {}
+            Err(err) => warn!("Could not fade out a track: {err}"),
+        }
+        let faded = handle.set_volume(volume * fade_in);
+        if let Err(err) = &faded {
+            warn!("Could not fade in a track: {err}");
+        }
+
+        match faded.is_err() || progress >= 1.0 {
+            true => {
+                self.player.queue().set_fading_in(None);
+                Some(Event::Cancel)
+            }
+            false => None,
+        }
+    }
+}
+
+/// How far into a fade of `length` the next track is at `position`, from 0 to 1.
+fn progress(position: Duration, length: Duration) -> f32 {
+    match length.is_zero() {
+        true => 1.0,
+        false => (position.as_secs_f32() / length.as_secs_f32()).min(1.0),
+    }
+}
+
+/// The gains of the outgoing and the next track. They keep the same loudness overall, a linear
+/// fade would dip in the middle.
+fn gains(progress: f32) -> (f32, f32) {
+    let angle = progress.clamp(0.0, 1.0) * std::f32::consts::FRAC_PI_2;
+
+    (angle.cos(), angle.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades_ramp_from_the_outgoing_to_the_next_track() {
+        let length = Duration::from_secs(4);
+
+        assert_eq!(progress(Duration::ZERO, length), 0.0);
+        assert_eq!(progress(Duration::from_secs(1), length), 0.25);
+        assert_eq!(progress(Duration::from_secs(9), length), 1.0);
+        assert_eq!(progress(Duration::from_secs(1), Duration::ZERO), 1.0);
+
+        assert_eq!(gains(0.0), (1.0, 0.0));
+        let (fade_out, fade_in) = gains(1.0);
+        assert!(fade_out.abs() < 1e-6 && (fade_in - 1.0).abs() < 1e-6);
+        let (fade_out, fade_in) = gains(0.5);
+        assert!((fade_out - fade_in).abs() < 1e-6);
+        assert!((fade_out.powi(2) + fade_in.powi(2) - 1.0).abs() < 1e-6);
+    }
+}
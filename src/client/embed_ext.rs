@@ -3,6 +3,11 @@ use std::{fmt::Display, time::Duration};
 use poise::serenity_prelude::{Colour, CreateEmbed, Timestamp};
 use songbird::{input::Metadata, tracks::TrackState};
 
+use crate::client::{
+    chapters::{self, Chapter},
+    segments::Segment,
+};
+
 pub trait CreateEmbedExt {
     const ERROR_COLOUR: Colour = Colour::DARK_RED;
     const WARN_COLOUR: Colour = Colour::ORANGE;
@@ -20,8 +25,15 @@ pub trait CreateEmbedExt {
 
     fn oxo_footer(&mut self) -> &mut Self;
 
-    fn song_embed(&mut self, song_metadata: &Metadata, track_state: &TrackState) -> &mut Self;
+    fn song_embed(
+        &mut self,
+        song_metadata: &Metadata,
+        track_state: &TrackState,
+        chapters: &[Chapter],
+    ) -> &mut Self;
     fn info_embed(&mut self, msg: impl Display) -> &mut Self;
+    /// Lists the segments that were skipped in a track, if there are any.
+    fn skipped_segments(&mut self, skipped: &[Segment]) -> &mut Self;
 }
 
 impl CreateEmbedExt for CreateEmbed {
@@ -58,7 +70,12 @@ impl CreateEmbedExt for CreateEmbed {
             .now()
     }
 
-    fn song_embed(&mut self, song_metadata: &Metadata, track_state: &TrackState) -> &mut Self {
+    fn song_embed(
+        &mut self,
+        song_metadata: &Metadata,
+        track_state: &TrackState,
+        chapters: &[Chapter],
+    ) -> &mut Self {
         let title = song_metadata.title.clone().unwrap_or_else(|| "N/A".into());
         let author = format!(
             "By: {}",
@@ -73,25 +90,19 @@ impl CreateEmbedExt for CreateEmbed {
         let total_duration = song_metadata.duration.unwrap_or_default();
         let already_played = track_state.position;
 
-        fn duration_format(duration: &Duration) -> String {
-            let seconds = duration.as_secs();
-            let minutes = seconds / 60;
-            let hours = minutes / 60;
-            let minutes = minutes % 60;
-            let seconds = seconds % 60;
-
-            if hours > 0 {
-                format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
-            } else {
-                format!("{:02}:{:02}", minutes, seconds)
-            }
-        }
-
-        let description = format!(
+        let mut description = format!(
             "Duration: `{} / {}`",
             duration_format(&already_played),
             duration_format(&total_duration)
         );
+        if let Some(current) = chapters::current(chapters, already_played) {
+            description += &format!(
+                "\nChapter: `{}/{}` {}",
+                current + 1,
+                chapters.len(),
+                chapters[current].title
+            );
+        }
 
         let url = song_metadata
             .source_url
@@ -102,7 +113,49 @@ impl CreateEmbedExt for CreateEmbed {
             .title(title)
             .author(|a| a.name(author).icon_url(Self::MUSIC_ICON))
             .thumbnail(thumbnail)
-            .description(description)
-            .url(url)
+            .description(description);
+
+        // files from the library have no url discord could link to
+        if url.starts_with("http") {
+            self.url(url);
+        }
+
+        self
+    }
+
+    fn skipped_segments(&mut self, skipped: &[Segment]) -> &mut Self {
+        if skipped.is_empty() {
+            return self;
+        }
+
+        let skipped = skipped
+            .iter()
+            .map(|segment| {
+                format!(
+                    "{} `{} - {}`",
+                    segment.category,
+                    duration_format(&segment.start()),
+                    duration_format(&segment.end())
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.field("Skipped", skipped, false)
+    }
+}
+
+/// `mm:ss`, or `hh:mm:ss` for anything longer than an hour.
+pub fn duration_format(duration: &Duration) -> String {
+    let seconds = duration.as_secs();
+    let minutes = seconds / 60;
+    let hours = minutes / 60;
+    let minutes = minutes % 60;
+    let seconds = seconds % 60;
+
+    if hours > 0 {
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
     }
 }
@@ -1,49 +1,40 @@
-use std::sync::Arc;
+use std::{fmt::Display, time::Duration};
 
-use poise::{
-    async_trait,
-    serenity_prelude::{ChannelId, GuildId, Http, Mutex},
-};
+use poise::{async_trait, serenity_prelude::ChannelId};
+
+use songbird::{tracks::TrackHandle, Event, EventContext, EventHandler};
 
-use songbird::{create_player, Call, Event, EventContext, EventHandler, TrackEvent};
+use tracing::{debug, info, warn, Instrument, Span};
 
-use tracing::debug;
+use crate::metrics::METRICS;
 
-use crate::client::bot::{Context, LoopMode, Queues, State};
+use crate::client::{
+    autoplay::related_track,
+    bot::LoopMode,
+    notifications::Notice,
+    player::{GuildPlayer, Request},
+    queue_ext::{place_fairly, Discarded},
+    settings::GuildSettings,
+    sources::lazy::{resolve_failure, QueueEntry},
+    spans::track_span,
+};
 
+/// Announces tracks that ended and keeps the queue going (looping, autoplay, leaving once
+/// it's empty). Attached to every track by [`GuildPlayer::add`].
 #[derive(Clone)]
 pub struct EndEventHandler {
-    http: Arc<Http>,
-    channel_id: ChannelId,
-    queues: Queues,
-    call: Arc<Mutex<Call>>,
-    guild_id: GuildId,
-    loop_mode: Arc<Mutex<LoopMode>>,
-    handler: Arc<Mutex<Call>>,
+    player: GuildPlayer,
 }
 
 impl EndEventHandler {
-    pub fn new(ctx: Context, state: &State, handler: Arc<Mutex<Call>>, guild_id: GuildId) -> Self {
-        Self {
-            channel_id: ctx.channel_id(),
-            http: ctx.serenity_context().http.clone(),
-            call: handler.clone(),
-            guild_id,
-            queues: state.queues.clone(),
-            loop_mode: state.loop_mode.clone(),
-            handler,
-        }
+    pub fn new(player: GuildPlayer) -> Self {
+        Self { player }
     }
 }
 
 #[async_trait]
 impl EventHandler for EndEventHandler {
     async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
-        debug!(
-            channel_id = self.channel_id.to_string(),
-            "Triggered leave-after-end-event-handler"
-        );
-
         let EventContext::Track(tracks) = ctx else {
             return None;
         };
@@ -53,60 +44,230 @@ impl EventHandler for EndEventHandler {
             return None;
         };
 
+        let span = track_span(handle).await;
+        self.track_ended(handle).instrument(span).await;
+
+        None
+    }
+}
+
+impl EndEventHandler {
+    pub async fn track_ended(&self, handle: &TrackHandle) {
+        if handle.typemap().read().await.contains_key::<Discarded>() {
+            debug!("Discarded track ended");
+            return;
+        }
+        let player = &self.player;
+        let settings = player.settings().await;
+        let request = Request::of(handle).await;
+        let channel_id = settings.announce_channel.or(request.channel_id);
+
         let fallback_title = "No title found, no seriously this is not the name of the track - for some reason there just isn't one".to_string();
         let title = handle.metadata().title.as_ref().unwrap_or(&fallback_title);
 
-        let _ = self
-            .channel_id
-            .say(&self.http, format!("Finished playing `{}` uwu", title))
-            .await;
+        let failure = resolve_failure(handle).await;
+        match &failure {
+            Some(reason) => {
+                info!("Track could not be played, skipping it");
+                METRICS.tracks_failed.inc();
+                player
+                    .notify_ended(
+                        channel_id,
+                        handle,
+                        Notice::TrackFailed,
+                        &[("title", title), ("reason", reason.as_str())],
+                    )
+                    .await;
+            }
+            None => {
+                info!("Track finished playing");
+                METRICS.tracks_played.inc();
+                player
+                    .notify_ended(
+                        channel_id,
+                        handle,
+                        Notice::TrackFinished,
+                        &[("title", title)],
+                    )
+                    .await;
+            }
+        }
 
-        let queues = self.queues.lock().await;
+        // looked up before taking the lock, `/play` and friends shouldn't wait on it
+        let loop_mode = player.loop_mode(&settings).await;
+        let queue_mode = player.state().queue_mode(player.guild_id()).await;
+        let requeued = match loop_mode {
+            LoopMode::Off => None,
+            // a track that can't be played would be looped forever
+            _ if failure.is_some() => None,
+            _ => self.requeue(handle).await,
+        };
 
-        let queue = queues.get(&self.guild_id).unwrap();
+        if let Some(entry) = requeued {
+            let queue = player.queue().lock().await;
+            match loop_mode {
+                LoopMode::Off => {}
+                // track-looping is handled on command-execution once
+                LoopMode::Track => {
+                    // TODO: broken, tracks are playing king of the hill and try to silence one another
+                    log_err(queue.pause(), "Could not pause the queue");
+                    player.add(&queue, entry, request, &settings).await;
+                    queue.modify_queue(|q| {
+                        let last = q.pop_back().unwrap();
+                        q.push_front(last);
+                    });
+                    log_err(queue.resume(), "Could not resume the queue");
+                    debug!("Looping track");
+                }
+                LoopMode::Queue => {
+                    let handle = player.add(&queue, entry, request, &settings).await;
+                    place_fairly(&queue, player.queue().pinned(), queue_mode, &handle).await;
+                    debug!("Re-queued track to loop the queue");
+                }
+            }
+        }
 
-        let loop_mode = self.loop_mode.lock().await;
+        if player.queue().read().is_empty() && settings.autoplay {
+            self.autoplay(handle, request, channel_id, &settings).await;
+        }
 
-        let input = handle.metadata().source_url.as_ref().unwrap();
+        if player.queue().read().is_empty() {
+            match settings.idle_timeout {
+                None => {
+                    player.notify(channel_id, Notice::QueueEnded, &[]).await;
 
-        let mut handler = self.handler.lock().await;
+                    info!("Queue is empty, leaving");
+                    player.leave().await;
+                }
+                Some(idle_timeout) => {
+                    debug!(?idle_timeout, "Queue is empty, waiting before leaving");
+                    let period = player.queue().start_idling();
+                    tokio::spawn(
+                        leave_when_idle(self.clone(), channel_id, idle_timeout, period)
+                            .instrument(Span::current()),
+                    );
+                }
+            }
+        }
+    }
 
-        match *loop_mode {
-            LoopMode::Off => {}
-            // track-looping is handled on command-execution once
-            LoopMode::Track => {
-                // TODO: broken, tracks are playing king of the hill and try to silence one another
-                let input = songbird::ytdl(&input).await.unwrap();
-                let (track, track_handle) = create_player(input);
-                let _ = track_handle.add_event(Event::Track(TrackEvent::End), self.clone());
+    /// Queues a track related to `handle`, if nobody queued anything while it was looked up.
+    async fn autoplay(
+        &self,
+        handle: &TrackHandle,
+        request: Request,
+        channel_id: Option<ChannelId>,
+        settings: &GuildSettings,
+    ) {
+        let player = &self.player;
+        let state = player.state();
 
-                let _ = queue.pause();
-                queue.add(track, &mut handler);
-                queue.modify_queue(|q| {
-                    let last = q.pop_back().unwrap();
-                    q.push_front(last);
-                });
-                let _ = queue.resume();
+        let Some(url) = related_track(&state.config.ytdl, handle.metadata()).await else {
+            warn!("Autoplay could not find a related track");
+            return;
+        };
+        let entry = match QueueEntry::lookup(&state.resolvers, &url, &settings.limits).await {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("Could not load autoplay track {url}: {err}");
+                return;
             }
-            LoopMode::Queue => {
-                let input = songbird::ytdl(&input).await.unwrap();
-                let (track, track_handle) = create_player(input);
-                let _ = track_handle.add_event(Event::Track(TrackEvent::End), self.clone());
+        };
+
+        let queue = player.queue().lock().await;
+        if !queue.is_empty() {
+            debug!(
+                url,
+                "Something was queued in the meantime, dropping the autoplay track"
+            );
+            return;
+        }
+        // nobody asked for it, but it is announced where the last track was
+        let request = Request {
+            requester: None,
+            channel_id: request.channel_id,
+        };
+        let track_handle = player.add(&queue, entry, request, settings).await;
+        drop(queue);
+        info!(url, "Autoplay queued a related track");
 
-                queue.add(track, &mut handler);
+        let title = track_handle.metadata().title.as_deref().unwrap_or("N/A");
+        player
+            .notify(channel_id, Notice::Autoplay, &[("title", title)])
+            .await;
+    }
+
+    /// A fresh copy of `handle` to loop it, with everything that is known about it already.
+    async fn requeue(&self, handle: &TrackHandle) -> Option<QueueEntry> {
+        let metadata = handle.metadata().clone();
+        let Some(url) = metadata.source_url.clone() else {
+            warn!("Track has no source url, it can't be looped");
+            return None;
+        };
+
+        let resolvers = self.player.state().resolvers.clone();
+        match QueueEntry::new(resolvers, &url, metadata).await {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                warn!("Could not requeue the track to loop it: {err}");
+                None
             }
         }
+    }
+}
+
+/// Keeps the stage topic up to date, see [`GuildPlayer::track_started`].
+#[derive(Clone)]
+pub struct PlayEventHandler {
+    player: GuildPlayer,
+}
+
+impl PlayEventHandler {
+    pub fn new(player: GuildPlayer) -> Self {
+        Self { player }
+    }
+}
 
-        if queue.is_empty() {
-            let _ = self
-                .channel_id
-                .say(&self.http, "No more songs to play OwO, guess I'll go...")
-                .await;
+#[async_trait]
+impl EventHandler for PlayEventHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
 
-            let mut call = self.call.lock().await;
-            let _ = call.leave().await;
+        for (_, handle) in tracks.iter() {
+            let span = track_span(handle).await;
+            self.player.track_started(handle).instrument(span).await;
         }
 
         None
     }
 }
+
+/// Leaves the channel if nothing was queued within `idle_timeout`. The queue may have run
+/// empty again in the meantime, then the timer of that idle `period` decides instead.
+async fn leave_when_idle(
+    handler: EndEventHandler,
+    channel_id: Option<ChannelId>,
+    idle_timeout: Duration,
+    period: u64,
+) {
+    tokio::time::sleep(idle_timeout).await;
+
+    let queue = handler.player.queue();
+    if queue.is_idling_since(period) && queue.read().is_empty() {
+        handler
+            .player
+            .notify(channel_id, Notice::QueueEnded, &[])
+            .await;
+
+        info!("Idle for {idle_timeout:?}, leaving");
+        handler.player.leave().await;
+    }
+}
+
+fn log_err<E: Display>(result: Result<(), E>, message: &str) {
+    if let Err(err) = result {
+        warn!("{message}: {err}");
+    }
+}
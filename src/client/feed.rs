@@ -0,0 +1,102 @@
+use poise::serenity_prelude::{ChannelId, GuildId};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::trace;
+
+/// Events a subscriber hasn't received yet are kept up to this many, older ones are dropped.
+const FEED_CAPACITY: usize = 256;
+
+/// Something that happened in a guild, as sent to api clients on `/api/feed`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FeedEvent {
+    /// The voice connection dropped without oxo leaving
+    Disconnected {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        reason: String,
+    },
+    Reconnecting {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        attempt: u32,
+    },
+    /// Either oxo rejoined after a [`FeedEvent::Disconnected`], or discord moved the call
+    /// to another voice server
+    Reconnected {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    },
+    /// Gave up rejoining, the queue is kept until someone uses `/join`
+    ReconnectFailed {
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        attempts: u32,
+    },
+}
+
+impl FeedEvent {
+    pub fn guild_id(&self) -> GuildId {
+        match self {
+            FeedEvent::Disconnected { guild_id, .. }
+            | FeedEvent::Reconnecting { guild_id, .. }
+            | FeedEvent::Reconnected { guild_id, .. }
+            | FeedEvent::ReconnectFailed { guild_id, .. } => *guild_id,
+        }
+    }
+}
+
+/// Hands every published [`FeedEvent`] to whoever is subscribed at that moment.
+#[derive(Debug, Clone)]
+pub struct Feed(broadcast::Sender<FeedEvent>);
+
+impl Default for Feed {
+    fn default() -> Self {
+        Self(broadcast::channel(FEED_CAPACITY).0)
+    }
+}
+
+impl Feed {
+    pub fn publish(&self, event: FeedEvent) {
+        if self.0.send(event).is_err() {
+            trace!("Nobody is subscribed to the feed");
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FeedEvent> {
+        self.0.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn subscribers_get_events_published_after_subscribing() {
+        let feed = Feed::default();
+        feed.publish(FeedEvent::Reconnected {
+            guild_id: GuildId(1),
+            channel_id: ChannelId(2),
+        });
+
+        let mut events = feed.subscribe();
+        feed.publish(FeedEvent::Reconnecting {
+            guild_id: GuildId(1),
+            channel_id: ChannelId(2),
+            attempt: 3,
+        });
+
+        let event = serde_json::to_value(events.recv().await.unwrap()).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "event": "reconnecting",
+                "guild_id": "1",
+                "channel_id": "2",
+                "attempt": 3,
+            })
+        );
+        assert!(events.try_recv().is_err());
+    }
+}
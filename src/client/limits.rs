@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+use poise::serenity_prelude::{Attachment, GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use songbird::{input::Metadata, tracks::TrackQueue};
+
+use crate::{
+    client::{queue_ext::Requester, settings::optional_secs},
+    config::AttachmentConfig,
+    error::AppError,
+};
+
+/// Per-guild restrictions on what may be queued. `None` means unlimited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub max_queue_len: Option<usize>,
+    pub max_tracks_per_user: Option<usize>,
+    #[serde(with = "optional_secs", rename = "max_track_duration_secs")]
+    pub max_track_duration: Option<Duration>,
+    /// Domains or keywords, matched case-insensitively against the request, title and url
+    pub blocked: Vec<String>,
+    #[serde(with = "optional_secs", rename = "play_cooldown_secs")]
+    pub play_cooldown: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    QueueLength(usize),
+    TracksPerUser(usize),
+    TrackDuration(Duration),
+    Blocked,
+    Cooldown(Duration),
+    FileSize(u64),
+    FileType,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::QueueLength(max) => write!(f, "Max. queue length ({max} tracks)"),
+            Limit::TracksPerUser(max) => write!(f, "Max. tracks per user ({max} tracks)"),
+            Limit::TrackDuration(max) => {
+                write!(f, "Max. track duration ({} min)", max.as_secs() / 60)
+            }
+            Limit::Blocked => write!(f, "Blocked domain or keyword"),
+            Limit::Cooldown(cooldown) => write!(f, "Cooldown ({}s)", cooldown.as_secs()),
+            Limit::FileSize(max) => write!(f, "Max. file size ({} MB)", max / 1024 / 1024),
+            Limit::FileType => write!(f, "Allowed file types"),
+        }
+    }
+}
+
+impl Limits {
+    /// Checks the raw request (url or search query) before anything is resolved.
+    pub fn check_request(&self, request: &str) -> Result<(), AppError> {
+        match self.blocked_pattern(request) {
+            Some(pattern) => Err(AppError::limit_exceeded(
+                Limit::Blocked,
+                format!("Requests containing `{pattern}` are not allowed in this server"),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks whether there is room in the queue for another track of `requester`.
+    pub async fn check_queue(
+        &self,
+        queue: Option<&TrackQueue>,
+        requester: Option<UserId>,
+    ) -> Result<(), AppError> {
+        let tracks = queue.map(TrackQueue::current_queue).unwrap_or_default();
+
+        if let Some(max) = self.max_queue_len.filter(|max| tracks.len() >= *max) {
+            return Err(AppError::limit_exceeded(
+                Limit::QueueLength(max),
+                format!("The queue is full, it can hold at most {max} tracks"),
+            ));
+        }
+
+        let (Some(max), Some(requester)) = (self.max_tracks_per_user, requester) else {
+            return Ok(());
+        };
+
+        let mut queued_by_requester = 0;
+        for track in &tracks {
+            if track.typemap().read().await.get::<Requester>() == Some(&requester) {
+                queued_by_requester += 1;
+            }
+        }
+
+        if queued_by_requester >= max {
+            return Err(AppError::limit_exceeded(
+                Limit::TracksPerUser(max),
+                format!("You already have {queued_by_requester} tracks queued, the limit is {max}"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the metadata of a resolved track. If the duration is limited, tracks of unknown
+    /// length (e.g. live streams) are rejected, they could go on forever.
+    pub fn check_metadata(&self, metadata: &Metadata) -> Result<(), AppError> {
+        if let Some(max) = self.max_track_duration {
+            let too_long = match metadata.duration {
+                None => Some("I can't tell how long this track is".to_string()),
+                Some(duration) if duration > max => Some(format!(
+                    "This track is {} min long",
+                    duration.as_secs() / 60
+                )),
+                Some(_) => None,
+            };
+            if let Some(reason) = too_long {
+                return Err(AppError::limit_exceeded(
+                    Limit::TrackDuration(max),
+                    format!(
+                        "{reason}, tracks may be at most {} min long",
+                        max.as_secs() / 60
+                    ),
+                ));
+            }
+        }
+
+        [&metadata.title, &metadata.source_url]
+            .into_iter()
+            .flatten()
+            .try_for_each(|field| self.check_request(field))
+    }
+
+    fn blocked_pattern(&self, haystack: &str) -> Option<&str> {
+        let haystack = haystack.to_lowercase();
+
+        self.blocked
+            .iter()
+            .find(|pattern| haystack.contains(&pattern.to_lowercase()))
+            .map(String::as_str)
+    }
+}
+
+/// Checks a file uploaded to discord against the configured size and type limits.
+pub fn check_attachment(
+    config: &AttachmentConfig,
+    attachment: &Attachment,
+) -> Result<(), AppError> {
+    if attachment.size > config.max_size() {
+        return Err(AppError::limit_exceeded(
+            Limit::FileSize(config.max_size()),
+            format!(
+                "`{}` is too big, files may be at most {} MB",
+                attachment.filename, config.max_size_mb
+            ),
+        ));
+    }
+
+    let mime_type = attachment.content_type.as_deref().unwrap_or_default();
+    if !config.allows_type(mime_type) {
+        return Err(AppError::limit_exceeded(
+            Limit::FileType,
+            format!(
+                "`{}` doesn't look like an audio file, only {} can be played",
+                attachment.filename,
+                config.types.join(", ")
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Remembers when each user last used `/play`.
+#[derive(Debug, Default)]
+pub struct Cooldowns(HashMap<(GuildId, UserId), Instant>);
+
+impl Cooldowns {
+    /// Fails if `user` queued a track with `/play` less than `cooldown` ago.
+    pub fn check(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        cooldown: Option<Duration>,
+    ) -> Result<(), AppError> {
+        if let (Some(cooldown), Some(last)) = (cooldown, self.0.get(&(guild_id, user_id))) {
+            let elapsed = last.elapsed();
+            if elapsed < cooldown {
+                return Err(AppError::limit_exceeded(
+                    Limit::Cooldown(cooldown),
+                    format!(
+                        "Slow down! You can queue another track in {}s",
+                        (cooldown - elapsed).as_secs() + 1
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts the cooldown of `user`, once a `/play` of theirs actually queued a track.
+    pub fn record(&mut self, guild_id: GuildId, user_id: UserId) {
+        self.0.insert((guild_id, user_id), Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn attachment(size: u64, content_type: Option<&str>) -> Attachment {
+        serde_json::from_value(json!({
+            "id": "1",
+            "filename": "song.mp3",
+            "proxy_url": "https://media.discordapp.net/attachments/1/2/song.mp3",
+            "url": "https://cdn.discordapp.com/attachments/1/2/song.mp3",
+            "size": size,
+            "content_type": content_type,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn types_match_exactly_or_by_prefix() {
+        let config = AttachmentConfig {
+            max_size_mb: 25,
+            types: vec!["audio/*".into(), "video/MP4".into()],
+        };
+
+        assert!(config.allows_type("audio/mpeg"));
+        assert!(config.allows_type("Audio/OGG; codecs=opus"));
+        assert!(config.allows_type("video/mp4"));
+        assert!(!config.allows_type("video/webm"));
+        assert!(!config.allows_type("image/png"));
+        assert!(!config.allows_type(""));
+    }
+
+    #[test]
+    fn attachments_are_checked_for_size_and_type() {
+        let config = AttachmentConfig::default();
+        let max_size = config.max_size();
+
+        assert!(check_attachment(&config, &attachment(max_size, Some("audio/mpeg"))).is_ok());
+        assert!(check_attachment(&config, &attachment(max_size + 1, Some("audio/mpeg"))).is_err());
+        assert!(check_attachment(&config, &attachment(1, Some("text/plain"))).is_err());
+        assert!(check_attachment(&config, &attachment(1, None)).is_err());
+    }
+
+    #[test]
+    fn unknown_durations_are_rejected_when_limited() {
+        let metadata = |secs: Option<u64>| Metadata {
+            duration: secs.map(Duration::from_secs),
+            ..Default::default()
+        };
+        let mut limits = Limits::default();
+        assert!(limits.check_metadata(&metadata(None)).is_ok());
+
+        limits.max_track_duration = Some(Duration::from_secs(600));
+        assert!(limits.check_metadata(&metadata(Some(600))).is_ok());
+        assert!(limits.check_metadata(&metadata(Some(601))).is_err());
+        assert!(limits.check_metadata(&metadata(None)).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use poise::async_trait;
+
+use crate::{client::sources::local::LOCAL_PREFIX, error::AppError, library::Library};
+
+use super::{Lyrics, LyricsProvider, LyricsQuery};
+
+/// Lyrics of files in the library, from a `.lrc` file next to them or their lyrics tag.
+#[derive(Debug)]
+pub struct LocalLyrics {
+    library: Arc<Library>,
+}
+
+impl LocalLyrics {
+    pub fn new(library: Arc<Library>) -> Self {
+        Self { library }
+    }
+}
+
+#[async_trait]
+impl LyricsProvider for LocalLyrics {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn lyrics(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, AppError> {
+        let Some(path) = query
+            .source_url
+            .as_deref()
+            .and_then(|url| url.strip_prefix(LOCAL_PREFIX))
+        else {
+            return Ok(None);
+        };
+
+        let lyrics = self.library.lyrics(path).await;
+
+        Ok(lyrics.and_then(|lyrics| Lyrics::parse(self.name(), &lyrics)))
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use poise::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::error::AppError;
+
+use super::{Lyrics, LyricsProvider, LyricsQuery};
+
+/// LRCLIB asks clients to say who they are.
+const USER_AGENT: &str = "oxo (https://github.com/Giftzwerg02/oxo)";
+/// Search results this far off the duration of the track are some other version of the song.
+const MAX_DURATION_DIFFERENCE: Duration = Duration::from_secs(5);
+
+/// [LRCLIB](https://lrclib.net), a free database of mostly synced lyrics.
+#[derive(Debug)]
+pub struct Lrclib {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    duration: Option<f64>,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+impl Lrclib {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The song with exactly this title and artist.
+    async fn get(&self, title: &str, artist: &str) -> Result<Option<Record>, AppError> {
+        let response = self
+            .client
+            .get(format!("{}/api/get", self.url))
+            .query(&[("track_name", title), ("artist_name", artist)])
+            .header(reqwest::header::USER_AGENT, USER_AGENT)
+            .send()
+            .await
+            .map_err(unreachable)?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        response
+            .error_for_status()
+            .map_err(unreachable)?
+            .json()
+            .await
+            .map(Some)
+            .map_err(unreachable)
+    }
+
+    async fn search(&self, text: &str) -> Result<Vec<Record>, AppError> {
+        self.client
+            .get(format!("{}/api/search", self.url))
+            .query(&[("q", text)])
+            .header(reqwest::header::USER_AGENT, USER_AGENT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(unreachable)?
+            .json()
+            .await
+            .map_err(unreachable)
+    }
+}
+
+#[async_trait]
+impl LyricsProvider for Lrclib {
+    fn name(&self) -> &'static str {
+        "lrclib"
+    }
+
+    async fn lyrics(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, AppError> {
+        let title = clean_title(&query.title);
+
+        let exact = match &query.artist {
+            Some(artist) => self.get(&title, artist).await?,
+            None => None,
+        };
+        let record = match exact {
+            Some(record) => Some(record),
+            // the artist of a video is often just the channel that uploaded it
+            None => self.search(&title).await?.into_iter().find(|record| {
+                let (Some(wanted), Some(found)) = (query.duration, record.duration) else {
+                    return true;
+                };
+                wanted.abs_diff(Duration::from_secs_f64(found.max(0.0))) <= MAX_DURATION_DIFFERENCE
+            }),
+        };
+
+        Ok(record.and_then(|record| {
+            let text = record.synced_lyrics.or(record.plain_lyrics)?;
+            Lyrics::parse(self.name(), &text)
+        }))
+    }
+}
+
+/// Drops what video titles add to the name of a song, e.g. `(Official Video)` or `[HD]`.
+fn clean_title(title: &str) -> String {
+    let mut cleaned = String::with_capacity(title.len());
+    let mut depth = 0usize;
+    for c in title.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            c if depth == 0 => cleaned.push(c),
+            _ => {}
+        }
+    }
+
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn unreachable(err: reqwest::Error) -> AppError {
+    AppError::internal(format!("Could not look up lyrics on lrclib: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_decorations_are_dropped() {
+        assert_eq!(
+            clean_title("Artist - Song (Official Video) [4K]"),
+            "Artist - Song"
+        );
+        assert_eq!(clean_title("Song"), "Song");
+    }
+}
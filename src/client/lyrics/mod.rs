@@ -0,0 +1,430 @@
+//! Finds the lyrics of tracks, synced to the track where possible.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use poise::async_trait;
+use songbird::{input::Metadata, tracks::TrackHandle};
+use tracing::{debug, warn};
+
+use crate::{config::Config, error::AppError, library::Library};
+
+pub mod local;
+pub mod lrclib;
+pub mod subtitles;
+pub mod view;
+
+/// How many lines `/lyrics` shows per page.
+pub const LINES_PER_PAGE: usize = 15;
+
+/// What to look for, usually taken from the metadata of a track.
+#[derive(Debug, Clone, Default)]
+pub struct LyricsQuery {
+    pub title: String,
+    pub artist: Option<String>,
+    pub duration: Option<Duration>,
+    /// The track itself, for providers that read the lyrics from the source
+    pub source_url: Option<String>,
+}
+
+impl LyricsQuery {
+    /// The song `metadata` describes, `None` if it doesn't even have a title.
+    pub fn of(metadata: &Metadata) -> Option<Self> {
+        // yt-dlp only knows the name of the song on some sites, the title is that of the video
+        let title = metadata.track.clone().or_else(|| metadata.title.clone())?;
+
+        Some(Self {
+            title,
+            artist: metadata.artist.clone(),
+            duration: metadata.duration,
+            source_url: metadata.source_url.clone(),
+        })
+    }
+
+    /// Whatever a user typed in.
+    pub fn search(text: &str) -> Self {
+        Self {
+            title: text.trim().to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// One source of lyrics, e.g. tags of local files or an online database.
+#[async_trait]
+pub trait LyricsProvider: Send + Sync {
+    /// Shown below the lyrics and in logs.
+    fn name(&self) -> &'static str;
+
+    /// The lyrics of `query`, `None` if this provider doesn't have any.
+    async fn lyrics(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, AppError>;
+}
+
+/// All providers, asked in order until one of them has synced lyrics.
+pub struct LyricsProviders(Vec<Box<dyn LyricsProvider>>);
+
+/// The lyrics found for a track, kept in its typemap so they are only looked up once.
+struct TrackLyrics;
+
+impl songbird::typemap::TypeMapKey for TrackLyrics {
+    type Value = Option<Arc<Lyrics>>;
+}
+
+impl LyricsProviders {
+    pub fn new(providers: Vec<Box<dyn LyricsProvider>>) -> Self {
+        Self(providers)
+    }
+
+    /// The providers enabled in `config`, files from `library` come first.
+    pub fn from_config(config: &Config, library: Option<Arc<Library>>) -> Self {
+        let mut providers: Vec<Box<dyn LyricsProvider>> = vec![];
+
+        if let Some(library) = library {
+            providers.push(Box::new(local::LocalLyrics::new(library)));
+        }
+        if config.lyrics.subtitles {
+            providers.push(Box::new(subtitles::SubtitleLyrics::new(
+                config.ytdl.clone(),
+            )));
+        }
+        if let Some(url) = &config.lyrics.lrclib_url {
+            providers.push(Box::new(lrclib::Lrclib::new(url)));
+        }
+
+        Self::new(providers)
+    }
+
+    /// The first synced lyrics any provider has, or else the first plain ones.
+    pub async fn find(&self, query: &LyricsQuery) -> Option<Lyrics> {
+        let mut plain = None;
+
+        for provider in &self.0 {
+            debug!(
+                title = query.title,
+                provider = provider.name(),
+                "Looking up lyrics"
+            );
+            match provider.lyrics(query).await {
+                Ok(Some(lyrics)) if lyrics.is_synced() => return Some(lyrics),
+                Ok(Some(lyrics)) => plain = plain.or(Some(lyrics)),
+                Ok(None) => {}
+                Err(err) => warn!(
+                    provider = provider.name(),
+                    "Could not look up lyrics: {err}"
+                ),
+            }
+        }
+
+        plain
+    }
+
+    /// The lyrics of `track`, looked up the first time they are needed.
+    pub async fn of_track(&self, track: &TrackHandle) -> Option<Arc<Lyrics>> {
+        if let Some(lyrics) = track.typemap().read().await.get::<TrackLyrics>() {
+            return lyrics.clone();
+        }
+
+        let lyrics = match LyricsQuery::of(track.metadata()) {
+            Some(query) => self.find(&query).await.map(Arc::new),
+            None => None,
+        };
+        track
+            .typemap()
+            .write()
+            .await
+            .insert::<TrackLyrics>(lyrics.clone());
+
+        lyrics
+    }
+}
+
+impl fmt::Debug for LyricsProviders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|provider| provider.name()))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LyricsLine {
+    /// When the line is sung, only known for synced lyrics
+    pub time: Option<Duration>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Lyrics {
+    /// The provider that found them
+    pub source: &'static str,
+    pub lines: Vec<LyricsLine>,
+}
+
+impl Lyrics {
+    /// Parses LRC (`[01:23.45]A line`) into synced lyrics, anything else is taken as plain
+    /// text. `None` if there is no text at all.
+    pub fn parse(source: &'static str, text: &str) -> Option<Self> {
+        let synced = text.lines().flat_map(parse_lrc_line).collect::<Vec<_>>();
+
+        let lines = match synced.is_empty() {
+            true => text
+                .lines()
+                .map(|line| LyricsLine {
+                    time: None,
+                    text: line.trim().to_string(),
+                })
+                .collect(),
+            false => {
+                let offset = lrc_offset(text);
+                let mut lines = synced
+                    .into_iter()
+                    .map(|line| LyricsLine {
+                        time: line.time.map(|time| shift(time, offset)),
+                        ..line
+                    })
+                    .collect::<Vec<_>>();
+                // lines sung several times are written once with all their times
+                lines.sort_by_key(|line| line.time);
+                lines
+            }
+        };
+
+        Self::new(source, lines)
+    }
+
+    /// Parses WebVTT subtitles, every cue becomes a line at its start.
+    pub fn from_vtt(source: &'static str, vtt: &str) -> Option<Self> {
+        let mut lines: Vec<LyricsLine> = vec![];
+        let mut cues = vtt.lines().map(str::trim);
+
+        while let Some(line) = cues.next() {
+            let Some((start, _)) = line.split_once("-->") else {
+                continue;
+            };
+            let Some(time) = parse_timestamp(start.trim()) else {
+                continue;
+            };
+
+            let text = cues
+                .by_ref()
+                .take_while(|line| !line.is_empty())
+                .map(strip_tags)
+                .collect::<Vec<_>>()
+                .join(" ");
+            // captions that scroll repeat the previous line
+            if lines.last().is_some_and(|last| last.text == text) {
+                continue;
+            }
+
+            lines.push(LyricsLine {
+                time: Some(time),
+                text,
+            });
+        }
+
+        Self::new(source, lines)
+    }
+
+    fn new(source: &'static str, mut lines: Vec<LyricsLine>) -> Option<Self> {
+        while lines.last().is_some_and(|line| line.text.is_empty()) {
+            lines.pop();
+        }
+        let start = lines.iter().position(|line| !line.text.is_empty())?;
+        lines.drain(..start);
+
+        Some(Self { source, lines })
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.lines.iter().all(|line| line.time.is_some())
+    }
+
+    /// The line being sung `position` into the track, if the lyrics are synced and it started.
+    pub fn line_at(&self, position: Duration) -> Option<usize> {
+        if !self.is_synced() {
+            return None;
+        }
+
+        self.lines
+            .iter()
+            .rposition(|line| line.time.is_some_and(|time| time <= position))
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.lines.len().div_ceil(LINES_PER_PAGE)
+    }
+
+    /// The page `line` is on.
+    pub fn page_of(line: usize) -> usize {
+        line / LINES_PER_PAGE
+    }
+
+    /// The lines of page `page` as markdown, with the line `highlight` in bold.
+    pub fn page(&self, page: usize, highlight: Option<usize>) -> String {
+        let first = page * LINES_PER_PAGE;
+
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(LINES_PER_PAGE)
+            .map(
+                |(i, line)| match (line.text.as_str(), Some(i) == highlight) {
+                    ("", true) => "▶ ♪".to_string(),
+                    ("", false) => "♪".to_string(),
+                    (text, true) => format!("▶ **{text}**"),
+                    (text, false) => text.to_string(),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// A line like `[00:12.34][01:02.03]Text`, one [`LyricsLine`] per time. Metadata tags like
+/// `[ar:Artist]` have no time and are skipped.
+fn parse_lrc_line(line: &str) -> Vec<LyricsLine> {
+    let mut rest = line.trim();
+    let mut times = vec![];
+
+    while let Some((tag, after)) = rest.strip_prefix('[').and_then(|tag| tag.split_once(']')) {
+        match parse_timestamp(tag) {
+            Some(time) => times.push(time),
+            None => break,
+        }
+        rest = after;
+    }
+
+    let text = strip_tags(rest);
+    times
+        .into_iter()
+        .map(|time| LyricsLine {
+            time: Some(time),
+            text: text.clone(),
+        })
+        .collect()
+}
+
+/// `[offset:+500]` shifts every line, positive values make them show up earlier.
+fn lrc_offset(text: &str) -> i64 {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("[offset:")?.strip_suffix(']'))
+        .find_map(|offset| offset.trim().trim_start_matches('+').parse().ok())
+        .unwrap_or(0)
+}
+
+fn shift(time: Duration, offset_ms: i64) -> Duration {
+    let millis = time.as_millis() as i64 - offset_ms;
+
+    Duration::from_millis(millis.max(0) as u64)
+}
+
+/// `mm:ss`, `mm:ss.xx` or `hh:mm:ss.xxx`, as used by LRC and WebVTT.
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let (rest, fraction) = match timestamp.split_once(['.', ',']) {
+        Some((rest, fraction)) => (rest, fraction),
+        None => (timestamp, "0"),
+    };
+    if fraction.is_empty() || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut secs = 0;
+    let parts = rest.split(':').collect::<Vec<_>>();
+    if !(2..=3).contains(&parts.len()) {
+        return None;
+    }
+    for part in parts {
+        secs = secs * 60 + part.parse::<u64>().ok()?;
+    }
+    // `.5` is half a second, `.05` five hundredths
+    let millis = format!("{fraction:0<3}")[..3].parse::<u64>().ok()?;
+
+    Some(Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+/// Removes `<...>` tags, like word timings of enhanced LRC or styling in WebVTT.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    stripped.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(millis: u64, text: &str) -> LyricsLine {
+        LyricsLine {
+            time: Some(Duration::from_millis(millis)),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn lrc_lines_are_sorted_by_time() {
+        let lyrics = Lyrics::parse(
+            "test",
+            "[ar:Someone]\n[offset:+100]\n[00:01.50]First\n[00:03.00][00:10.00]<00:03.00>Chorus\n[00:05.2]\n[00:06.00]Last",
+        )
+        .unwrap();
+
+        assert!(lyrics.is_synced());
+        assert_eq!(
+            lyrics.lines,
+            [
+                line(1400, "First"),
+                line(2900, "Chorus"),
+                line(5100, ""),
+                line(5900, "Last"),
+                line(9900, "Chorus"),
+            ]
+        );
+        assert_eq!(lyrics.line_at(Duration::from_secs(1)), None);
+        assert_eq!(lyrics.line_at(Duration::from_secs(3)), Some(1));
+        assert_eq!(lyrics.line_at(Duration::from_secs(60)), Some(4));
+    }
+
+    #[test]
+    fn plain_text_is_not_synced() {
+        let lyrics = Lyrics::parse("test", "\nOne\n\nTwo\n\n").unwrap();
+
+        assert!(!lyrics.is_synced());
+        assert_eq!(lyrics.lines.len(), 3);
+        assert_eq!(lyrics.line_at(Duration::from_secs(10)), None);
+        assert!(Lyrics::parse("test", " \n\n").is_none());
+    }
+
+    #[test]
+    fn vtt_cues_become_lines() {
+        let vtt = "WEBVTT\nKind: captions\n\n1\n00:00:01.000 --> 00:00:03.000\n<c>Hello</c>\nthere\n\n00:04.500 --> 00:05.000 align:start\nHello there\n\n00:06.000 --> 00:07.000\nBye\n";
+        let lyrics = Lyrics::from_vtt("test", vtt).unwrap();
+
+        assert_eq!(lyrics.lines, [line(1000, "Hello there"), line(6000, "Bye")]);
+    }
+
+    #[test]
+    fn pages_highlight_the_current_line() {
+        let text = (0..20)
+            .map(|i| format!("[00:{i:02}.00]Line {i}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let lyrics = Lyrics::parse("test", &text).unwrap();
+
+        let current = lyrics.line_at(Duration::from_millis(16_500)).unwrap();
+        assert_eq!(lyrics.page_count(), 2);
+        assert_eq!(Lyrics::page_of(current), 1);
+        assert_eq!(
+            lyrics.page(1, Some(current)),
+            "Line 15\n▶ **Line 16**\nLine 17\nLine 18\nLine 19"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use poise::async_trait;
+use serde_json::Value;
+
+use crate::{client::sources::ytdl::ytdl_json, config::YtdlConfig, error::AppError};
+
+use super::{Lyrics, LyricsProvider, LyricsQuery};
+
+/// Subtitles uploaded together with a video, e.g. lyric videos on YouTube. They are always
+/// synced. Captions generated by YouTube are not used, they are rarely any good for songs.
+#[derive(Debug)]
+pub struct SubtitleLyrics {
+    config: YtdlConfig,
+    client: reqwest::Client,
+}
+
+impl SubtitleLyrics {
+    pub fn new(config: YtdlConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl LyricsProvider for SubtitleLyrics {
+    fn name(&self) -> &'static str {
+        "subtitles"
+    }
+
+    async fn lyrics(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, AppError> {
+        let Some(url) = query
+            .source_url
+            .as_deref()
+            .filter(|url| url.starts_with("http"))
+        else {
+            return Ok(None);
+        };
+
+        let output = ytdl_json(&self.config, url).await?;
+        let Some(subtitle_url) = subtitle_url(&output) else {
+            return Ok(None);
+        };
+
+        let vtt = self
+            .client
+            .get(subtitle_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| AppError::internal(format!("Could not download the subtitles: {err}")))?
+            .text()
+            .await
+            .map_err(|err| AppError::internal(format!("Could not read the subtitles: {err}")))?;
+
+        Ok(Lyrics::from_vtt(self.name(), &vtt))
+    }
+}
+
+/// The WebVTT subtitles in what yt-dlp printed, preferring the language of the video and then
+/// english.
+fn subtitle_url(output: &Value) -> Option<&str> {
+    let subtitles = output.get("subtitles")?.as_object()?;
+    let mut languages = subtitles
+        .keys()
+        .filter(|language| *language != "live_chat")
+        .collect::<Vec<_>>();
+    languages.sort();
+
+    let video_language = output.get("language").and_then(Value::as_str);
+    let language = video_language
+        .and_then(|video| {
+            languages
+                .iter()
+                .find(|language| language.starts_with(video))
+        })
+        .or_else(|| languages.iter().find(|language| language.starts_with("en")))
+        .or(languages.first())?;
+
+    subtitles[language.as_str()]
+        .as_array()?
+        .iter()
+        .find(|format| format.get("ext").and_then(Value::as_str) == Some("vtt"))?
+        .get("url")?
+        .as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn the_language_of_the_video_is_preferred() {
+        let format =
+            |url: &str| json!([{ "ext": "srv1", "url": "wrong" }, { "ext": "vtt", "url": url }]);
+        let output = json!({
+            "language": "de",
+            "subtitles": { "en": format("en"), "de-DE": format("de"), "live_chat": format("chat") },
+        });
+        assert_eq!(subtitle_url(&output), Some("de"));
+
+        let output = json!({ "subtitles": { "fr": format("fr"), "en-US": format("en") } });
+        assert_eq!(subtitle_url(&output), Some("en"));
+
+        let output = json!({ "subtitles": { "live_chat": format("chat") } });
+        assert_eq!(subtitle_url(&output), None);
+    }
+}
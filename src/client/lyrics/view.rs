@@ -0,0 +1,213 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use poise::serenity_prelude::{
+    ButtonStyle, CollectComponentInteraction, CreateComponents, CreateEmbed,
+    InteractionResponseType,
+};
+use songbird::tracks::TrackHandle;
+
+use crate::client::{bot::Context, commands::CmdRes, embed_ext::CreateEmbedExt};
+
+use super::Lyrics;
+
+/// How long the buttons work, discord only lets oxo edit its reply for 15 minutes.
+const TIMEOUT: Duration = Duration::from_secs(14 * 60);
+/// How often the highlighted line moves on while following the track.
+const REFRESH: Duration = Duration::from_secs(3);
+/// Discord allows at most 256 characters in embed titles.
+const MAX_TITLE_LEN: usize = 200;
+
+/// The lyrics as shown by `/lyrics`, one page at a time.
+///
+/// Synced lyrics of the current track highlight the line being sung and turn the pages on
+/// their own, until someone turns a page themselves.
+pub struct LyricsView {
+    title: String,
+    lyrics: Arc<Lyrics>,
+    /// The track the lyrics belong to, if it is playing
+    track: Option<TrackHandle>,
+    page: usize,
+    highlight: Option<usize>,
+    following: bool,
+    /// The custom ids of the previous, follow and next buttons
+    buttons: [String; 3],
+    /// Whether the buttons still work
+    active: bool,
+}
+
+impl LyricsView {
+    pub fn new(
+        ctx: Context<'_>,
+        title: &str,
+        lyrics: Arc<Lyrics>,
+        track: Option<TrackHandle>,
+    ) -> Self {
+        let title = match title.chars().count() > MAX_TITLE_LEN {
+            true => title.chars().take(MAX_TITLE_LEN - 1).chain(['…']).collect(),
+            false => title.to_string(),
+        };
+
+        Self {
+            title,
+            following: track.is_some() && lyrics.is_synced(),
+            track,
+            lyrics,
+            page: 0,
+            highlight: None,
+            buttons: ["previous", "follow", "next"]
+                .map(|button| format!("{}lyrics-{button}", ctx.id())),
+            active: true,
+        }
+    }
+
+    /// Sends the view and keeps it up to date until the buttons time out.
+    pub async fn show(mut self, ctx: Context<'_>) -> CmdRes {
+        self.follow().await;
+        let reply = ctx
+            .send(|create| {
+                create
+                    .embed(|e| self.embed(e))
+                    .components(|c| self.components(c))
+            })
+            .await?;
+
+        let deadline = Instant::now() + TIMEOUT;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let prefix = format!("{}lyrics-", ctx.id());
+            let press = CollectComponentInteraction::new(ctx)
+                .filter(move |press| press.data.custom_id.starts_with(&prefix))
+                .timeout(match self.following {
+                    true => REFRESH.min(remaining),
+                    false => remaining,
+                })
+                .await;
+
+            match press {
+                Some(press) => {
+                    self.press(&press.data.custom_id).await;
+                    press
+                        .create_interaction_response(ctx, |response| {
+                            response
+                                .kind(InteractionResponseType::UpdateMessage)
+                                .interaction_response_data(|data| {
+                                    data.embed(|e| self.embed(e))
+                                        .components(|c| self.components(c))
+                                })
+                        })
+                        .await?;
+                }
+                None if self.following => {
+                    let shown = (self.page, self.highlight, self.following);
+                    self.follow().await;
+                    if shown != (self.page, self.highlight, self.following) {
+                        reply
+                            .edit(ctx, |edit| {
+                                edit.embed(|e| self.embed(e))
+                                    .components(|c| self.components(c))
+                            })
+                            .await?;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        self.following = false;
+        self.active = false;
+        reply
+            .edit(ctx, |edit| {
+                edit.embed(|e| self.embed(e))
+                    .components(|c| self.components(c))
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn press(&mut self, button: &str) {
+        let last_page = self.lyrics.page_count().saturating_sub(1);
+
+        if button == self.buttons[0] {
+            self.following = false;
+            self.page = self.page.saturating_sub(1);
+        } else if button == self.buttons[2] {
+            self.following = false;
+            self.page = (self.page + 1).min(last_page);
+        } else if button == self.buttons[1] {
+            self.following = true;
+            self.follow().await;
+        }
+    }
+
+    /// Highlights the line being sung right now and turns to its page, stops following once
+    /// the track ended.
+    async fn follow(&mut self) {
+        if !self.following {
+            return;
+        }
+
+        // fails once the track ended
+        let position = match &self.track {
+            Some(track) => track.get_info().await.ok().map(|info| info.position),
+            None => None,
+        };
+        match position {
+            Some(position) => {
+                self.highlight = self.lyrics.line_at(position);
+                self.page = Lyrics::page_of(self.highlight.unwrap_or_default());
+            }
+            None => {
+                self.following = false;
+                self.highlight = None;
+            }
+        }
+    }
+
+    fn embed<'a>(&self, e: &'a mut CreateEmbed) -> &'a mut CreateEmbed {
+        let footer = format!(
+            "Page {}/{} · Lyrics from {}",
+            self.page + 1,
+            self.lyrics.page_count(),
+            self.lyrics.source
+        );
+
+        e.normal_styling()
+            .title(&self.title)
+            .description(self.lyrics.page(self.page, self.highlight))
+            .footer(|f| f.text(footer))
+    }
+
+    fn components<'a>(&self, c: &'a mut CreateComponents) -> &'a mut CreateComponents {
+        let can_follow = self.track.is_some() && self.lyrics.is_synced();
+        let page_count = self.lyrics.page_count();
+        if !self.active || (page_count <= 1 && !can_follow) {
+            return c;
+        }
+
+        c.create_action_row(|row| {
+            row.create_button(|b| {
+                b.custom_id(&self.buttons[0])
+                    .emoji('◀')
+                    .style(ButtonStyle::Secondary)
+                    .disabled(self.page == 0)
+            });
+            if can_follow {
+                row.create_button(|b| {
+                    b.custom_id(&self.buttons[1])
+                        .label("Follow")
+                        .style(ButtonStyle::Primary)
+                        .disabled(self.following)
+                });
+            }
+            row.create_button(|b| {
+                b.custom_id(&self.buttons[2])
+                    .emoji('▶')
+                    .style(ButtonStyle::Secondary)
+                    .disabled(self.page + 1 >= page_count)
+            })
+        })
+    }
+}
@@ -1,4 +1,23 @@
+pub mod autoplay;
 pub mod bot;
+pub mod chapters;
 pub mod commands;
+pub mod crossfade;
 pub mod embed_ext;
 pub mod events;
+pub mod feed;
+pub mod limits;
+pub mod lyrics;
+pub mod notifications;
+pub mod player;
+pub mod queue_ext;
+pub mod queues;
+pub mod reconnect;
+pub mod registration;
+pub mod resume;
+pub mod segments;
+pub mod settings;
+pub mod sources;
+pub mod spans;
+pub mod stage;
+pub mod voice;
@@ -0,0 +1,526 @@
+//! Everything oxo posts on its own, without anyone asking: what is playing now, tracks that
+//! could not be played, reconnects, leaving once the queue ran out.
+//!
+//! Guilds pick where these go (`announce_channel`), whether they make a sound, whether they
+//! replace each other and how they are worded, see [`NotificationSettings`].
+
+use std::collections::HashMap;
+
+use poise::serenity_prelude::{ChannelId, CreateEmbed, GuildId, MessageFlags, MessageId, Mutex};
+use serde::{Deserialize, Serialize};
+use songbird::tracks::TrackHandle;
+use tracing::{debug, warn};
+
+use crate::{
+    client::{bot::State, chapters, embed_ext::CreateEmbedExt},
+    error::AppError,
+};
+
+/// Discord allows at most 2000 characters per message, placeholders need some room.
+const MAX_TEMPLATE_LEN: usize = 1000;
+
+/// Something oxo tells a guild about.
+#[derive(
+    Debug, poise::ChoiceParameter, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Notice {
+    /// Posted above the embed of a track that started
+    #[name = "now_playing"]
+    NowPlaying,
+    #[name = "track_finished"]
+    TrackFinished,
+    #[name = "track_failed"]
+    TrackFailed,
+    #[name = "autoplay"]
+    Autoplay,
+    #[name = "queue_ended"]
+    QueueEnded,
+    #[name = "disconnected"]
+    Disconnected,
+    #[name = "reconnected"]
+    Reconnected,
+    /// Reconnected while a track was playing
+    #[name = "continued"]
+    Continued,
+    #[name = "reconnect_failed"]
+    ReconnectFailed,
+    /// The queue was picked up again after a restart
+    #[name = "resumed"]
+    Resumed,
+}
+
+impl Notice {
+    pub const ALL: [Self; 10] = [
+        Self::NowPlaying,
+        Self::TrackFinished,
+        Self::TrackFailed,
+        Self::Autoplay,
+        Self::QueueEnded,
+        Self::Disconnected,
+        Self::Reconnected,
+        Self::Continued,
+        Self::ReconnectFailed,
+        Self::Resumed,
+    ];
+
+    /// What can be used in the template of this notice, as `{name}`.
+    pub fn placeholders(self) -> &'static [&'static str] {
+        match self {
+            Self::NowPlaying | Self::TrackFinished | Self::Autoplay => &["title"],
+            Self::TrackFailed => &["title", "reason"],
+            Self::Disconnected => &["channel", "reason"],
+            Self::Continued => &["position"],
+            Self::ReconnectFailed => &["channel"],
+            Self::QueueEnded | Self::Reconnected | Self::Resumed => &[],
+        }
+    }
+
+    fn default_template(self, personality: Personality) -> &'static str {
+        use Personality::*;
+
+        match (self, personality) {
+            (Self::NowPlaying, Playful) => "Now playing uwu",
+            (Self::NowPlaying, Plain) => "Now playing",
+            (Self::TrackFinished, Playful) => "Finished playing `{title}` uwu",
+            (Self::TrackFinished, Plain) => "Finished playing `{title}`",
+            (Self::TrackFailed, _) => "Could not play `{title}`, skipping it: {reason}",
+            (Self::Autoplay, _) => "Autoplay picked `{title}` next",
+            (Self::QueueEnded, Playful) => "No more songs to play OwO, guess I'll go...",
+            (Self::QueueEnded, Plain) => "The queue is empty, leaving the channel",
+            (Self::Disconnected, Playful) => {
+                "Lost the connection to {channel} ({reason}), trying to get back..."
+            }
+            (Self::Disconnected, Plain) => {
+                "Lost the connection to {channel} ({reason}), reconnecting"
+            }
+            (Self::Reconnected, Playful) => "I'm back!",
+            (Self::Reconnected, Plain) => "Reconnected",
+            (Self::Continued, Playful) => "I'm back! Continuing at {position}",
+            (Self::Continued, Plain) => "Reconnected, continuing at {position}",
+            (Self::ReconnectFailed, _) => "Could not get back into {channel}, `/join` to try again",
+            (Self::Resumed, Playful) => "I'm back! Picking up where we left off",
+            (Self::Resumed, Plain) => "Restarted, continuing the queue",
+        }
+    }
+}
+
+/// Whether notifications are posted, and whether they make a sound.
+#[derive(
+    Debug, poise::ChoiceParameter, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationMode {
+    #[default]
+    All,
+    /// Posted without pinging anyone's phone, like discord's `@silent`
+    Silent,
+    Off,
+}
+
+/// What happens to the previous notification when a new one is posted in the same channel.
+#[derive(
+    Debug, poise::ChoiceParameter, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviousNotification {
+    #[default]
+    Keep,
+    Delete,
+    /// The previous notification is edited into the new one
+    Replace,
+}
+
+/// The default wording of notifications.
+#[derive(
+    Debug, poise::ChoiceParameter, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Personality {
+    /// uwu
+    #[default]
+    Playful,
+    Plain,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub mode: NotificationMode,
+    /// Post an embed whenever a track starts
+    pub now_playing: bool,
+    pub previous: PreviousNotification,
+    pub personality: Personality,
+    /// Replace the wording of the personality, an empty template posts nothing
+    pub templates: HashMap<Notice, String>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            mode: NotificationMode::All,
+            now_playing: true,
+            previous: PreviousNotification::Keep,
+            personality: Personality::Playful,
+            templates: HashMap::new(),
+        }
+    }
+}
+
+impl NotificationSettings {
+    /// The template of `notice`, the guild's own or the one of its personality.
+    pub fn template(&self, notice: Notice) -> &str {
+        match self.templates.get(&notice) {
+            Some(template) => template,
+            None => notice.default_template(self.personality),
+        }
+    }
+
+    /// `notice` as it is posted, with `values` filled in for its placeholders.
+    pub fn render(&self, notice: Notice, values: &[(&str, &str)]) -> String {
+        let mut rendered = String::new();
+        let mut rest = self.template(notice);
+        while let Some((before, after)) = rest.split_once('{') {
+            rendered += before;
+            let value = after.split_once('}').and_then(|(name, after)| {
+                let (_, value) = values
+                    .iter()
+                    .find(|(placeholder, _)| *placeholder == name)?;
+                Some((value, after))
+            });
+            match value {
+                Some((value, after)) => {
+                    rendered += value;
+                    rest = after;
+                }
+                None => {
+                    rendered.push('{');
+                    rest = after;
+                }
+            }
+        }
+
+        rendered + rest
+    }
+
+    /// Replaces the template of `notice`, `None` goes back to the one of the personality.
+    pub fn set_template(
+        &mut self,
+        notice: Notice,
+        template: Option<String>,
+    ) -> Result<(), AppError> {
+        match template {
+            Some(template) => {
+                check_template(notice, &template)?;
+                self.templates.insert(notice, template);
+            }
+            None => {
+                self.templates.remove(&notice);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        for (notice, template) in &self.templates {
+            check_template(*notice, template)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_template(notice: Notice, template: &str) -> Result<(), AppError> {
+    if template.chars().count() > MAX_TEMPLATE_LEN {
+        return Err(AppError::invalid_input(format!(
+            "The template of {notice} can be at most {MAX_TEMPLATE_LEN} characters long"
+        )));
+    }
+
+    let mut rest = template;
+    while let Some((_, after)) = rest.split_once('{') {
+        let Some((name, after)) = after.split_once('}') else {
+            break;
+        };
+        if !notice.placeholders().contains(&name) {
+            let allowed = match notice.placeholders() {
+                [] => "none".to_string(),
+                placeholders => placeholders
+                    .iter()
+                    .map(|name| format!("`{{{name}}}`"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            return Err(AppError::invalid_input(format!(
+                "`{{{name}}}` can't be used for {notice}, its placeholders are: {allowed}"
+            )));
+        }
+        rest = after;
+    }
+
+    Ok(())
+}
+
+/// Set on tracks whose now-playing embed was posted, so pausing and resuming doesn't post it
+/// again. Holds the message once it was sent, to replace that one when the track ends.
+struct Announced;
+
+impl songbird::typemap::TypeMapKey for Announced {
+    type Value = Option<Posted>;
+}
+
+/// A notification that is about to be sent.
+struct Draft {
+    content: String,
+    embed: Option<CreateEmbed>,
+}
+
+/// A notification that was sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posted {
+    channel_id: ChannelId,
+    message_id: MessageId,
+}
+
+/// Posts notifications and remembers the last one of every guild, to replace it.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    last: Mutex<HashMap<GuildId, Posted>>,
+}
+
+impl Notifier {
+    /// Posts `notice` in `channel_id`, with `values` for its placeholders.
+    pub async fn notify(
+        &self,
+        state: &State,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        notice: Notice,
+        values: &[(&str, &str)],
+    ) {
+        let settings = state.settings.get(guild_id).await.notifications;
+        let content = settings.render(notice, values);
+
+        self.post(state, guild_id, channel_id, &settings, content, None)
+            .await;
+    }
+
+    /// Posts `notice` about `track`, which ended. If `track` was announced, only its own
+    /// now-playing embed is replaced or deleted: with crossfading, the next track was announced
+    /// already.
+    pub async fn track_ended(
+        &self,
+        state: &State,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        track: &TrackHandle,
+        notice: Notice,
+        values: &[(&str, &str)],
+    ) {
+        let settings = state.settings.get(guild_id).await.notifications;
+        let content = settings.render(notice, values);
+        // tracks that weren't announced replace whatever was posted last, like other notices
+        let announced = track.typemap().read().await.get::<Announced>().copied();
+        let previous = match announced {
+            Some(announced) => announced,
+            None => self.last.lock().await.get(&guild_id).copied(),
+        };
+
+        let draft = Draft {
+            content,
+            embed: None,
+        };
+        self.send(state, guild_id, channel_id, &settings, draft, previous)
+            .await;
+    }
+
+    /// Posts the embed of `track`, which just started. Every track is only announced once.
+    pub async fn now_playing(
+        &self,
+        state: &State,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        track: &TrackHandle,
+    ) {
+        let settings = state.settings.get(guild_id).await.notifications;
+        if !settings.now_playing || settings.mode == NotificationMode::Off || channel_id.is_none() {
+            return;
+        }
+        {
+            let mut typemap = track.typemap().write().await;
+            if typemap.contains_key::<Announced>() {
+                return;
+            }
+            typemap.insert::<Announced>(None);
+        }
+
+        // fails once the track ended already
+        let Ok(track_state) = track.get_info().await else {
+            return;
+        };
+        let chapters = chapters::of_track(&state.resolvers, track).await;
+        let mut embed = CreateEmbed::default();
+        embed.song_embed(track.metadata(), &track_state, &chapters);
+
+        let content = settings.render(Notice::NowPlaying, &[]);
+        let posted = self
+            .post(state, guild_id, channel_id, &settings, content, Some(embed))
+            .await;
+        if posted.is_some() {
+            track.typemap().write().await.insert::<Announced>(posted);
+        }
+    }
+
+    /// Posts `content` and `embed` in `channel_id`, the way `settings` say. Nothing is posted
+    /// if there is neither or notifications are off.
+    pub async fn post(
+        &self,
+        state: &State,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        settings: &NotificationSettings,
+        content: String,
+        embed: Option<CreateEmbed>,
+    ) -> Option<Posted> {
+        let previous = self.last.lock().await.get(&guild_id).copied();
+        let draft = Draft { content, embed };
+
+        self.send(state, guild_id, channel_id, settings, draft, previous)
+            .await
+    }
+
+    /// Sends a notification, replacing or deleting `previous` if it is in the same channel.
+    async fn send(
+        &self,
+        state: &State,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        settings: &NotificationSettings,
+        Draft { content, embed }: Draft,
+        previous: Option<Posted>,
+    ) -> Option<Posted> {
+        let channel_id = channel_id?;
+        if settings.mode == NotificationMode::Off || (content.is_empty() && embed.is_none()) {
+            return None;
+        }
+        let http = state.http().await?;
+
+        let flags = match settings.mode {
+            NotificationMode::Silent => MessageFlags::SUPPRESS_NOTIFICATIONS,
+            _ => MessageFlags::empty(),
+        };
+        let previous = previous
+            .filter(|previous| previous.channel_id == channel_id)
+            .map(|previous| previous.message_id);
+
+        match (settings.previous, previous) {
+            (PreviousNotification::Replace, Some(message_id)) => {
+                let edited = channel_id
+                    .edit_message(&http, message_id, |edit| {
+                        // templates are editable, titles and errors mention whoever they want
+                        edit.content(&content)
+                            .set_embeds(embed.clone().into_iter().collect())
+                            .allowed_mentions(|mentions| mentions.empty_parse())
+                    })
+                    .await;
+                match edited {
+                    Ok(message) => {
+                        return Some(Posted {
+                            channel_id,
+                            message_id: message.id,
+                        })
+                    }
+                    // somebody deleted it, a new one is posted instead
+                    Err(err) => debug!("Could not replace the previous notification: {err}"),
+                }
+            }
+            (PreviousNotification::Delete, Some(message_id)) => {
+                if let Err(err) = channel_id.delete_message(&http, message_id).await {
+                    debug!("Could not delete the previous notification: {err}");
+                }
+            }
+            _ => {}
+        }
+
+        let sent = channel_id
+            .send_message(&http, |message| {
+                message
+                    .content(&content)
+                    .flags(flags)
+                    .allowed_mentions(|mentions| mentions.empty_parse());
+                if let Some(embed) = embed {
+                    message.set_embed(embed);
+                }
+                message
+            })
+            .await;
+
+        match sent {
+            Ok(message) => {
+                let posted = Posted {
+                    channel_id,
+                    message_id: message.id,
+                };
+                self.last.lock().await.insert(guild_id, posted);
+
+                Some(posted)
+            }
+            Err(err) => {
+                warn!(%channel_id, "Could not send a notification: {err}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guild_templates_replace_the_personality() {
+        let mut settings = NotificationSettings::default();
+        let values = [("title", "Never Gonna Give You Up")];
+
+        assert_eq!(
+            settings.render(Notice::TrackFinished, &values),
+            "Finished playing `Never Gonna Give You Up` uwu"
+        );
+
+        settings.personality = Personality::Plain;
+        assert_eq!(
+            settings.render(Notice::TrackFinished, &values),
+            "Finished playing `Never Gonna Give You Up`"
+        );
+
+        settings
+            .templates
+            .insert(Notice::TrackFinished, "{title} is over".into());
+        assert_eq!(
+            settings.render(Notice::TrackFinished, &values),
+            "Never Gonna Give You Up is over"
+        );
+        assert_eq!(
+            settings.render(Notice::TrackFinished, &[("title", "{title}")]),
+            "{title} is over"
+        );
+    }
+
+    #[test]
+    fn every_default_template_only_uses_its_placeholders() {
+        for notice in Notice::ALL {
+            for personality in [Personality::Playful, Personality::Plain] {
+                check_template(notice, notice.default_template(personality)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert!(check_template(Notice::TrackFinished, "{title} ended").is_ok());
+        assert!(check_template(Notice::TrackFinished, "").is_ok());
+        assert!(check_template(Notice::TrackFinished, "{reason}").is_err());
+        assert!(check_template(Notice::Resumed, "{title}").is_err());
+    }
+}
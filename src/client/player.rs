@@ -0,0 +1,431 @@
+use std::sync::Arc;
+
+use poise::serenity_prelude::{ChannelId, GuildChannel, GuildId, Http, Mutex, UserId};
+use songbird::{
+    tracks::{TrackHandle, TrackQueue},
+    Call, Event, TrackEvent,
+};
+use tracing::warn;
+
+use crate::{
+    client::{
+        bot::{LoopMode, State},
+        crossfade,
+        events::{EndEventHandler, PlayEventHandler},
+        notifications::Notice,
+        queue_ext::{discard, place_fairly, RequestChannel, Requester},
+        queues::GuildQueue,
+        settings::GuildSettings,
+        sources::lazy::QueueEntry,
+        spans::start_track_span,
+        stage,
+    },
+    error::{AppError, Error},
+};
+
+/// Who asked for a track and where. Tracks from the api have neither.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Request {
+    pub requester: Option<UserId>,
+    pub channel_id: Option<ChannelId>,
+}
+
+impl Request {
+    pub fn new(requester: UserId, channel_id: ChannelId) -> Self {
+        Self {
+            requester: Some(requester),
+            channel_id: Some(channel_id),
+        }
+    }
+
+    /// The request `track` was queued for.
+    pub async fn of(track: &TrackHandle) -> Self {
+        let typemap = track.typemap().read().await;
+
+        Self {
+            requester: typemap.get::<Requester>().copied(),
+            channel_id: typemap.get::<RequestChannel>().copied(),
+        }
+    }
+}
+
+/// Plays the queue of one guild in its call.
+///
+/// Everything that queues tracks goes through here (commands, the api, resuming, looping and
+/// autoplay), so every track gets its volume, request info, span and end-event the same way.
+#[derive(Clone)]
+pub struct GuildPlayer {
+    state: Arc<State>,
+    guild_id: GuildId,
+    queue: Arc<GuildQueue>,
+    call: Arc<Mutex<Call>>,
+}
+
+impl GuildPlayer {
+    /// A player for `call`, which does not have to be connected.
+    pub fn new(state: Arc<State>, guild_id: GuildId, call: Arc<Mutex<Call>>) -> Self {
+        Self {
+            queue: state.queues.get_or_create(guild_id),
+            state,
+            guild_id,
+            call,
+        }
+    }
+
+    /// Joins `channel_id`, moving over if oxo is in another channel of the guild. A queue that
+    /// was paused by leaving plays again.
+    pub async fn join(
+        state: &Arc<State>,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<Self, Error> {
+        let was_connected = match state.songbird_instance.get(guild_id) {
+            Some(call) => call.lock().await.current_channel().is_some(),
+            None => false,
+        };
+        // moving keeps the call and with it the queue
+        let (call, joined) = state.songbird_instance.join(guild_id, channel_id).await;
+        joined?;
+
+        let player = Self::new(state.clone(), guild_id, call);
+        if !was_connected {
+            // picks up where `/leave` left off
+            if let Err(err) = player.queue.resume() {
+                warn!("Could not resume the queue: {err}");
+            }
+        }
+        state.reconnects.watch(&player).await;
+        player.take_the_stage(channel_id).await;
+        // api requests for the guild should come here right away, not after the next heartbeat
+        if let Some(cluster) = &state.cluster {
+            cluster.announce(state).await;
+        }
+
+        Ok(player)
+    }
+
+    /// The player of a guild oxo is in a call in.
+    pub fn get(state: &Arc<State>, guild_id: GuildId) -> Option<Self> {
+        let call = state.songbird_instance.get(guild_id)?;
+
+        Some(Self::new(state.clone(), guild_id, call))
+    }
+
+    pub fn guild_id(&self) -> GuildId {
+        self.guild_id
+    }
+
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    pub fn queue(&self) -> &GuildQueue {
+        &self.queue
+    }
+
+    pub fn call(&self) -> &Arc<Mutex<Call>> {
+        &self.call
+    }
+
+    /// The voice channel oxo is in, if it is connected.
+    pub async fn current_channel(&self) -> Option<ChannelId> {
+        let channel_id = self.call.lock().await.current_channel()?;
+
+        Some(ChannelId(channel_id.0))
+    }
+
+    pub async fn settings(&self) -> GuildSettings {
+        self.state.settings.get(self.guild_id).await
+    }
+
+    /// The loop-mode set with `/loop`, or the `loop_default` of the guild.
+    pub async fn loop_mode(&self, settings: &GuildSettings) -> LoopMode {
+        self.state
+            .loop_modes
+            .lock()
+            .await
+            .get(&self.guild_id)
+            .cloned()
+            .unwrap_or_else(|| settings.loop_default.clone())
+    }
+
+    /// Where to post about this guild: its announce channel, or where the current track was
+    /// queued from.
+    pub async fn announce_channel(&self, settings: &GuildSettings) -> Option<ChannelId> {
+        match (settings.announce_channel, self.queue.read().current()) {
+            (Some(channel_id), _) => Some(channel_id),
+            (None, Some(current)) => Request::of(&current).await.channel_id,
+            (None, None) => None,
+        }
+    }
+
+    /// Posts `notice` in `channel_id`, see [`Notifier`](crate::client::notifications::Notifier).
+    pub async fn notify(
+        &self,
+        channel_id: Option<ChannelId>,
+        notice: Notice,
+        values: &[(&str, &str)],
+    ) {
+        self.state
+            .notifier
+            .notify(&self.state, self.guild_id, channel_id, notice, values)
+            .await;
+    }
+
+    /// Posts `notice` about `track`, which ended, in `channel_id`.
+    pub async fn notify_ended(
+        &self,
+        channel_id: Option<ChannelId>,
+        track: &TrackHandle,
+        notice: Notice,
+        values: &[(&str, &str)],
+    ) {
+        self.state
+            .notifier
+            .track_ended(
+                &self.state,
+                self.guild_id,
+                channel_id,
+                track,
+                notice,
+                values,
+            )
+            .await;
+    }
+
+    /// Queues `entry` after checking that there is room for it.
+    pub async fn enqueue(
+        &self,
+        entry: QueueEntry,
+        request: Request,
+    ) -> Result<TrackHandle, AppError> {
+        let settings = self.settings().await;
+        let queue_mode = self.state.queue_mode(self.guild_id).await;
+
+        let queue = self.queue.lock().await;
+        // the queue might have filled up while the track was looked up
+        settings
+            .limits
+            .check_queue(Some(&queue), request.requester)
+            .await?;
+
+        let handle = self.add(&queue, entry, request, &settings).await;
+        place_fairly(&queue, self.queue.pinned(), queue_mode, &handle).await;
+
+        Ok(handle)
+    }
+
+    /// Adds `entry` to the end of `queue`, which has to be the locked queue of this player.
+    pub async fn add(
+        &self,
+        queue: &TrackQueue,
+        entry: QueueEntry,
+        request: Request,
+        settings: &GuildSettings,
+    ) -> TrackHandle {
+        let crossfade = settings.crossfade.unwrap_or_default();
+        let (mut track, handle) = entry.into_player(queue, crossfade).await;
+        track.set_volume(settings.volume());
+        {
+            let mut typemap = handle.typemap().write().await;
+            if let Some(requester) = request.requester {
+                typemap.insert::<Requester>(requester);
+            }
+            if let Some(channel_id) = request.channel_id {
+                typemap.insert::<RequestChannel>(channel_id);
+            }
+        }
+        start_track_span(&handle, self.guild_id, request.requester).await;
+        self.state.segments.watch(&handle, &settings.skip_segments);
+        crossfade::watch(self, &handle, crossfade);
+
+        let end_event = EndEventHandler::new(self.clone());
+        if let Err(err) = handle.add_event(Event::Track(TrackEvent::End), end_event) {
+            warn!("Could not watch the end of a track: {err}");
+        }
+        // queued tracks fire this once it's their turn, the first one starts right away
+        let play_event = PlayEventHandler::new(self.clone());
+        if let Err(err) = handle.add_event(Event::Track(TrackEvent::Play), play_event) {
+            warn!("Could not watch the start of a track: {err}");
+        }
+        if queue.is_empty() {
+            let (player, handle) = (self.clone(), handle.clone());
+            tokio::spawn(async move { player.track_started(&handle).await });
+        }
+
+        queue.add(track, &mut *self.call.lock().await);
+
+        handle
+    }
+
+    /// Announces `track` and shows it as the topic, if oxo is on a stage.
+    pub async fn track_started(&self, track: &TrackHandle) {
+        let settings = self.settings().await;
+        let channel_id = settings
+            .announce_channel
+            .or(Request::of(track).await.channel_id);
+        self.state
+            .notifier
+            .now_playing(&self.state, self.guild_id, channel_id, track)
+            .await;
+
+        let Some(channel_id) = self.current_channel().await else {
+            return;
+        };
+
+        if let Some((http, stage)) = self.stage(channel_id).await {
+            stage::set_topic(&http, &stage, &track_title(track)).await;
+        }
+    }
+
+    /// Asks to speak if `channel_id` is a stage, oxo can't be heard there otherwise.
+    async fn take_the_stage(&self, channel_id: ChannelId) {
+        let Some((http, stage)) = self.stage(channel_id).await else {
+            return;
+        };
+
+        stage::take_the_stage(&http, &stage).await;
+        if let Some(current) = self.queue.read().current() {
+            stage::set_topic(&http, &stage, &track_title(&current)).await;
+        }
+    }
+
+    /// The stage `channel_id`, if it is one and oxo is connected to discord.
+    async fn stage(&self, channel_id: ChannelId) -> Option<(Arc<Http>, GuildChannel)> {
+        let http = self.state.http().await?;
+        let cache = self.state.cache().await?;
+        let stage = stage::stage_channel(&cache, &http, channel_id).await?;
+
+        Some((http, stage))
+    }
+
+    /// Leaves the call, the queue is left as it is.
+    pub async fn leave(&self) {
+        if let Err(err) = self.call.lock().await.leave().await {
+            warn!("Could not leave the call: {err}");
+        }
+    }
+
+    /// Throws away the whole queue, including the current track, and leaves the call.
+    /// Returns how many tracks were removed.
+    pub async fn stop(&self) -> usize {
+        let queue = self.queue.lock().await;
+        let removed = queue.modify_queue(|q| q.drain(..).collect::<Vec<_>>());
+        let count = removed.len();
+        discard(removed).await;
+        drop(queue);
+
+        self.leave().await;
+
+        count
+    }
+}
+
+fn track_title(track: &TrackHandle) -> String {
+    let metadata = track.metadata();
+
+    metadata
+        .title
+        .clone()
+        .or_else(|| metadata.source_url.clone())
+        .unwrap_or_else(|| "Something without a title".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::client::{
+        limits::Limits,
+        sources::fake::{fake_state, FakeResolver},
+    };
+
+    const GUILD: GuildId = GuildId(1);
+    const USER: UserId = UserId(2);
+    const CHANNEL: ChannelId = ChannelId(3);
+
+    async fn player(defaults: GuildSettings) -> GuildPlayer {
+        let state = fake_state(FakeResolver::new("https://"), defaults).await;
+        let call = Arc::new(Mutex::new(Call::standalone(GUILD, USER)));
+
+        GuildPlayer::new(state, GUILD, call)
+    }
+
+    async fn lookup(player: &GuildPlayer, query: &str) -> QueueEntry {
+        QueueEntry::lookup(&player.state.resolvers, query, &Limits::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn queued_tracks_remember_their_request() {
+        let player = player(GuildSettings::default()).await;
+
+        let entry = lookup(&player, "https://a").await;
+        let handle = player
+            .enqueue(entry, Request::new(USER, CHANNEL))
+            .await
+            .unwrap();
+
+        let request = Request::of(&handle).await;
+        assert_eq!(request.requester, Some(USER));
+        assert_eq!(request.channel_id, Some(CHANNEL));
+        assert_eq!(player.queue().read().len(), 1);
+        assert!(player.state.queues.get(GUILD).is_some());
+    }
+
+    #[tokio::test]
+    async fn the_queue_limit_is_checked_per_requester() {
+        let player = player(GuildSettings {
+            limits: Limits {
+                max_tracks_per_user: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        for query in ["https://a", "https://b"] {
+            let entry = lookup(&player, query).await;
+            player.enqueue(entry, Request::default()).await.unwrap();
+        }
+        let entry = lookup(&player, "https://c").await;
+        player
+            .enqueue(entry, Request::new(USER, CHANNEL))
+            .await
+            .unwrap();
+
+        let entry = lookup(&player, "https://d").await;
+        let full = player.enqueue(entry, Request::new(USER, CHANNEL)).await;
+
+        assert!(full.is_err());
+        assert_eq!(player.queue().read().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn ended_tracks_are_looped_with_their_request() {
+        let player = player(GuildSettings {
+            loop_default: LoopMode::Queue,
+            idle_timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })
+        .await;
+
+        let entry = lookup(&player, "https://a").await;
+        let handle = player
+            .enqueue(entry, Request::new(USER, CHANNEL))
+            .await
+            .unwrap();
+        EndEventHandler::new(player.clone())
+            .track_ended(&handle)
+            .await;
+
+        let tracks = player.queue().read().current_queue();
+        assert_eq!(tracks.len(), 2);
+        let looped = tracks.last().unwrap();
+        assert_ne!(looped.uuid(), handle.uuid());
+        assert_eq!(looped.metadata().source_url.as_deref(), Some("https://a"));
+        assert_eq!(Request::of(looped).await.requester, Some(USER));
+    }
+}
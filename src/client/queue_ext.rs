@@ -0,0 +1,489 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    ops::RangeInclusive,
+};
+
+use poise::serenity_prelude::{ChannelId, UserId};
+use songbird::{
+    tracks::{Queued, TrackError, TrackHandle, TrackQueue},
+    typemap::TypeMapKey,
+};
+use tracing::warn;
+
+use crate::{client::bot::QueueMode, error::AppError};
+
+/// Marks a track which was taken out of the queue on purpose (`/remove`, `/clear`, ...)
+/// so that its end-event is not mistaken for a track that finished playing.
+pub struct Discarded;
+
+impl TypeMapKey for Discarded {
+    type Value = ();
+}
+
+/// The user who queued a track. Tracks added through the api have no requester.
+pub struct Requester;
+
+impl TypeMapKey for Requester {
+    type Value = UserId;
+}
+
+/// The text channel a track was queued from.
+pub struct RequestChannel;
+
+impl TypeMapKey for RequestChannel {
+    type Value = ChannelId;
+}
+
+/// Queue positions are 1-based, position 1 being the track that is currently playing.
+/// All methods validate positions against the queue while holding its lock and hand back
+/// the removed tracks, which still have to be passed to [`discard`].
+///
+/// The first `pinned` tracks are playing already and stay where they are, see
+/// [`GuildQueue::pinned`](crate::client::queues::GuildQueue::pinned).
+pub trait TrackQueueExt {
+    fn remove_positions(
+        &self,
+        pinned: usize,
+        positions: RangeInclusive<usize>,
+    ) -> Result<Vec<Queued>, AppError>;
+    fn move_position(&self, pinned: usize, from: usize, to: usize) -> Result<(), AppError>;
+    fn remove_until(&self, pinned: usize, position: usize) -> Result<Vec<Queued>, AppError>;
+    fn clear_upcoming(&self, pinned: usize) -> Vec<Queued>;
+    fn remove_duplicates(&self, pinned: usize) -> Vec<Queued>;
+}
+
+impl TrackQueueExt for TrackQueue {
+    fn remove_positions(
+        &self,
+        pinned: usize,
+        positions: RangeInclusive<usize>,
+    ) -> Result<Vec<Queued>, AppError> {
+        self.modify_queue(|q| {
+            check_upcoming(q.len(), pinned, *positions.start())?;
+            check_upcoming(q.len(), pinned, *positions.end())?;
+
+            Ok(q.drain(positions.start() - 1..*positions.end()).collect())
+        })
+    }
+
+    fn move_position(&self, pinned: usize, from: usize, to: usize) -> Result<(), AppError> {
+        self.modify_queue(|q| {
+            check_upcoming(q.len(), pinned, from)?;
+            check_upcoming(q.len(), pinned, to)?;
+
+            let track = q.remove(from - 1).expect("position was checked");
+            q.insert(to - 1, track);
+
+            Ok(())
+        })
+    }
+
+    fn remove_until(&self, pinned: usize, position: usize) -> Result<Vec<Queued>, AppError> {
+        self.modify_queue(|q| {
+            check_upcoming(q.len(), 1, position)?;
+            // everything before `position` goes, which a track that is fading in can't
+            if pinned > 1 {
+                return Err(fading_in(2));
+            }
+
+            Ok(q.drain(1..position - 1).collect())
+        })
+    }
+
+    fn clear_upcoming(&self, pinned: usize) -> Vec<Queued> {
+        self.modify_queue(|q| q.drain(pinned.min(q.len())..).collect())
+    }
+
+    fn remove_duplicates(&self, pinned: usize) -> Vec<Queued> {
+        self.modify_queue(|q| {
+            let mut seen = HashSet::new();
+
+            // the current track is always the first occurrence, so it is never removed
+            let duplicates = q
+                .iter()
+                .enumerate()
+                .filter(|(_, track)| match &track.metadata().source_url {
+                    Some(url) => !seen.insert(url.clone()),
+                    None => false,
+                })
+                .map(|(index, _)| index)
+                .filter(|index| *index >= pinned)
+                .collect::<Vec<_>>();
+
+            duplicates
+                .into_iter()
+                .rev()
+                .filter_map(|index| q.remove(index))
+                .collect()
+        })
+    }
+}
+
+/// Stops tracks that were taken out of a queue, marking them as [`Discarded`] first.
+pub async fn discard(tracks: Vec<Queued>) {
+    for track in tracks {
+        track.typemap().write().await.insert::<Discarded>(());
+
+        match track.stop() {
+            // the track is gone already
+            Ok(()) | Err(TrackError::Finished) => {}
+            Err(err) => warn!("Could not stop a discarded track: {err}"),
+        }
+    }
+}
+
+/// Reorders all upcoming tracks so that requesters take turns, if fair queueing is enabled.
+/// The first `pinned` tracks stay where they are, like in [`TrackQueueExt`].
+///
+/// Each requester keeps the relative order of their own tracks. Only used when the mode is
+/// switched on, added tracks are placed with [`place_fairly`] so manual reordering sticks.
+pub async fn apply_queue_mode(queue: &TrackQueue, pinned: usize, queue_mode: QueueMode) {
+    if queue_mode != QueueMode::Fair {
+        return;
+    }
+
+    let requesters = requesters(queue).await;
+    queue.modify_queue(|q| {
+        // the last track that plays already had the latest turn
+        let Some(current) = q.get(pinned - 1) else {
+            return;
+        };
+
+        let requester_of = |track: &Queued| requesters.of(track);
+        let current = requester_of(current);
+        let upcoming = q.iter().skip(pinned).map(requester_of).collect::<Vec<_>>();
+
+        let mut tracks = q.drain(pinned..).map(Some).collect::<Vec<_>>();
+        for index in round_robin(&current, &upcoming) {
+            q.push_back(tracks[index].take().expect("every index is visited once"));
+        }
+    });
+}
+
+/// Moves `track`, which was just added to the end of `queue`, to its turn if fair queueing is
+/// enabled. The other tracks stay where they are.
+pub async fn place_fairly(
+    queue: &TrackQueue,
+    pinned: usize,
+    queue_mode: QueueMode,
+    track: &TrackHandle,
+) {
+    if queue_mode != QueueMode::Fair {
+        return;
+    }
+
+    let requesters = requesters(queue).await;
+    queue.modify_queue(|q| {
+        let Some(index) = q.iter().position(|queued| queued.uuid() == track.uuid()) else {
+            return;
+        };
+        // tracks that are playing stay where they are
+        if index < pinned {
+            return;
+        }
+        let queued = q.remove(index).expect("the track was found");
+
+        let requester_of = |track: &Queued| requesters.of(track);
+        let current = requester_of(&q[pinned - 1]);
+        let upcoming = q.iter().skip(pinned).map(requester_of).collect::<Vec<_>>();
+        let position = fair_position(&current, &upcoming, &requester_of(&queued));
+
+        q.insert(pinned + position, queued);
+    });
+}
+
+/// The requester of every track in `queue`, read before it is modified since that can't wait.
+async fn requesters(queue: &TrackQueue) -> Requesters {
+    let mut requesters = vec![];
+    for track in queue.current_queue() {
+        let requester = track.typemap().read().await.get::<Requester>().copied();
+        requesters.push((track, requester));
+    }
+
+    Requesters(requesters)
+}
+
+struct Requesters(Vec<(TrackHandle, Option<UserId>)>);
+
+impl Requesters {
+    /// Tracks that were added in the meantime are treated like api requests.
+    fn of(&self, track: &Queued) -> Option<UserId> {
+        self.0
+            .iter()
+            .find(|(handle, _)| handle.uuid() == track.uuid())
+            .and_then(|(_, requester)| *requester)
+    }
+}
+
+/// Returns the order in which `keys` should be played so that each key takes turns,
+/// starting with the first key that is not `current`.
+fn round_robin<K: Eq + Hash + Clone>(current: &K, keys: &[K]) -> Vec<usize> {
+    let mut groups: Vec<(K, Vec<usize>)> = vec![];
+    for (index, key) in keys.iter().enumerate() {
+        match groups.iter_mut().find(|(k, _)| k == key) {
+            Some((_, group)) => group.push(index),
+            None => groups.push((key.clone(), vec![index])),
+        }
+    }
+
+    if groups.first().map(|(k, _)| k) == Some(current) {
+        groups.rotate_left(1);
+    }
+
+    let rounds = groups
+        .iter()
+        .map(|(_, g)| g.len())
+        .max()
+        .unwrap_or_default();
+
+    (0..rounds)
+        .flat_map(|round| {
+            groups
+                .iter()
+                .filter_map(move |(_, g)| g.get(round).copied())
+        })
+        .collect()
+}
+
+/// Where among the `upcoming` keys a new one for `key` goes so that it waits for its turn:
+/// after every track from an earlier round, `current` counting as the first round of its key.
+fn fair_position<K: Eq + Hash + Clone>(current: &K, upcoming: &[K], key: &K) -> usize {
+    let mut rounds = HashMap::from([(current.clone(), 1)]);
+    let mut round_of = |key: &K| {
+        let round = rounds.entry(key.clone()).or_insert(0);
+        *round += 1;
+        *round - 1
+    };
+
+    let upcoming_rounds = upcoming.iter().map(&mut round_of).collect::<Vec<_>>();
+    let round = round_of(key);
+
+    upcoming_rounds
+        .iter()
+        .position(|upcoming| *upcoming > round)
+        .unwrap_or(upcoming.len())
+}
+
+/// Parses either a single position (`3`) or an inclusive range of positions (`3-7`).
+pub fn parse_positions(input: &str) -> Result<RangeInclusive<usize>, AppError> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .map_err(|_| AppError::invalid_input(format!("`{}` is not a queue position", n.trim())))
+    };
+
+    let range = match input.split_once('-') {
+        Some((start, end)) => parse(start)?..=parse(end)?,
+        None => parse(input)?..=parse(input)?,
+    };
+
+    if range.is_empty() {
+        return Err(AppError::invalid_input(format!(
+            "`{input}` is not a valid range, the start has to come before the end"
+        )));
+    }
+
+    Ok(range)
+}
+
+fn check_upcoming(len: usize, pinned: usize, position: usize) -> Result<(), AppError> {
+    match position {
+        0 => Err(AppError::invalid_input("Positions start at 1")),
+        1 => Err(AppError::invalid_input(
+            "Position 1 is the track that is currently playing, use `/skip` for that one",
+        )),
+        p if p <= pinned => Err(fading_in(p)),
+        p if p > len => Err(AppError::invalid_input(format!(
+            "There is no track at position {p}, the queue only has {len} tracks"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn fading_in(position: usize) -> AppError {
+    AppError::invalid_input(format!(
+        "The track at position {position} is fading in already, use `/skip` to get to it"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::GuildId;
+    use songbird::{
+        create_player,
+        input::{Codec, Container, Input, Metadata, Reader},
+        Call,
+    };
+
+    use super::*;
+
+    /// A queue with a track for every url, the first one playing. The call has to be kept
+    /// around, the tracks end without it.
+    fn queue(urls: &str) -> (TrackQueue, Call) {
+        let mut call = Call::standalone(GuildId(1), UserId(1));
+        let queue = TrackQueue::new();
+
+        for url in urls.chars() {
+            // ten seconds of stereo f32 silence, so the first track doesn't end mid-test
+            let silence = Reader::from_memory(vec![0; 48_000 * 2 * 4 * 10]);
+            let metadata = Metadata {
+                source_url: Some(url.to_string()),
+                ..Default::default()
+            };
+            let input = Input::new(
+                true,
+                silence,
+                Codec::FloatPcm,
+                Container::Raw,
+                Some(metadata),
+            );
+            queue.add(create_player(input).0, &mut call);
+        }
+
+        (queue, call)
+    }
+
+    fn urls(tracks: &[impl std::ops::Deref<Target = TrackHandle>]) -> String {
+        tracks
+            .iter()
+            .filter_map(|track| track.metadata().source_url.clone())
+            .collect()
+    }
+
+    fn queued(queue: &TrackQueue) -> String {
+        urls(&queue.current_queue().iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn requesters_take_turns() {
+        let order = |current, keys: &str| {
+            let keys = keys.chars().collect::<Vec<_>>();
+            round_robin(&current, &keys)
+                .into_iter()
+                .map(|index| keys[index])
+                .collect::<String>()
+        };
+
+        assert_eq!(order('a', "aabacb"), "bcabaa");
+        assert_eq!(order('a', "bba"), "bab");
+        assert_eq!(order('c', "aabb"), "abab");
+        assert_eq!(order('x', ""), "");
+    }
+
+    #[test]
+    fn added_tracks_wait_for_their_turn() {
+        let position = |current, upcoming: &str, key| {
+            let upcoming = upcoming.chars().collect::<Vec<_>>();
+            fair_position(&current, &upcoming, &key)
+        };
+
+        // the current track counts as a turn of its requester
+        assert_eq!(position('a', "", 'a'), 0);
+        assert_eq!(position('a', "a", 'b'), 0);
+        assert_eq!(position('a', "ba", 'b'), 2);
+        assert_eq!(position('a', "bab", 'c'), 1);
+        assert_eq!(position('a', "bcab", 'c'), 4);
+        // tracks that were moved by hand keep their order, the new one goes in between
+        assert_eq!(position('a', "aaab", 'b'), 1);
+    }
+
+    #[test]
+    fn positions_are_single_numbers_or_ranges() {
+        let parse = |input| parse_positions(input).map_err(|err| err.message());
+
+        assert_eq!(parse("3"), Ok(3..=3));
+        assert_eq!(parse("3-7"), Ok(3..=7));
+        assert_eq!(parse(" 2 - 4 "), Ok(2..=4));
+        assert_eq!(parse("4-4"), Ok(4..=4));
+        assert!(parse("7-3")
+            .unwrap_err()
+            .contains("start has to come before the end"));
+        assert!(parse("two")
+            .unwrap_err()
+            .contains("`two` is not a queue position"));
+        assert!(parse("1-2-3").is_err());
+        assert!(parse("-3").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn only_upcoming_positions_can_be_changed() {
+        let check = |position| check_upcoming(5, 1, position).map_err(|err| err.message());
+
+        assert!(check(0).unwrap_err().contains("start at 1"));
+        assert!(check(1).unwrap_err().contains("use `/skip`"));
+        assert_eq!(check(2), Ok(()));
+        assert_eq!(check(5), Ok(()));
+        assert!(check(6).unwrap_err().contains("only has 5 tracks"));
+        assert!(check_upcoming(5, 2, 2)
+            .unwrap_err()
+            .message()
+            .contains("fading in already"));
+    }
+
+    #[tokio::test]
+    async fn positions_are_removed_inclusively() {
+        let (queue, _call) = queue("abcde");
+
+        assert_eq!(urls(&queue.remove_positions(1, 2..=2).unwrap()), "b");
+        assert_eq!(urls(&queue.remove_positions(1, 3..=4).unwrap()), "de");
+        assert_eq!(queued(&queue), "ac");
+
+        assert!(queue.remove_positions(1, 1..=2).is_err());
+        assert!(queue.remove_positions(1, 2..=3).is_err());
+        assert_eq!(queued(&queue), "ac");
+    }
+
+    #[tokio::test]
+    async fn tracks_move_to_exactly_the_given_position() {
+        let (queue, _call) = queue("abcd");
+
+        queue.move_position(1, 4, 2).unwrap();
+        assert_eq!(queued(&queue), "adbc");
+        queue.move_position(1, 2, 4).unwrap();
+        assert_eq!(queued(&queue), "abcd");
+        queue.move_position(1, 3, 3).unwrap();
+        assert_eq!(queued(&queue), "abcd");
+
+        assert!(queue.move_position(1, 1, 2).is_err());
+        assert!(queue.move_position(1, 2, 5).is_err());
+        assert!(queue.move_position(1, 5, 2).is_err());
+        assert_eq!(queued(&queue), "abcd");
+    }
+
+    #[tokio::test]
+    async fn skipping_to_a_track_removes_the_ones_before_it() {
+        let (queue, _call) = queue("abcd");
+
+        // the track after the current one is next already
+        assert_eq!(urls(&queue.remove_until(1, 2).unwrap()), "");
+        assert_eq!(urls(&queue.remove_until(1, 4).unwrap()), "bc");
+        assert_eq!(queued(&queue), "ad");
+
+        assert!(queue.remove_until(1, 1).is_err());
+        assert!(queue.remove_until(1, 3).is_err());
+        assert_eq!(queued(&queue), "ad");
+    }
+
+    #[tokio::test]
+    async fn clearing_keeps_the_current_track() {
+        let (empty, _call) = queue("");
+        assert!(empty.clear_upcoming(1).is_empty());
+
+        let (queue, _call) = queue("abc");
+        assert_eq!(urls(&queue.clear_upcoming(1)), "bc");
+        assert_eq!(queued(&queue), "a");
+        assert!(queue.clear_upcoming(1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_later_duplicates_are_removed() {
+        let (queue, _call) = queue("abacbb");
+
+        // removed from the back
+        assert_eq!(urls(&queue.remove_duplicates(1)), "bba");
+        assert_eq!(queued(&queue), "abc");
+        assert!(queue.remove_duplicates(1).is_empty());
+    }
+}
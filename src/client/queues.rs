@@ -0,0 +1,289 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+};
+
+use poise::serenity_prelude::{GuildId, Mutex};
+use songbird::tracks::{TrackError, TrackHandle, TrackQueue, TrackResult};
+use tokio::sync::MutexGuard;
+
+/// The queue of a single guild.
+///
+/// Changes that have to see the queue unchanged from start to end, e.g. checking the limits
+/// and then adding a track, go through [`GuildQueue::lock`]. Slow work (looking up tracks,
+/// talking to discord) should happen before taking that lock.
+#[derive(Debug)]
+pub struct GuildQueue {
+    /// songbird's queue is shared between its clones and synchronised internally,
+    /// so reading it doesn't have to wait for the lock
+    queue: TrackQueue,
+    exclusive: Mutex<TrackQueue>,
+    /// Counts how often the queue ran empty, so only the latest idle timer may leave
+    idle_periods: AtomicU64,
+    /// The next track while it fades in, it plays alongside the current one until that ends
+    fading_in: StdMutex<Option<TrackHandle>>,
+}
+
+impl Default for GuildQueue {
+    fn default() -> Self {
+        let queue = TrackQueue::new();
+
+        Self {
+            exclusive: Mutex::new(queue.clone()),
+            queue,
+            idle_periods: AtomicU64::new(0),
+            fading_in: Default::default(),
+        }
+    }
+}
+
+impl GuildQueue {
+    /// The queue, for reading it and for changes that are a single call (`skip`, `pause`, ...).
+    pub fn read(&self) -> &TrackQueue {
+        &self.queue
+    }
+
+    /// Waits until nobody else is changing the queue.
+    pub async fn lock(&self) -> MutexGuard<'_, TrackQueue> {
+        self.exclusive.lock().await
+    }
+
+    /// Pauses the current track, and the next one if it is fading in already.
+    pub fn pause(&self) -> TrackResult<()> {
+        self.queue.pause()?;
+        self.with_fading_in(TrackHandle::pause)
+    }
+
+    /// Resumes the current track, and the next one if it was fading in when it was paused.
+    pub fn resume(&self) -> TrackResult<()> {
+        self.queue.resume()?;
+        self.with_fading_in(TrackHandle::play)
+    }
+
+    /// Sets or clears the track that fades in, see [`crate::client::crossfade`].
+    pub fn set_fading_in(&self, track: Option<TrackHandle>) {
+        *self.fading_in.lock().unwrap() = track;
+    }
+
+    /// How many tracks at the front of the queue are playing already: the current one, and the
+    /// next one while it fades in. Reordering the queue leaves them where they are.
+    pub fn pinned(&self) -> usize {
+        let fading_in = self
+            .fading_in
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(TrackHandle::uuid);
+        let next = self.queue.current_queue().get(1).map(TrackHandle::uuid);
+
+        match fading_in.is_some() && fading_in == next {
+            true => 2,
+            false => 1,
+        }
+    }
+
+    fn with_fading_in(&self, action: fn(&TrackHandle) -> TrackResult<()>) -> TrackResult<()> {
+        let fading_in = self.fading_in.lock().unwrap().clone();
+
+        match fading_in.as_ref().map(action) {
+            // the track is gone already
+            None | Some(Ok(())) | Some(Err(TrackError::Finished)) => Ok(()),
+            Some(Err(err)) => Err(err),
+        }
+    }
+
+    /// Starts a new idle period, which ends as soon as the next one starts.
+    pub fn start_idling(&self) -> u64 {
+        self.idle_periods.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Whether `period` is still the latest idle period.
+    pub fn is_idling_since(&self, period: u64) -> bool {
+        self.idle_periods.load(Ordering::SeqCst) == period
+    }
+}
+
+/// The queues of all guilds, each with its own lock.
+///
+/// The map itself is only locked for as long as it takes to look a queue up, so nothing
+/// that happens in one guild ever waits on another one.
+#[derive(Debug, Clone, Default)]
+pub struct Queues(Arc<StdMutex<HashMap<GuildId, Arc<GuildQueue>>>>);
+
+impl Queues {
+    pub fn get(&self, guild_id: GuildId) -> Option<Arc<GuildQueue>> {
+        self.0.lock().unwrap().get(&guild_id).cloned()
+    }
+
+    /// The queue of `guild_id`, an empty one is created if it has none yet.
+    pub fn get_or_create(&self, guild_id: GuildId) -> Arc<GuildQueue> {
+        self.0.lock().unwrap().entry(guild_id).or_default().clone()
+    }
+
+    pub fn guild_ids(&self) -> Vec<GuildId> {
+        self.0.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Every queue, in no particular order.
+    pub fn all(&self) -> Vec<(GuildId, Arc<GuildQueue>)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(guild_id, queue)| (*guild_id, queue.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use poise::serenity_prelude::UserId;
+    use songbird::Call;
+
+    use super::*;
+    use crate::{
+        client::{
+            bot::{QueueMode, State},
+            limits::Limits,
+            player::{GuildPlayer, Request},
+            queue_ext::{apply_queue_mode, discard, TrackQueueExt},
+            settings::GuildSettings,
+            sources::{
+                fake::{fake_state, FakeResolver},
+                lazy::QueueEntry,
+            },
+        },
+        error::AppError,
+    };
+
+    const LOOKUP: Duration = Duration::from_millis(300);
+
+    /// A bot whose lookups are slow, with `defaults` for every guild.
+    async fn state(defaults: GuildSettings) -> Arc<State> {
+        fake_state(FakeResolver::new("https://").with_delay(LOOKUP), defaults).await
+    }
+
+    /// What `/play` does: look the track up, then queue it with the player of the guild.
+    async fn play(state: Arc<State>, guild_id: GuildId) -> Result<TrackHandle, AppError> {
+        let settings = state.settings.get(guild_id).await;
+        let entry = QueueEntry::lookup(&state.resolvers, "https://track", &settings.limits)
+            .await
+            .unwrap();
+
+        let call = Arc::new(Mutex::new(Call::standalone(guild_id, UserId(1))));
+        let player = GuildPlayer::new(state, guild_id, call);
+        player.enqueue(entry, Request::default()).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn guilds_do_not_wait_on_each_other() {
+        let state = state(GuildSettings::default()).await;
+        let queues = state.queues.clone();
+
+        // one guild is busy with its queue for the whole test
+        let busy = queues.get_or_create(GuildId(0));
+        let _busy = busy.lock().await;
+
+        let started = Instant::now();
+        let requests =
+            (1..=100).map(|guild_id| tokio::spawn(play(state.clone(), GuildId(guild_id))));
+        for request in requests.collect::<Vec<_>>() {
+            request.await.unwrap().unwrap();
+        }
+
+        // a hundred lookups one after another would take 30s
+        assert!(
+            started.elapsed() < LOOKUP * 10,
+            "took {:?}",
+            started.elapsed()
+        );
+        assert_eq!(queues.all().len(), 101);
+        assert!(queues
+            .all()
+            .iter()
+            .all(|(guild_id, queue)| { *guild_id == GuildId(0) || queue.read().len() == 1 }));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_requests_respect_the_queue_limit() {
+        let state = state(GuildSettings {
+            limits: Limits {
+                max_queue_len: Some(5),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        let requests = (0..50).map(|_| tokio::spawn(play(state.clone(), GuildId(1))));
+        let mut queued = 0;
+        for request in requests.collect::<Vec<_>>() {
+            if request.await.unwrap().is_ok() {
+                queued += 1;
+            }
+        }
+
+        assert_eq!(queued, 5);
+        assert_eq!(state.queues.get(GuildId(1)).unwrap().read().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn a_track_that_fades_in_stays_next() {
+        let state = state(GuildSettings::default()).await;
+        let mut tracks = vec![];
+        for _ in 0..4 {
+            tracks.push(play(state.clone(), GuildId(1)).await.unwrap());
+        }
+        let uuids = |queue: &TrackQueue| {
+            queue
+                .current_queue()
+                .iter()
+                .map(TrackHandle::uuid)
+                .collect::<Vec<_>>()
+        };
+
+        let guild_queue = state.queues.get(GuildId(1)).unwrap();
+        assert_eq!(guild_queue.pinned(), 1);
+        guild_queue.set_fading_in(Some(tracks[1].clone()));
+        assert_eq!(guild_queue.pinned(), 2);
+
+        let queue = guild_queue.lock().await;
+        assert!(queue.move_position(2, 4, 2).is_err());
+        assert!(queue.move_position(2, 2, 4).is_err());
+        assert!(queue.remove_positions(2, 2..=3).is_err());
+        assert!(queue.remove_until(2, 3).is_err());
+
+        queue.move_position(2, 4, 3).unwrap();
+        apply_queue_mode(&queue, 2, QueueMode::Fair).await;
+        let expected = [0, 1, 3, 2].map(|i| tracks[i].uuid());
+        assert_eq!(uuids(&queue), expected);
+
+        // every track is the same, only the upcoming ones are duplicates
+        let removed = queue.remove_duplicates(2);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(uuids(&queue), expected[..2]);
+        discard(removed).await;
+
+        // once the fade is over it is the current track, the next one can be moved again
+        guild_queue.set_fading_in(None);
+        assert_eq!(guild_queue.pinned(), 1);
+        assert_eq!(queue.clear_upcoming(1).len(), 1);
+    }
+
+    #[test]
+    fn only_the_latest_idle_period_counts() {
+        let queue = GuildQueue::default();
+
+        let first = queue.start_idling();
+        assert!(queue.is_idling_since(first));
+
+        let second = queue.start_idling();
+        assert!(!queue.is_idling_since(first));
+        assert!(queue.is_idling_since(second));
+    }
+}
@@ -0,0 +1,244 @@
+//! Gets oxo back into its voice channel when the connection drops.
+//!
+//! songbird reconnects on its own when discord moves a call to another voice server, but
+//! gives up on connections that drop while playing. Those are rejoined here with a backoff,
+//! continuing the current track where it was when the connection dropped.
+
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use poise::{
+    async_trait,
+    serenity_prelude::{ChannelId, GuildId},
+};
+use songbird::{
+    events::context_data::{DisconnectKind, DisconnectReason},
+    model::CloseCode,
+    tracks::{PlayMode, TrackHandle},
+    CoreEvent, Event, EventContext, EventHandler,
+};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::{
+    client::{feed::FeedEvent, notifications::Notice, player::GuildPlayer},
+    metrics::METRICS,
+};
+
+/// Watches the voice connection of every call oxo joins, see [`GuildPlayer::join`].
+#[derive(Debug, Default)]
+pub struct Reconnects {
+    /// Guilds whose call is watched already, songbird keeps the call around after leaving
+    watched: Mutex<HashSet<GuildId>>,
+    /// Guilds that are being rejoined right now
+    reconnecting: Mutex<HashSet<GuildId>>,
+}
+
+impl Reconnects {
+    /// Starts watching the call of `player`, unless it is watched already.
+    pub async fn watch(&self, player: &GuildPlayer) {
+        if !self.watched.lock().unwrap().insert(player.guild_id()) {
+            return;
+        }
+
+        let mut call = player.call().lock().await;
+        for event in [CoreEvent::DriverDisconnect, CoreEvent::DriverReconnect] {
+            call.add_global_event(Event::Core(event), VoiceEventHandler(player.clone()));
+        }
+    }
+}
+
+struct VoiceEventHandler(GuildPlayer);
+
+#[async_trait]
+impl EventHandler for VoiceEventHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let player = &self.0;
+
+        match ctx {
+            EventContext::DriverDisconnect(data) => {
+                let reason = match (data.kind, data.reason) {
+                    // oxo left on its own
+                    (DisconnectKind::Runtime, None) => return None,
+                    // whoever tried to join gets the error
+                    (DisconnectKind::Connect, _) => return None,
+                    (_, reason) => describe(reason),
+                };
+                let channel_id = ChannelId(data.channel_id?.0);
+                let span = info_span!("reconnect", guild_id = %player.guild_id(), %channel_id);
+                tokio::spawn(reconnect(player.clone(), channel_id, reason).instrument(span));
+            }
+            EventContext::DriverReconnect(data) => {
+                let channel_id = ChannelId(data.channel_id?.0);
+
+                info!(guild_id = %player.guild_id(), "Voice connection moved to {}", data.server);
+                player.state().feed.publish(FeedEvent::Reconnected {
+                    guild_id: player.guild_id(),
+                    channel_id,
+                });
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+async fn reconnect(player: GuildPlayer, channel_id: ChannelId, reason: String) {
+    let state = player.state().clone();
+    let guild_id = player.guild_id();
+    if !state
+        .reconnects
+        .reconnecting
+        .lock()
+        .unwrap()
+        .insert(guild_id)
+    {
+        return;
+    }
+
+    warn!("Voice connection dropped: {reason}");
+    METRICS.voice_disconnects.inc();
+    state.feed.publish(FeedEvent::Disconnected {
+        guild_id,
+        channel_id,
+        reason: reason.clone(),
+    });
+
+    let settings = player.settings().await;
+    let text_channel = player.announce_channel(&settings).await;
+    let channel = format!("<#{channel_id}>");
+    player
+        .notify(
+            text_channel,
+            Notice::Disconnected,
+            &[("channel", &channel), ("reason", reason.as_str())],
+        )
+        .await;
+
+    // the track would keep going without anyone hearing it
+    let interrupted = pause(&player).await;
+
+    let config = &state.config.reconnect;
+    let mut rejoined = None;
+    for attempt in 1..=config.attempts {
+        tokio::time::sleep(config.backoff(attempt)).await;
+
+        // somebody disconnected oxo or told it to leave in the meantime
+        if player.current_channel().await.is_none() {
+            info!("Left the channel while reconnecting, not rejoining");
+            state
+                .reconnects
+                .reconnecting
+                .lock()
+                .unwrap()
+                .remove(&guild_id);
+            return;
+        }
+
+        state.feed.publish(FeedEvent::Reconnecting {
+            guild_id,
+            channel_id,
+            attempt,
+        });
+        match GuildPlayer::join(&state, guild_id, channel_id).await {
+            Ok(player) => {
+                rejoined = Some(player);
+                break;
+            }
+            Err(err) => warn!(attempt, "Could not rejoin: {err}"),
+        }
+    }
+
+    match rejoined {
+        Some(player) => {
+            info!("Rejoined after the voice connection dropped");
+            METRICS.voice_reconnects.inc();
+            state.feed.publish(FeedEvent::Reconnected {
+                guild_id,
+                channel_id,
+            });
+
+            match interrupted {
+                Some((track, position)) => {
+                    resume(&player, &track, position);
+                    let position =
+                        format!("{}:{:02}", position.as_secs() / 60, position.as_secs() % 60);
+                    player
+                        .notify(text_channel, Notice::Continued, &[("position", &position)])
+                        .await;
+                }
+                None => player.notify(text_channel, Notice::Reconnected, &[]).await,
+            }
+        }
+        None => {
+            warn!(attempts = config.attempts, "Could not rejoin, giving up");
+            state.feed.publish(FeedEvent::ReconnectFailed {
+                guild_id,
+                channel_id,
+                attempts: config.attempts,
+            });
+
+            player.leave().await;
+            player
+                .notify(
+                    text_channel,
+                    Notice::ReconnectFailed,
+                    &[("channel", &channel)],
+                )
+                .await;
+        }
+    }
+
+    state
+        .reconnects
+        .reconnecting
+        .lock()
+        .unwrap()
+        .remove(&guild_id);
+}
+
+/// Pauses the queue, returning the current track and its position if it was playing.
+async fn pause(player: &GuildPlayer) -> Option<(TrackHandle, Duration)> {
+    let queue = player.queue();
+    let current = queue.read().current()?;
+    let info = current.get_info().await.ok()?;
+    if info.playing != PlayMode::Play {
+        return None;
+    }
+
+    if let Err(err) = queue.pause() {
+        warn!("Could not pause the queue while reconnecting: {err}");
+    }
+
+    Some((current, info.position))
+}
+
+/// Continues `track` at `position`, if it is still the current one.
+fn resume(player: &GuildPlayer, track: &TrackHandle, position: Duration) {
+    let queue = player.queue();
+    if queue.read().current().map(|current| current.uuid()) != Some(track.uuid()) {
+        return;
+    }
+
+    if let Err(err) = track.seek_time(position) {
+        warn!("Could not seek to where the track was: {err}");
+    }
+    if let Err(err) = queue.resume() {
+        warn!("Could not resume the queue: {err}");
+    }
+}
+
+/// What went wrong, in a way that can be posted in discord.
+fn describe(reason: Option<DisconnectReason>) -> String {
+    match reason {
+        Some(DisconnectReason::TimedOut) => "timed out".into(),
+        Some(DisconnectReason::Io) => "network error".into(),
+        Some(DisconnectReason::WsClosed(Some(CloseCode::Disconnected))) => {
+            "disconnected by discord".into()
+        }
+        Some(DisconnectReason::WsClosed(Some(CloseCode::VoiceServerCrash))) => {
+            "the voice server crashed".into()
+        }
+        Some(DisconnectReason::WsClosed(_)) => "the voice server closed the connection".into(),
+        Some(_) | None => "internal error".into(),
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use poise::serenity_prelude::{Command, GuildId, Http};
+use serde::Deserialize;
+use serde_json::{Number, Value};
+use tracing::info;
+
+use crate::{client::commands::PoiseCommand, error::Error};
+
+/// Where a set of slash commands is registered.
+#[derive(Debug, Clone, Copy)]
+pub enum Scope {
+    /// Every guild, changes can take a while to show up
+    Global,
+    /// A single guild, changes show up right away
+    Guild(GuildId),
+}
+
+/// The parts of a slash command that discord stores, used to find out whether
+/// the registered commands are still up to date.
+#[derive(Debug, PartialEq, Deserialize)]
+struct CommandShape {
+    #[serde(rename = "type", default = "chat_input")]
+    kind: u8,
+    name: String,
+    #[serde(default)]
+    name_localizations: Option<HashMap<String, String>>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    description_localizations: Option<HashMap<String, String>>,
+    #[serde(default)]
+    options: Vec<OptionShape>,
+    #[serde(default)]
+    default_member_permissions: Option<String>,
+    /// Discord treats a missing value as `true`
+    #[serde(default)]
+    dm_permission: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct OptionShape {
+    #[serde(rename = "type")]
+    kind: u8,
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    required: bool,
+    #[serde(default)]
+    choices: Vec<ChoiceShape>,
+    #[serde(default)]
+    options: Vec<OptionShape>,
+    #[serde(default)]
+    channel_types: Vec<u8>,
+    #[serde(default)]
+    min_value: Option<Number>,
+    #[serde(default)]
+    max_value: Option<Number>,
+    #[serde(default)]
+    min_length: Option<u16>,
+    #[serde(default)]
+    max_length: Option<u16>,
+    #[serde(default)]
+    autocomplete: bool,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct ChoiceShape {
+    name: String,
+    value: Value,
+}
+
+fn chat_input() -> u8 {
+    1
+}
+
+impl CommandShape {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        let mut shape: Self = serde_json::from_value(value)?;
+        shape.dm_permission = Some(shape.dm_permission.unwrap_or(true));
+
+        Ok(shape)
+    }
+}
+
+/// Registers `commands` in `scope`, unless exactly these commands are registered there already.
+/// Returns whether anything had to be registered.
+pub async fn sync_commands(
+    http: &Http,
+    commands: &[PoiseCommand],
+    scope: Scope,
+) -> Result<bool, Error> {
+    let registered = match scope {
+        Scope::Global => http.get_global_application_commands().await?,
+        Scope::Guild(guild_id) => guild_id.get_application_commands(http).await?,
+    };
+    let builder = poise::builtins::create_application_commands(commands);
+
+    let registered = shapes(
+        registered
+            .into_iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()?,
+    )?;
+    let wanted = shapes(builder.0.clone())?;
+
+    let changes = changes(&registered, &wanted);
+    if changes.is_empty() {
+        info!("Commands in {scope:?} are up to date");
+        return Ok(false);
+    }
+    for change in changes {
+        match change {
+            Change::Add(name) => info!("Registering new command /{name} in {scope:?}"),
+            Change::Update(name) => info!("Updating command /{name} in {scope:?}"),
+            Change::Remove(name) => info!("Removing command /{name} from {scope:?}"),
+        }
+    }
+
+    match scope {
+        Scope::Global => {
+            Command::set_global_application_commands(http, |commands| {
+                *commands = builder;
+                commands
+            })
+            .await?;
+        }
+        Scope::Guild(guild_id) => {
+            guild_id
+                .set_application_commands(http, |commands| {
+                    *commands = builder;
+                    commands
+                })
+                .await?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// The shapes of the commands in `values`, ordered by name.
+fn shapes(values: Vec<Value>) -> Result<Vec<CommandShape>, Error> {
+    let mut shapes = values
+        .into_iter()
+        .map(CommandShape::from_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    shapes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(shapes)
+}
+
+/// What registering the `wanted` commands changes about the `registered` ones.
+#[derive(Debug, PartialEq)]
+enum Change<'a> {
+    Add(&'a str),
+    Update(&'a str),
+    Remove(&'a str),
+}
+
+fn changes<'a>(registered: &'a [CommandShape], wanted: &'a [CommandShape]) -> Vec<Change<'a>> {
+    let find = |commands: &'a [CommandShape], name: &str| {
+        commands.iter().find(|command| command.name == name)
+    };
+
+    let mut changes = vec![];
+    for command in wanted {
+        match find(registered, &command.name) {
+            None => changes.push(Change::Add(&command.name)),
+            Some(registered) if registered != command => {
+                changes.push(Change::Update(&command.name))
+            }
+            Some(_) => {}
+        }
+    }
+    for command in registered {
+        if find(wanted, &command.name).is_none() {
+            changes.push(Change::Remove(&command.name));
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::client::commands::commands;
+
+    /// How discord returns `command` once it is registered: with ids and without defaults.
+    fn registered(mut command: Value) -> Value {
+        let fields = command.as_object_mut().unwrap();
+        fields.insert("id".into(), json!("1084950421254213724"));
+        fields.insert("application_id".into(), json!("1084950421254213700"));
+        fields.insert("version".into(), json!("1084950421254213725"));
+        if fields.get("dm_permission") == Some(&json!(true)) {
+            fields.remove("dm_permission");
+        }
+
+        command
+    }
+
+    #[test]
+    fn registered_commands_match_what_oxo_registers() {
+        let wanted = poise::builtins::create_application_commands(&commands().unwrap()).0;
+        let registered = wanted.iter().cloned().map(registered).collect();
+
+        let wanted = shapes(wanted).unwrap();
+        let registered = shapes(registered).unwrap();
+
+        assert_eq!(changes(&registered, &wanted), vec![]);
+    }
+
+    #[test]
+    fn changed_commands_are_found_by_name() {
+        let command = |name: &str, description: &str| json!({ "name": name, "description": description, "options": [] });
+        let registered = shapes(vec![
+            registered(command("play", "Play a track")),
+            registered(command("skip", "Skip the current track")),
+            registered(command("stop", "Stop playing")),
+        ])
+        .unwrap();
+        let wanted = shapes(vec![
+            command("play", "Play a track"),
+            command("skip", "Skip one or more tracks"),
+            command("queue", "Show the queue"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            changes(&registered, &wanted),
+            vec![
+                Change::Add("queue"),
+                Change::Update("skip"),
+                Change::Remove("stop")
+            ]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use songbird::{error::JoinError, tracks::TrackHandle};
+use tracing::{debug, info, warn};
+
+use crate::{
+    client::{
+        bot::State,
+        notifications::Notice,
+        player::{GuildPlayer, Request},
+        queue_ext::{Discarded, RequestChannel, Requester},
+        sources::lazy::QueueEntry,
+    },
+    error::Error,
+};
+
+const SNAPSHOTS_DOCUMENT: &str = "queue_snapshots";
+
+/// What a guild was playing when oxo shut down.
+#[derive(Debug, Serialize, Deserialize)]
+struct QueueSnapshot {
+    voice_channel: ChannelId,
+    /// Where the tracks were requested, if any of them was requested in a text channel
+    text_channel: Option<ChannelId>,
+    /// How far into the first track playback was
+    position_secs: u64,
+    tracks: Vec<TrackSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TrackSnapshot {
+    url: String,
+    requester: Option<UserId>,
+}
+
+/// Stops every queue and leaves all calls, saving the queues first if resuming is enabled.
+pub async fn drain(state: &State) {
+    let config = &state.config.shutdown;
+    let queues = state.queues.all();
+    let mut snapshots = HashMap::new();
+
+    for (guild_id, queue) in &queues {
+        let queue = queue.lock().await;
+        let tracks = queue.current_queue();
+        if tracks.is_empty() {
+            continue;
+        }
+
+        match snapshot(state, *guild_id, &tracks).await {
+            Some(snapshot) => {
+                if let Some(notice) = &config.notice {
+                    let settings = state.settings.get(*guild_id).await;
+                    let channel_id = settings.announce_channel.or(snapshot.text_channel);
+                    state
+                        .notifier
+                        .post(
+                            state,
+                            *guild_id,
+                            channel_id,
+                            &settings.notifications,
+                            notice.clone(),
+                            None,
+                        )
+                        .await;
+                }
+
+                snapshots.insert(*guild_id, snapshot);
+            }
+            None => warn!("Could not save the queue of guild {guild_id}"),
+        }
+
+        for track in &tracks {
+            track.typemap().write().await.insert::<Discarded>(());
+        }
+        queue.stop();
+    }
+
+    for (guild_id, _) in &queues {
+        match state.songbird_instance.remove(*guild_id).await {
+            // oxo is not in a call there
+            Ok(()) | Err(JoinError::NoCall) => {}
+            Err(err) => warn!("Could not leave the call in guild {guild_id}: {err}"),
+        }
+    }
+
+    if config.resume {
+        info!("Saving {} queues to resume them later", snapshots.len());
+
+        if let Err(err) = state
+            .storage
+            .save(&snapshots_document(state), &snapshots)
+            .await
+        {
+            warn!("Could not save the queues: {err}");
+        }
+    }
+}
+
+/// Every node of a cluster resumes its own queues.
+fn snapshots_document(state: &State) -> String {
+    match &state.cluster {
+        Some(cluster) => cluster.own_document(SNAPSHOTS_DOCUMENT),
+        None => SNAPSHOTS_DOCUMENT.to_string(),
+    }
+}
+
+/// Tracks without a url to queue them again are left out, `None` means nothing can be resumed.
+async fn snapshot(
+    state: &State,
+    guild_id: GuildId,
+    tracks: &[TrackHandle],
+) -> Option<QueueSnapshot> {
+    let call = state.songbird_instance.get(guild_id)?;
+    let voice_channel = ChannelId(call.lock().await.current_channel()?.0);
+
+    let mut text_channel = None;
+    let mut track_snapshots = vec![];
+    for track in tracks {
+        let typemap = track.typemap().read().await;
+        text_channel = text_channel.or(typemap.get::<RequestChannel>().copied());
+
+        let Some(url) = track.metadata().source_url.clone() else {
+            debug!(
+                "Not saving {:?} of guild {guild_id}, it has no url",
+                track.metadata().title
+            );
+            continue;
+        };
+        track_snapshots.push(TrackSnapshot {
+            url,
+            requester: typemap.get::<Requester>().copied(),
+        });
+    }
+    if track_snapshots.is_empty() {
+        return None;
+    }
+
+    // the position only belongs to the first saved track if that is the current one
+    let position = match tracks[0].metadata().source_url {
+        Some(_) => match tracks[0].get_info().await {
+            Ok(info) => info.position,
+            Err(_) => Duration::ZERO,
+        },
+        None => Duration::ZERO,
+    };
+
+    Some(QueueSnapshot {
+        voice_channel,
+        text_channel,
+        position_secs: position.as_secs(),
+        tracks: track_snapshots,
+    })
+}
+
+/// Re-queues everything that was saved by [`drain`] during the last shutdown.
+pub async fn resume(state: &Arc<State>) -> Result<(), Error> {
+    let document = snapshots_document(state);
+    let snapshots: HashMap<GuildId, QueueSnapshot> = state.storage.load(&document).await?;
+    if snapshots.is_empty() {
+        return Ok(());
+    }
+
+    // saved right away, so a queue that breaks oxo is not resumed on every start
+    state
+        .storage
+        .save(&document, &HashMap::<GuildId, QueueSnapshot>::new())
+        .await?;
+
+    for (guild_id, snapshot) in snapshots {
+        match resume_queue(state, guild_id, &snapshot).await {
+            Ok(()) => {
+                let settings = state.settings.get(guild_id).await;
+                let channel_id = settings.announce_channel.or(snapshot.text_channel);
+                state
+                    .notifier
+                    .notify(state, guild_id, channel_id, Notice::Resumed, &[])
+                    .await;
+            }
+            Err(err) => warn!("Could not resume the queue of guild {guild_id}: {err}"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn resume_queue(
+    state: &Arc<State>,
+    guild_id: GuildId,
+    snapshot: &QueueSnapshot,
+) -> Result<(), Error> {
+    let player = GuildPlayer::join(state, guild_id, snapshot.voice_channel).await?;
+    let settings = player.settings().await;
+
+    for (i, track) in snapshot.tracks.iter().enumerate() {
+        // mostly cached from before the restart
+        let entry = match state.resolvers.metadata(&track.url).await {
+            Ok(metadata) => QueueEntry::new(state.resolvers.clone(), &track.url, metadata).await,
+            Err(err) => Err(err),
+        };
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("Could not resume {}: {err}", track.url);
+                continue;
+            }
+        };
+
+        let request = Request {
+            requester: track.requester,
+            channel_id: snapshot.text_channel,
+        };
+        // put back the way it was, the limits were checked when it was queued
+        let queue = player.queue().lock().await;
+        let handle = player.add(&queue, entry, request, &settings).await;
+
+        // seeking instead of starting the stream there keeps the position of the track absolute,
+        // which chapters, segments, lyrics and crossfades rely on
+        if i == 0 && snapshot.position_secs > 0 {
+            if let Err(err) = handle.seek_time(Duration::from_secs(snapshot.position_secs)) {
+                warn!("Could not seek to where {} was: {err}", track.url);
+            }
+        }
+    }
+
+    info!(
+        "Resumed {} tracks in guild {guild_id}",
+        snapshot.tracks.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use poise::async_trait;
+
+use crate::error::AppError;
+
+use super::{Segment, SegmentCategory, SegmentProvider};
+
+/// Segments from a json file mapping video ids to their segments, e.g.
+/// `{"dQw4w9WgXcQ": [{"start": 0.0, "end": 12.5, "category": "intro"}]}`.
+///
+/// The file is read on every lookup, so it can be edited while oxo is running.
+#[derive(Debug)]
+pub struct LocalSegments {
+    path: PathBuf,
+}
+
+impl LocalSegments {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+#[async_trait]
+impl SegmentProvider for LocalSegments {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn segments(
+        &self,
+        video_id: &str,
+        _categories: &[SegmentCategory],
+    ) -> Result<Vec<Segment>, AppError> {
+        let unreadable = |err: &dyn std::fmt::Display| {
+            AppError::internal(format!("Could not read {}: {err}", self.path.display()))
+        };
+
+        let json = tokio::fs::read(&self.path)
+            .await
+            .map_err(|err| unreadable(&err))?;
+        let mut database: HashMap<String, Vec<Segment>> =
+            serde_json::from_slice(&json).map_err(|err| unreadable(&err))?;
+
+        Ok(database.remove(video_id).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn segments_are_read_from_the_database() {
+        let path = std::env::temp_dir().join(format!("oxo-segments-{}.json", std::process::id()));
+        tokio::fs::write(
+            &path,
+            r#"{"dQw4w9WgXcQ": [{"start": 0.0, "end": 12.5, "category": "intro"}]}"#,
+        )
+        .await
+        .unwrap();
+        let local = LocalSegments::new(&path);
+
+        let found = local.segments("dQw4w9WgXcQ", &[]).await.unwrap();
+        let missing = local.segments("jfKfPfyJRdk", &[]).await.unwrap();
+
+        assert_eq!(
+            found,
+            [Segment {
+                start: 0.0,
+                end: 12.5,
+                category: SegmentCategory::Intro
+            }]
+        );
+        assert!(missing.is_empty());
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
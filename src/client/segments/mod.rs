@@ -0,0 +1,384 @@
+//! Skips the parts of YouTube videos that are not music, like sponsor reads, intros and
+//! outros, for guilds that enabled `skip_segments`.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use poise::async_trait;
+use serde::{Deserialize, Serialize};
+use songbird::{tracks::TrackHandle, Event, EventContext, EventHandler};
+use tracing::{debug, warn};
+
+use crate::{config::Config, error::AppError};
+
+pub mod local;
+pub mod sponsorblock;
+
+/// How often the position of a track is checked against its segments.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Segments that end this close to the end of a track end the track right away.
+const END_TOLERANCE: Duration = Duration::from_secs(1);
+
+#[derive(
+    Debug, poise::ChoiceParameter, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentCategory {
+    #[name = "sponsor"]
+    Sponsor,
+    #[name = "intro"]
+    Intro,
+    #[name = "outro"]
+    Outro,
+    #[name = "non_music"]
+    NonMusic,
+}
+
+impl SegmentCategory {
+    pub const ALL: [Self; 4] = [Self::Sponsor, Self::Intro, Self::Outro, Self::NonMusic];
+
+    /// What SponsorBlock calls the category.
+    pub fn sponsorblock_name(self) -> &'static str {
+        match self {
+            Self::Sponsor => "sponsor",
+            Self::Intro => "intro",
+            Self::Outro => "outro",
+            Self::NonMusic => "music_offtopic",
+        }
+    }
+}
+
+/// A part of a video, in seconds from its start.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub category: SegmentCategory,
+}
+
+impl Segment {
+    pub fn start(&self) -> Duration {
+        Duration::from_secs_f64(self.start.max(0.0))
+    }
+
+    pub fn end(&self) -> Duration {
+        Duration::from_secs_f64(self.end.max(0.0))
+    }
+
+    fn contains(&self, position: Duration) -> bool {
+        self.start() <= position && position < self.end()
+    }
+}
+
+/// One source of segments, e.g. a local file or a SponsorBlock server.
+#[async_trait]
+pub trait SegmentProvider: Send + Sync {
+    /// Shown in logs.
+    fn name(&self) -> &'static str;
+
+    /// The segments of the YouTube video `video_id` in `categories`, empty if there are none.
+    async fn segments(
+        &self,
+        video_id: &str,
+        categories: &[SegmentCategory],
+    ) -> Result<Vec<Segment>, AppError>;
+}
+
+/// All providers, asked in order until one of them knows segments of a video.
+pub struct SegmentProviders(Vec<Box<dyn SegmentProvider>>);
+
+/// The segments that were skipped in a track, kept in its typemap.
+pub struct SkippedSegments;
+
+impl songbird::typemap::TypeMapKey for SkippedSegments {
+    type Value = Vec<Segment>;
+}
+
+impl SegmentProviders {
+    pub fn new(providers: Vec<Box<dyn SegmentProvider>>) -> Self {
+        Self(providers)
+    }
+
+    /// The providers enabled in `config`, the local database comes first.
+    pub fn from_config(config: &Config) -> Self {
+        let mut providers: Vec<Box<dyn SegmentProvider>> = vec![];
+
+        if let Some(path) = &config.segments.database {
+            providers.push(Box::new(local::LocalSegments::new(path)));
+        }
+        if let Some(url) = &config.segments.sponsorblock_url {
+            providers.push(Box::new(sponsorblock::SponsorBlock::new(url)));
+        }
+
+        Self::new(providers)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The segments of `video_id` in `categories` of the first provider that has any.
+    pub async fn find(&self, video_id: &str, categories: &[SegmentCategory]) -> Vec<Segment> {
+        for provider in &self.0 {
+            debug!(video_id, provider = provider.name(), "Looking up segments");
+            match provider.segments(video_id, categories).await {
+                Ok(segments) if segments.is_empty() => {}
+                Ok(mut segments) => {
+                    segments.retain(|segment| {
+                        categories.contains(&segment.category) && segment.start < segment.end
+                    });
+                    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+                    return segments;
+                }
+                Err(err) => warn!(
+                    provider = provider.name(),
+                    "Could not look up segments: {err}"
+                ),
+            }
+        }
+
+        vec![]
+    }
+
+    /// Skips the segments in `categories` of `track`, if it is a YouTube video.
+    pub fn watch(self: &Arc<Self>, track: &TrackHandle, categories: &[SegmentCategory]) {
+        if self.is_empty() || categories.is_empty() {
+            return;
+        }
+        let Some(video_id) = track.metadata().source_url.as_deref().and_then(youtube_id) else {
+            return;
+        };
+
+        let skipper = SegmentSkipper {
+            providers: self.clone(),
+            video_id: video_id.to_string(),
+            categories: categories.to_vec(),
+            looked_up: Default::default(),
+            segments: Default::default(),
+        };
+        if let Err(err) = track.add_event(Event::Periodic(CHECK_INTERVAL, None), skipper) {
+            warn!("Could not watch the segments of a track: {err}");
+        }
+    }
+}
+
+impl fmt::Debug for SegmentProviders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|provider| provider.name()))
+            .finish()
+    }
+}
+
+/// The segments of `track` that were skipped so far.
+pub async fn skipped_segments(track: &TrackHandle) -> Vec<Segment> {
+    track
+        .typemap()
+        .read()
+        .await
+        .get::<SkippedSegments>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Seeks past segments while a track plays. The segments are looked up once the track
+/// started, queued tracks don't need them yet.
+struct SegmentSkipper {
+    providers: Arc<SegmentProviders>,
+    video_id: String,
+    categories: Vec<SegmentCategory>,
+    looked_up: Arc<AtomicBool>,
+    segments: Arc<OnceLock<Vec<Segment>>>,
+}
+
+#[async_trait]
+impl EventHandler for SegmentSkipper {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let [(track_state, handle)] = **tracks else {
+            return None;
+        };
+
+        // looking up the segments would hold up every other event of the call
+        if !self.looked_up.swap(true, Ordering::Relaxed) {
+            let (providers, video_id) = (self.providers.clone(), self.video_id.clone());
+            let (categories, segments) = (self.categories.clone(), self.segments.clone());
+            tokio::spawn(async move {
+                let found = providers.find(&video_id, &categories).await;
+                debug!(video_id, count = found.len(), "Found segments to skip");
+                if segments.set(found).is_err() {
+                    warn!(video_id, "The segments to skip were looked up twice");
+                }
+            });
+        }
+        let segments = self.segments.get()?;
+        if segments.is_empty() {
+            return Some(Event::Cancel);
+        }
+
+        let skipped = skipped_segments(handle).await;
+        let segment = segment_to_skip(segments, &skipped, track_state.position)?;
+        debug!(?segment, "Skipping a segment");
+
+        let ends_track = handle
+            .metadata()
+            .duration
+            .is_some_and(|duration| segment.end() + END_TOLERANCE >= duration);
+        let skip = match ends_track {
+            true => handle.stop(),
+            false => handle.seek_time(segment.end()),
+        };
+        match skip {
+            Ok(()) => handle
+                .typemap()
+                .write()
+                .await
+                .entry::<SkippedSegments>()
+                .or_default()
+                .push(*segment),
+            Err(err) => warn!("Could not skip a segment: {err}"),
+        }
+
+        None
+    }
+}
+
+/// The segment `position` is in, unless it was skipped already. Every segment is only skipped
+/// once, so seeking back into one plays it.
+fn segment_to_skip<'a>(
+    segments: &'a [Segment],
+    skipped: &[Segment],
+    position: Duration,
+) -> Option<&'a Segment> {
+    segments
+        .iter()
+        .find(|segment| segment.contains(position) && !skipped.contains(segment))
+}
+
+/// The id of the YouTube video at `url`.
+pub fn youtube_id(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let (host, path) = rest.split_once('/')?;
+    let host = host.trim_start_matches("www.").trim_start_matches("m.");
+
+    let id = match host {
+        "youtu.be" => path.split(['?', '#']).next(),
+        "youtube.com" | "music.youtube.com" => match path.split_once('?') {
+            Some(("watch", query)) => query
+                .split(['&', '#'])
+                .find_map(|param| param.strip_prefix("v=")),
+            _ => path
+                .strip_prefix("shorts/")
+                .or_else(|| path.strip_prefix("live/"))
+                .and_then(|id| id.split(['?', '#', '/']).next()),
+        },
+        _ => None,
+    }?;
+
+    let is_id = id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    is_id.then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Stub(Vec<Segment>);
+
+    #[async_trait]
+    impl SegmentProvider for Stub {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        async fn segments(
+            &self,
+            _video_id: &str,
+            _categories: &[SegmentCategory],
+        ) -> Result<Vec<Segment>, AppError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn segment(start: f64, end: f64, category: SegmentCategory) -> Segment {
+        Segment {
+            start,
+            end,
+            category,
+        }
+    }
+
+    #[test]
+    fn youtube_ids_are_found_in_every_kind_of_link() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&list=RDAMVM",
+            "https://m.youtube.com/watch?feature=share&v=dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?t=42",
+            "https://youtube.com/shorts/dQw4w9WgXcQ",
+        ] {
+            assert_eq!(youtube_id(url), Some("dQw4w9WgXcQ"), "{url}");
+        }
+
+        for url in [
+            "https://www.youtube.com/playlist?list=PL123",
+            "https://soundcloud.com/watch?v=dQw4w9WgXcQ",
+            "local:music/a.mp3",
+        ] {
+            assert_eq!(youtube_id(url), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn segments_are_only_skipped_once() {
+        let segments = [
+            segment(0.0, 10.0, SegmentCategory::Intro),
+            segment(60.0, 90.0, SegmentCategory::Sponsor),
+        ];
+        let at = Duration::from_secs;
+
+        assert_eq!(segment_to_skip(&segments, &[], at(5)), Some(&segments[0]));
+        assert_eq!(segment_to_skip(&segments, &[], at(30)), None);
+        assert_eq!(segment_to_skip(&segments, &[], at(90)), None);
+        assert_eq!(segment_to_skip(&segments[..], &segments[..1], at(5)), None);
+    }
+
+    #[tokio::test]
+    async fn only_enabled_categories_are_found() {
+        let providers = SegmentProviders::new(vec![
+            Box::new(Stub(vec![])),
+            Box::new(Stub(vec![
+                segment(200.0, 230.0, SegmentCategory::Outro),
+                segment(0.0, 12.0, SegmentCategory::Intro),
+                segment(50.0, 80.0, SegmentCategory::Sponsor),
+            ])),
+        ]);
+
+        let found = providers
+            .find(
+                "dQw4w9WgXcQ",
+                &[SegmentCategory::Intro, SegmentCategory::Outro],
+            )
+            .await;
+
+        assert_eq!(
+            found,
+            [
+                segment(0.0, 12.0, SegmentCategory::Intro),
+                segment(200.0, 230.0, SegmentCategory::Outro),
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use poise::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::error::AppError;
+
+use super::{Segment, SegmentCategory, SegmentProvider};
+
+/// Sent to SponsorBlock so its operators know who is asking.
+const USER_AGENT: &str = "oxo (https://github.com/Giftzwerg02/oxo)";
+
+/// A [SponsorBlock](https://sponsor.ajay.app) server, or anything that speaks its api.
+#[derive(Debug)]
+pub struct SponsorBlock {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    category: String,
+    segment: [f64; 2],
+}
+
+impl SponsorBlock {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl SegmentProvider for SponsorBlock {
+    fn name(&self) -> &'static str {
+        "sponsorblock"
+    }
+
+    async fn segments(
+        &self,
+        video_id: &str,
+        categories: &[SegmentCategory],
+    ) -> Result<Vec<Segment>, AppError> {
+        let names = categories
+            .iter()
+            .map(|category| category.sponsorblock_name())
+            .collect::<Vec<_>>();
+        let categories = serde_json::to_string(&names).expect("names are serializable");
+
+        let response = self
+            .client
+            .get(format!("{}/api/skipSegments", self.url))
+            .query(&[
+                ("videoID", video_id),
+                ("categories", &categories),
+                ("actionType", "skip"),
+            ])
+            .header(reqwest::header::USER_AGENT, USER_AGENT)
+            .send()
+            .await
+            .map_err(unreachable)?;
+        // there are no segments for the video
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+
+        let records: Vec<Record> = response
+            .error_for_status()
+            .map_err(unreachable)?
+            .json()
+            .await
+            .map_err(unreachable)?;
+
+        Ok(records.iter().filter_map(to_segment).collect())
+    }
+}
+
+fn to_segment(record: &Record) -> Option<Segment> {
+    let category = SegmentCategory::ALL
+        .into_iter()
+        .find(|category| category.sponsorblock_name() == record.category)?;
+    let [start, end] = record.segment;
+
+    Some(Segment {
+        start,
+        end,
+        category,
+    })
+}
+
+fn unreachable(err: reqwest::Error) -> AppError {
+    AppError::internal(format!("Could not reach SponsorBlock: {err}"))
+}
@@ -0,0 +1,487 @@
+use std::{collections::HashMap, fmt::Display, time::Duration};
+
+use poise::serenity_prelude::{ChannelId, GuildId, Mutex, RoleId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    client::{
+        bot::LoopMode, limits::Limits, notifications::NotificationSettings,
+        segments::SegmentCategory, sources::local::LOCAL_PREFIX,
+    },
+    error::{AppError, Error},
+    storage::Storage,
+};
+
+const SETTINGS_DOCUMENT: &str = "guild_settings";
+
+/// The locales Discord supports, see <https://discord.com/developers/docs/reference#locales>.
+pub const LOCALES: [&str; 31] = [
+    "id", "da", "de", "en-GB", "en-US", "es-ES", "fr", "hr", "it", "lt", "hu", "nl", "no", "pl",
+    "pt-BR", "ro", "fi", "sv-SE", "vi", "tr", "cs", "el", "bg", "ru", "uk", "hi", "th", "zh-CN",
+    "ja", "zh-TW", "ko",
+];
+
+/// Everything a guild can configure about oxo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// In percent, applied to every newly queued track
+    pub default_volume: u8,
+    /// If set, only members with this role may change what is playing
+    pub dj_role: Option<RoleId>,
+    /// Where notifications are posted, defaults to the channel a track was queued from
+    pub announce_channel: Option<ChannelId>,
+    /// How long to wait in an empty channel before leaving, `None` leaves right away
+    #[serde(with = "optional_secs", rename = "idle_timeout_secs")]
+    pub idle_timeout: Option<Duration>,
+    pub loop_default: LoopMode,
+    /// How long tracks fade into each other, `None` plays them one after the other
+    #[serde(with = "optional_secs", rename = "crossfade_secs")]
+    pub crossfade: Option<Duration>,
+    /// Keep playing related tracks once the queue runs out
+    pub autoplay: bool,
+    /// Parts of YouTube videos to skip, empty plays them whole
+    pub skip_segments: Vec<SegmentCategory>,
+    /// Domains tracks may be played from, `local` for the media directory, empty allows everything
+    pub allowed_sources: Vec<String>,
+    /// One of [`LOCALES`]
+    pub locale: String,
+    pub limits: Limits,
+    pub notifications: NotificationSettings,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            default_volume: 100,
+            dj_role: None,
+            announce_channel: None,
+            idle_timeout: None,
+            loop_default: LoopMode::Off,
+            crossfade: None,
+            autoplay: false,
+            skip_segments: vec![],
+            allowed_sources: vec![],
+            locale: "en-US".into(),
+            limits: Limits::default(),
+            notifications: NotificationSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, poise::ChoiceParameter, Clone, Copy)]
+pub enum SettingKey {
+    #[name = "default_volume"]
+    DefaultVolume,
+    #[name = "dj_role"]
+    DjRole,
+    #[name = "announce_channel"]
+    AnnounceChannel,
+    #[name = "idle_timeout_secs"]
+    IdleTimeout,
+    #[name = "loop_default"]
+    LoopDefault,
+    #[name = "crossfade_secs"]
+    Crossfade,
+    #[name = "autoplay"]
+    Autoplay,
+    #[name = "skip_segments"]
+    SkipSegments,
+    #[name = "max_queue"]
+    MaxQueue,
+    #[name = "allowed_sources"]
+    AllowedSources,
+    #[name = "locale"]
+    Locale,
+}
+
+impl GuildSettings {
+    pub const MAX_VOLUME: u8 = 200;
+    /// Anything longer plays two whole songs over each other.
+    pub const MAX_CROSSFADE: Duration = Duration::from_secs(10);
+
+    /// Parses `value` into the setting `key`. `none` clears optional settings.
+    pub fn set(&mut self, key: SettingKey, value: &str) -> Result<(), AppError> {
+        let value = value.trim();
+        let cleared = value.eq_ignore_ascii_case("none");
+
+        match key {
+            SettingKey::DefaultVolume => self.default_volume = parse(key, value)?,
+            SettingKey::DjRole if cleared => self.dj_role = None,
+            SettingKey::DjRole => self.dj_role = Some(RoleId(parse_id(key, value)?)),
+            SettingKey::AnnounceChannel if cleared => self.announce_channel = None,
+            SettingKey::AnnounceChannel => {
+                self.announce_channel = Some(ChannelId(parse_id(key, value)?))
+            }
+            SettingKey::IdleTimeout if cleared => self.idle_timeout = None,
+            SettingKey::IdleTimeout => {
+                self.idle_timeout = Some(parse(key, value)?)
+                    .filter(|secs| *secs > 0)
+                    .map(Duration::from_secs)
+            }
+            SettingKey::LoopDefault => {
+                self.loop_default = value
+                    .parse()
+                    .map_err(|_| invalid_value(key, value, "off, track or queue"))?
+            }
+            SettingKey::Crossfade if cleared => self.crossfade = None,
+            SettingKey::Crossfade => {
+                self.crossfade = Some(parse(key, value)?)
+                    .filter(|secs| *secs > 0)
+                    .map(Duration::from_secs)
+            }
+            SettingKey::Autoplay => {
+                self.autoplay = match value.to_lowercase().as_str() {
+                    "on" | "true" | "yes" => true,
+                    "off" | "false" | "no" => false,
+                    _ => return Err(invalid_value(key, value, "on or off")),
+                }
+            }
+            SettingKey::SkipSegments if cleared => self.skip_segments = vec![],
+            SettingKey::SkipSegments if value.eq_ignore_ascii_case("all") => {
+                self.skip_segments = SegmentCategory::ALL.to_vec()
+            }
+            SettingKey::SkipSegments => {
+                self.skip_segments = vec![];
+                for category in value.split(',').map(str::trim) {
+                    let category = category.parse().map_err(|_| {
+                        invalid_value(
+                            key,
+                            category,
+                            "sponsor, intro, outro, non_music, all or none",
+                        )
+                    })?;
+                    if !self.skip_segments.contains(&category) {
+                        self.skip_segments.push(category);
+                    }
+                }
+            }
+            SettingKey::MaxQueue if cleared => self.limits.max_queue_len = None,
+            SettingKey::MaxQueue => {
+                self.limits.max_queue_len = Some(parse(key, value)?).filter(|n| *n > 0)
+            }
+            SettingKey::AllowedSources if cleared => self.allowed_sources = vec![],
+            SettingKey::AllowedSources => {
+                self.allowed_sources = value
+                    .split(',')
+                    .map(|domain| domain.trim().to_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                    .collect()
+            }
+            SettingKey::Locale => {
+                self.locale = LOCALES
+                    .into_iter()
+                    .find(|locale| locale.eq_ignore_ascii_case(value))
+                    .ok_or_else(|| invalid_value(key, value, "a locale like en-US, de or pt-BR"))?
+                    .to_string()
+            }
+        }
+
+        self.validate()
+    }
+
+    /// Resets the setting `key` to its value in `defaults`.
+    pub fn reset(&mut self, key: SettingKey, defaults: &GuildSettings) {
+        let defaults = defaults.clone();
+
+        match key {
+            SettingKey::DefaultVolume => self.default_volume = defaults.default_volume,
+            SettingKey::DjRole => self.dj_role = defaults.dj_role,
+            SettingKey::AnnounceChannel => self.announce_channel = defaults.announce_channel,
+            SettingKey::IdleTimeout => self.idle_timeout = defaults.idle_timeout,
+            SettingKey::LoopDefault => self.loop_default = defaults.loop_default,
+            SettingKey::Crossfade => self.crossfade = defaults.crossfade,
+            SettingKey::Autoplay => self.autoplay = defaults.autoplay,
+            SettingKey::SkipSegments => self.skip_segments = defaults.skip_segments,
+            SettingKey::MaxQueue => self.limits.max_queue_len = defaults.limits.max_queue_len,
+            SettingKey::AllowedSources => self.allowed_sources = defaults.allowed_sources,
+            SettingKey::Locale => self.locale = defaults.locale,
+        }
+    }
+
+    /// Applies a (partial) json object on top of these settings, like `PATCH` would.
+    pub fn patch(&self, patch: Value) -> Result<Self, AppError> {
+        let mut value = serde_json::to_value(self).expect("settings are always serializable");
+        merge(&mut value, patch);
+
+        let patched: Self = serde_json::from_value(value)
+            .map_err(|err| AppError::invalid_input(err.to_string()))?;
+        patched.validate()?;
+
+        Ok(patched)
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.default_volume > Self::MAX_VOLUME {
+            return Err(AppError::invalid_input(format!(
+                "The default volume can be at most {}%",
+                Self::MAX_VOLUME
+            )));
+        }
+
+        if self
+            .crossfade
+            .is_some_and(|crossfade| crossfade > Self::MAX_CROSSFADE)
+        {
+            return Err(AppError::invalid_input(format!(
+                "Tracks can fade into each other for at most {} seconds",
+                Self::MAX_CROSSFADE.as_secs()
+            )));
+        }
+
+        if !LOCALES.contains(&self.locale.as_str()) {
+            return Err(AppError::invalid_input(format!(
+                "`{}` is not a locale Discord supports, try one of {}",
+                self.locale,
+                LOCALES.join(", ")
+            )));
+        }
+
+        self.notifications.validate()?;
+
+        Ok(())
+    }
+
+    /// Checks `url` against the allowed sources of this guild.
+    pub fn check_source(&self, url: &str) -> Result<(), AppError> {
+        if self.allowed_sources.is_empty() {
+            return Ok(());
+        }
+
+        // files from the media directory can be allowed with `local`
+        let host = match url.strip_prefix(LOCAL_PREFIX) {
+            Some(_) => "local".to_string(),
+            None => url
+                .split_once("://")
+                .map_or(url, |(_, rest)| rest)
+                .split(['/', '?', '#'])
+                .next()
+                .unwrap_or_default()
+                .to_lowercase(),
+        };
+
+        let allowed = self
+            .allowed_sources
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{domain}")));
+
+        match allowed {
+            true => Ok(()),
+            false => Err(AppError::forbidden(format!(
+                "Only tracks from {} can be played in this server",
+                self.allowed_sources.join(", ")
+            ))),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.default_volume as f32 / 100.0
+    }
+}
+
+/// All guild settings, kept in memory and written through to storage on every change.
+/// Guilds that never changed anything use the configured defaults.
+///
+/// Every change re-reads storage while holding its lock, so nodes sharing it don't undo each
+/// other's changes, see [`crate::cluster`].
+#[derive(Debug)]
+pub struct SettingsStore {
+    storage: Storage,
+    defaults: GuildSettings,
+    settings: Mutex<HashMap<GuildId, GuildSettings>>,
+}
+
+impl SettingsStore {
+    pub async fn load(storage: Storage, defaults: GuildSettings) -> Result<Self, Error> {
+        let settings = storage.load(SETTINGS_DOCUMENT).await?;
+
+        Ok(Self {
+            storage,
+            defaults,
+            settings: Mutex::new(settings),
+        })
+    }
+
+    pub fn defaults(&self) -> &GuildSettings {
+        &self.defaults
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> GuildSettings {
+        let settings = self.settings.lock().await;
+
+        settings
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_else(|| self.defaults.clone())
+    }
+
+    /// Modifies the settings of a guild and persists them, unless `f` fails.
+    pub async fn update(
+        &self,
+        guild_id: GuildId,
+        f: impl FnOnce(&mut GuildSettings) -> Result<(), AppError>,
+    ) -> Result<GuildSettings, Error> {
+        let mut settings = self.settings.lock().await;
+        let _lock = self.storage.lock(SETTINGS_DOCUMENT).await?;
+        *settings = self.storage.load(SETTINGS_DOCUMENT).await?;
+
+        let mut guild_settings = settings
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_else(|| self.defaults.clone());
+        f(&mut guild_settings)?;
+        settings.insert(guild_id, guild_settings.clone());
+
+        self.storage.save(SETTINGS_DOCUMENT, &*settings).await?;
+
+        Ok(guild_settings)
+    }
+
+    /// Picks up changes made by other nodes.
+    pub async fn reload(&self) -> Result<(), Error> {
+        let mut settings = self.settings.lock().await;
+        *settings = self.storage.load(SETTINGS_DOCUMENT).await?;
+
+        Ok(())
+    }
+
+    /// The settings of every guild that changed something.
+    pub async fn all(&self) -> HashMap<GuildId, GuildSettings> {
+        self.settings.lock().await.clone()
+    }
+
+    /// Replaces the settings of all guilds at once, e.g. with a backup.
+    pub async fn replace_all(
+        &self,
+        new_settings: HashMap<GuildId, GuildSettings>,
+    ) -> Result<(), Error> {
+        for (guild_id, guild_settings) in &new_settings {
+            guild_settings.validate().map_err(|err| {
+                AppError::invalid_input(format!("Guild {guild_id}: {}", err.message()))
+            })?;
+        }
+
+        // nobody else may change settings in between, like in `update`
+        let mut settings = self.settings.lock().await;
+        let _lock = self.storage.lock(SETTINGS_DOCUMENT).await?;
+        *settings = new_settings;
+
+        self.storage.save(SETTINGS_DOCUMENT, &*settings).await
+    }
+
+    pub async fn reset(&self, guild_id: GuildId) -> Result<(), Error> {
+        let mut settings = self.settings.lock().await;
+        let _lock = self.storage.lock(SETTINGS_DOCUMENT).await?;
+        *settings = self.storage.load(SETTINGS_DOCUMENT).await?;
+
+        settings.remove(&guild_id);
+
+        self.storage.save(SETTINGS_DOCUMENT, &*settings).await
+    }
+}
+
+fn parse<T: std::str::FromStr>(key: SettingKey, value: &str) -> Result<T, AppError> {
+    value
+        .parse()
+        .map_err(|_| invalid_value(key, value, "a positive number"))
+}
+
+/// Accepts raw ids as well as role (`<@&id>`) and channel (`<#id>`) mentions.
+fn parse_id(key: SettingKey, value: &str) -> Result<u64, AppError> {
+    value
+        .trim_start_matches("<@&")
+        .trim_start_matches("<#")
+        .trim_end_matches('>')
+        .parse()
+        .map_err(|_| invalid_value(key, value, "a mention, an id or none"))
+}
+
+fn invalid_value(key: SettingKey, value: &str, expected: impl Display) -> AppError {
+    AppError::invalid_input(format!(
+        "`{value}` is not a valid value for {key}, expected {expected}"
+    ))
+}
+
+fn merge(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+/// (De-)serializes an `Option<Duration>` as whole seconds.
+pub mod optional_secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn locales_must_be_supported() {
+        let mut settings = GuildSettings::default();
+
+        settings.set(SettingKey::Locale, "pt-br").unwrap();
+        assert_eq!(settings.locale, "pt-BR");
+        assert!(settings.set(SettingKey::Locale, "klingon").is_err());
+        assert!(settings.set(SettingKey::Locale, "pt").is_err());
+
+        settings.reset(SettingKey::Locale, &GuildSettings::default());
+        assert_eq!(settings.locale, "en-US");
+
+        assert_eq!(
+            settings.patch(json!({"locale": "de"})).unwrap().locale,
+            "de"
+        );
+        assert!(settings.patch(json!({"locale": "de-DE"})).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replacing_everything_waits_for_other_nodes() {
+        let dir = std::env::temp_dir().join(format!("oxo-settings-{}", std::process::id()));
+        let storage = Storage::new(&dir);
+        let store = SettingsStore::load(storage.clone(), GuildSettings::default())
+            .await
+            .unwrap();
+
+        // another node is changing the settings
+        let lock = storage.lock(SETTINGS_DOCUMENT).await.unwrap();
+        let backup = HashMap::from([(GuildId(1), GuildSettings::default())]);
+        let replace = tokio::spawn(async move { store.replace_all(backup).await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!replace.is_finished());
+
+        drop(lock);
+        replace.await.unwrap();
+        let saved: HashMap<GuildId, GuildSettings> = storage.load(SETTINGS_DOCUMENT).await.unwrap();
+        assert!(saved.contains_key(&GuildId(1)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
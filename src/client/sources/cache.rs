@@ -0,0 +1,390 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeZone, Utc};
+use poise::serenity_prelude::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use songbird::input::Metadata;
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::{
+    client::chapters::{self, Chapter},
+    config::{CacheConfig, ClusterConfig},
+    error::Error,
+    metrics::METRICS,
+    shutdown::Shutdown,
+    storage::Storage,
+};
+
+const CACHE_DOCUMENT: &str = "metadata_cache";
+
+/// Changes are collected for this long before the cache is saved, so resolving a whole
+/// playlist only causes a single save.
+const SAVE_DELAY: Duration = Duration::from_secs(30);
+
+/// Query parameters that only track where a link was shared, they never change the track.
+const TRACKING_PARAMS: [&str; 6] = ["si", "feature", "fbclid", "ref", "pp", "utm_"];
+
+/// Hosts that all serve the same YouTube videos.
+const YOUTUBE_HOSTS: [&str; 4] = [
+    "youtube.com",
+    "m.youtube.com",
+    "music.youtube.com",
+    "youtu.be",
+];
+
+/// What yt-dlp found out about an url, so it doesn't have to be asked again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedTrack {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration_secs: Option<f64>,
+    pub thumbnail: Option<String>,
+    pub source_url: Option<String>,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    /// Only set if yt-dlp picked a single format, which ffmpeg can stream without yt-dlp
+    pub stream: Option<CachedStream>,
+    resolved_at: DateTime<Utc>,
+    last_used: DateTime<Utc>,
+}
+
+/// The direct url of the audio, these expire after a few hours.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedStream {
+    pub url: String,
+    /// Headers yt-dlp would have sent when downloading the stream
+    pub headers: Vec<(String, String)>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl CachedTrack {
+    /// Reads the output of `yt-dlp --print-json`.
+    pub fn from_ytdl_output(value: &Value, stream_ttl: Duration) -> Self {
+        let metadata = Metadata::from_ytdl_output(value.clone());
+        let now = Utc::now();
+
+        let stream = value["url"].as_str().map(|url| {
+            let headers = value["http_headers"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect();
+
+            CachedStream {
+                url: url.to_string(),
+                headers,
+                expires_at: stream_expiry(url, now, stream_ttl),
+            }
+        });
+
+        Self {
+            title: metadata.title,
+            artist: metadata.artist,
+            duration_secs: metadata.duration.map(|duration| duration.as_secs_f64()),
+            thumbnail: metadata.thumbnail,
+            source_url: metadata.source_url,
+            chapters: chapters::from_ytdl_output(value),
+            stream,
+            resolved_at: now,
+            last_used: now,
+        }
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            duration: self.duration_secs.map(Duration::from_secs_f64),
+            thumbnail: self.thumbnail.clone(),
+            source_url: self.source_url.clone(),
+            channels: Some(2),
+            ..Default::default()
+        }
+    }
+
+    /// The stream url, unless it expired already.
+    pub fn live_stream(&self) -> Option<&CachedStream> {
+        self.stream
+            .as_ref()
+            .filter(|stream| stream.expires_at > Utc::now())
+    }
+}
+
+/// Remembers the metadata and stream urls yt-dlp resolved, keyed by [`normalize_url`].
+///
+/// Entries are dropped after [`CacheConfig::metadata_ttl_secs`], and the least recently
+/// used ones once there are more than [`CacheConfig::max_entries`].
+#[derive(Debug)]
+pub struct MetadataCache {
+    config: CacheConfig,
+    storage: Storage,
+    /// Every node of a cluster keeps its own cache
+    document: String,
+    entries: Mutex<HashMap<String, CachedTrack>>,
+    /// Notified whenever an entry is added, see [`MetadataCache::keep_saved`]
+    changed: Notify,
+}
+
+impl MetadataCache {
+    /// An empty cache, use [`MetadataCache::load`] to continue with the saved one.
+    pub fn new(storage: Storage, config: CacheConfig) -> Self {
+        Self {
+            config,
+            storage,
+            document: CACHE_DOCUMENT.to_string(),
+            entries: Default::default(),
+            changed: Notify::new(),
+        }
+    }
+
+    pub async fn load(
+        storage: Storage,
+        config: CacheConfig,
+        cluster: &ClusterConfig,
+    ) -> Result<Self, Error> {
+        let cache = Self {
+            document: cluster.own_document(CACHE_DOCUMENT),
+            ..Self::new(storage, config)
+        };
+        if cache.config.enabled {
+            let mut entries = cache.entries.lock().await;
+            *entries = cache.storage.load(&cache.document).await?;
+            cache.evict(&mut entries);
+        }
+
+        Ok(cache)
+    }
+
+    /// The cached entry of `url`, counted as a hit or a miss.
+    pub async fn get(&self, url: &str) -> Option<CachedTrack> {
+        if !self.config.enabled {
+            return None;
+        }
+        let key = normalize_url(url)?;
+
+        let mut entries = self.entries.lock().await;
+        let entry = entries
+            .get_mut(&key)
+            .filter(|entry| Utc::now() - entry.resolved_at < self.metadata_ttl());
+
+        match entry {
+            Some(entry) => {
+                METRICS.cache_hits.inc();
+                entry.last_used = Utc::now();
+                Some(entry.clone())
+            }
+            None => {
+                METRICS.cache_misses.inc();
+                None
+            }
+        }
+    }
+
+    /// Caches `track` for `url` and for the url yt-dlp reported, if that is a different one.
+    pub async fn insert(&self, url: &str, track: CachedTrack) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let keys = [Some(url), track.source_url.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter_map(normalize_url)
+            .collect::<Vec<_>>();
+
+        let mut entries = self.entries.lock().await;
+        for key in keys {
+            entries.insert(key, track.clone());
+        }
+        self.evict(&mut entries);
+        self.changed.notify_one();
+    }
+
+    /// Saves the cache shortly after it changed, until oxo shuts down. The last changes are
+    /// saved by a final [`MetadataCache::save`] on shutdown.
+    pub async fn keep_saved(self: Arc<Self>, shutdown: Shutdown) {
+        if !self.config.enabled {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                _ = self.changed.notified() => {}
+            }
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                _ = tokio::time::sleep(SAVE_DELAY) => {}
+            }
+
+            self.save().await;
+        }
+    }
+
+    /// Persists the cache, so it survives restarts.
+    pub async fn save(&self) {
+        if !self.config.enabled {
+            return;
+        }
+
+        // holding the lock keeps two saves from writing at the same time
+        let entries = self.entries.lock().await;
+        if let Err(err) = self.storage.save(&self.document, &*entries).await {
+            warn!("Could not save the metadata cache: {err}");
+        }
+    }
+
+    pub async fn len(&self) -> usize {
+        self.entries.lock().await.len()
+    }
+
+    pub fn stream_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.stream_ttl_secs)
+    }
+
+    fn metadata_ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.config.metadata_ttl_secs as i64)
+    }
+
+    fn evict(&self, entries: &mut HashMap<String, CachedTrack>) {
+        let now = Utc::now();
+        entries.retain(|_, entry| now - entry.resolved_at < self.metadata_ttl());
+
+        if entries.len() > self.config.max_entries {
+            let mut by_use = entries
+                .iter()
+                .map(|(key, entry)| (entry.last_used, key.clone()))
+                .collect::<Vec<_>>();
+            by_use.sort();
+
+            let excess = entries.len() - self.config.max_entries;
+            for (_, key) in by_use.into_iter().take(excess) {
+                entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// Expires streams a minute before the `expire` timestamp YouTube puts into its stream urls,
+/// or after `ttl`, whatever comes first.
+fn stream_expiry(url: &str, now: DateTime<Utc>, ttl: Duration) -> DateTime<Utc> {
+    let by_ttl = now + chrono::Duration::from_std(ttl).unwrap_or_else(|_| chrono::Duration::zero());
+
+    let by_url = url
+        .split(['?', '&', '/'])
+        .find_map(|part| part.strip_prefix("expire="))
+        .and_then(|expire| expire.parse().ok())
+        .and_then(|expire| Utc.timestamp_opt(expire, 0).single())
+        .map(|expire| expire - chrono::Duration::seconds(60));
+
+    by_url.map_or(by_ttl, |by_url| by_url.min(by_ttl))
+}
+
+/// Turns the different links to the same track into one cache key, e.g. `youtu.be/<id>` and
+/// `www.youtube.com/watch?v=<id>&si=...` both become `youtube:<id>`. Returns `None` for
+/// anything that isn't an url, like searches.
+pub fn normalize_url(url: &str) -> Option<String> {
+    let rest = url
+        .trim()
+        .strip_prefix("https://")
+        .or_else(|| url.trim().strip_prefix("http://"))?;
+    let rest = rest.split('#').next().unwrap_or_default();
+
+    let (host_and_path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (host, path) = host_and_path.split_once('/').unwrap_or((host_and_path, ""));
+    let host = host.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let path = path.trim_end_matches('/');
+
+    let params = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let tracking = TRACKING_PARAMS.iter().any(|tracking| {
+                key == *tracking || (tracking.ends_with('_') && key.starts_with(tracking))
+            });
+
+            (!tracking).then_some((key, value))
+        })
+        .collect::<Vec<_>>();
+
+    if YOUTUBE_HOSTS.contains(&host) {
+        let id = match path.split_once('/') {
+            _ if host == "youtu.be" => Some(path),
+            Some(("shorts" | "live" | "embed", id)) => Some(id),
+            _ if path == "watch" => params
+                .iter()
+                .find(|(key, _)| *key == "v")
+                .map(|(_, id)| *id),
+            _ => None,
+        };
+
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            return Some(format!("youtube:{id}"));
+        }
+    }
+
+    let mut params = params;
+    params.sort_unstable();
+    let query = params
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    Some(match query.is_empty() {
+        true => format!("{host}/{path}"),
+        false => format!("{host}/{path}?{query}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn youtube_links_share_a_key() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?si=abc&v=dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=abc",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&feature=share",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+        ] {
+            assert_eq!(
+                normalize_url(url).as_deref(),
+                Some("youtube:dQw4w9WgXcQ"),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_links_lose_tracking_params() {
+        assert_eq!(
+            normalize_url("https://SoundCloud.com/artist/track/?utm_source=x&b=2&a=1#t=3")
+                .as_deref(),
+            Some("soundcloud.com/artist/track?a=1&b=2")
+        );
+        assert_eq!(normalize_url("ytsearch1:never gonna give you up"), None);
+    }
+
+    #[test]
+    fn streams_expire_before_youtube_says_so() {
+        let now = Utc.timestamp_opt(1_000_000, 0).unwrap();
+        let url = "https://rr1.googlevideo.com/videoplayback?expire=1001000&ei=x";
+
+        assert_eq!(
+            stream_expiry(url, now, Duration::from_secs(3600)).timestamp(),
+            1_000_940
+        );
+        assert_eq!(
+            stream_expiry("https://example.com/a.mp3", now, Duration::from_secs(60)).timestamp(),
+            1_000_060
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use poise::async_trait;
+use songbird::input::{Codec, Container, Input, Metadata, Reader};
+
+use crate::{
+    client::{
+        bot::State,
+        settings::{GuildSettings, SettingsStore},
+    },
+    config::Config,
+    error::AppError,
+    storage::Storage,
+};
+
+use super::{cache::MetadataCache, Resolvers, SourceResolver};
+
+/// A bot with the default config that only knows `resolver`, with `defaults` for every guild.
+/// Nothing is ever written to its storage.
+pub async fn fake_state(resolver: FakeResolver, defaults: GuildSettings) -> Arc<State> {
+    let config = Config::default();
+    let storage = Storage::new(std::env::temp_dir().join("oxo-tests-unused"));
+    let settings = SettingsStore::load(storage.clone(), defaults)
+        .await
+        .unwrap();
+    let cache = MetadataCache::new(storage.clone(), config.cache.clone());
+
+    let mut state = State::new(config, storage, settings, cache);
+    state.resolvers = Arc::new(Resolvers::new(vec![Box::new(resolver)]));
+
+    Arc::new(state)
+}
+
+/// Resolves every query starting with its prefix to a second of silence, without touching
+/// the network, and remembers what it was asked for.
+#[derive(Debug, Clone)]
+pub struct FakeResolver {
+    prefix: &'static str,
+    resolved: Arc<Mutex<Vec<String>>>,
+    delay: Duration,
+}
+
+impl FakeResolver {
+    pub fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            resolved: Default::default(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Makes every metadata lookup take `delay`, like a slow yt-dlp would.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Every query this resolver resolved so far, oldest first.
+    pub fn resolved(&self) -> Vec<String> {
+        self.resolved.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl SourceResolver for FakeResolver {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        query.starts_with(self.prefix)
+    }
+
+    async fn resolve(&self, query: &str, _start: Duration) -> Result<Input, AppError> {
+        self.resolved.lock().unwrap().push(query.to_string());
+
+        // one second of stereo f32 samples at 48kHz
+        let silence = vec![0; 48_000 * 2 * 4];
+
+        Ok(Input::new(
+            true,
+            Reader::from_memory(silence),
+            Codec::FloatPcm,
+            Container::Raw,
+            Some(self.metadata(query).await?),
+        ))
+    }
+
+    async fn metadata(&self, query: &str) -> Result<Metadata, AppError> {
+        tokio::time::sleep(self.delay).await;
+
+        Ok(Metadata {
+            title: Some(query.to_string()),
+            source_url: Some(query.to_string()),
+            duration: Some(Duration::from_secs(1)),
+            channels: Some(2),
+            ..Default::default()
+        })
+    }
+}
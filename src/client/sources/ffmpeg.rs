@@ -0,0 +1,147 @@
+use std::{
+    ffi::OsStr,
+    io,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use serde_json::Value;
+use songbird::input::{
+    children_to_reader,
+    error::{Error, Result},
+    ffmpeg_optioned, Codec, Container, Input, Metadata,
+};
+use tokio::process::Command as TokioCommand;
+
+/// Makes ffmpeg write the raw stereo pcm songbird expects to stdout.
+const FFMPEG_ARGS: [&str; 9] = [
+    "-f",
+    "s16le",
+    "-ac",
+    "2",
+    "-ar",
+    "48000",
+    "-acodec",
+    "pcm_f32le",
+    "-",
+];
+
+/// Lets ffmpeg pick a dropped connection back up instead of ending the track early.
+pub const RECONNECT_ARGS: [&str; 6] = [
+    "-reconnect",
+    "1",
+    "-reconnect_streamed",
+    "1",
+    "-reconnect_delay_max",
+    "5",
+];
+
+/// Spawns ffmpeg decoding `input`, a path, an url or `-` to read from `stdin`,
+/// starting `start` into the track.
+pub fn spawn_ffmpeg(
+    input: &OsStr,
+    input_args: &[&str],
+    stdin: impl Into<Stdio>,
+    start: Duration,
+) -> Result<Child> {
+    let mut ffmpeg = Command::new("ffmpeg");
+    if !start.is_zero() {
+        ffmpeg.args(["-ss", &start.as_secs_f64().to_string()]);
+    }
+
+    Ok(ffmpeg
+        .args(input_args)
+        .arg("-i")
+        .arg(input)
+        .args(FFMPEG_ARGS)
+        .stdin(stdin)
+        .stderr(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?)
+}
+
+/// Plays `input` (a path or an url ffmpeg can open by itself) through songbird's ffmpeg input.
+///
+/// Whatever ffprobe finds in the tags of the file wins over `fallback`, except for the source url.
+pub async fn ffmpeg_input(
+    input: &OsStr,
+    input_args: &[&str],
+    start: Duration,
+    fallback: Metadata,
+) -> Result<Input> {
+    let start_secs = start.as_secs_f64().to_string();
+    let mut pre_input_args = input_args.to_vec();
+    if !start.is_zero() {
+        pre_input_args.extend(["-ss", &start_secs]);
+    }
+
+    let mut input = ffmpeg_optioned(input, &pre_input_args, &FFMPEG_ARGS).await?;
+    // FFMPEG_ARGS mix everything down to stereo, no matter what ffprobe found
+    input.stereo = true;
+    input.metadata = Box::new(with_fallback(*input.metadata, fallback));
+
+    Ok(input)
+}
+
+/// What ffprobe finds in the tags of `input` (a path or an url), merged like [`ffmpeg_input`]
+/// does. Knows the length of a file before it is played.
+pub async fn ffprobe(input: &OsStr, fallback: Metadata) -> Result<Metadata> {
+    let output = TokioCommand::new("ffprobe")
+        .args([
+            "-v",
+            "quiet",
+            "-of",
+            "json",
+            "-show_format",
+            "-show_streams",
+            "-i",
+        ])
+        .arg(input)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(Error::Io(io::Error::other(format!(
+            "ffprobe failed ({})",
+            output.status
+        ))));
+    }
+    let value: Value = serde_json::from_slice(&output.stdout).map_err(|error| Error::Json {
+        error,
+        parsed_text: String::from_utf8_lossy(&output.stdout).to_string(),
+    })?;
+
+    Ok(with_fallback(Metadata::from_ffprobe_json(&value), fallback))
+}
+
+/// Whatever ffprobe found wins over `fallback`, except for the source url.
+fn with_fallback(probed: Metadata, fallback: Metadata) -> Metadata {
+    Metadata {
+        title: probed.title.or(fallback.title),
+        artist: probed.artist.or(fallback.artist),
+        duration: probed.duration.or(fallback.duration),
+        thumbnail: probed.thumbnail.or(fallback.thumbnail),
+        channels: Some(2),
+        source_url: fallback.source_url,
+        ..probed
+    }
+}
+
+/// Plays `input` with `metadata` that is already known, without asking ffprobe first.
+pub fn ffmpeg_stream(
+    input: &OsStr,
+    input_args: &[&str],
+    start: Duration,
+    metadata: Metadata,
+) -> Result<Input> {
+    let ffmpeg = spawn_ffmpeg(input, input_args, Stdio::null(), start)?;
+
+    Ok(Input::new(
+        true,
+        children_to_reader::<f32>(vec![ffmpeg]),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(metadata),
+    ))
+}
@@ -0,0 +1,84 @@
+use std::{ffi::OsStr, time::Duration};
+
+use poise::async_trait;
+use songbird::input::{Input, Metadata};
+
+use crate::error::AppError;
+
+use super::{
+    ffmpeg::{ffmpeg_input, ffprobe, RECONNECT_ARGS},
+    SourceResolver, AUDIO_EXTENSIONS,
+};
+
+/// Where discord keeps uploaded files, which are checked to be audio by
+/// [`check_attachment`](crate::client::limits::check_attachment) whatever their extension.
+const ATTACHMENT_PATHS: [&str; 2] = [
+    "cdn.discordapp.com/attachments/",
+    "media.discordapp.net/attachments/",
+];
+
+/// Plays direct links to audio files, e.g. `https://example.com/song.mp3`, with ffmpeg.
+///
+/// Only links ending in one of the [`AUDIO_EXTENSIONS`] and files uploaded to discord are taken,
+/// everything else is left to yt-dlp.
+#[derive(Debug)]
+pub struct HttpResolver;
+
+#[async_trait]
+impl SourceResolver for HttpResolver {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let Some(path) = query
+            .strip_prefix("https://")
+            .or_else(|| query.strip_prefix("http://"))
+        else {
+            return false;
+        };
+
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        if ATTACHMENT_PATHS
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+        {
+            return true;
+        }
+        path.rsplit_once('.').is_some_and(|(_, extension)| {
+            AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        })
+    }
+
+    async fn resolve(&self, query: &str, start: Duration) -> Result<Input, AppError> {
+        let metadata = link_metadata(query);
+
+        Ok(ffmpeg_input(OsStr::new(query), &RECONNECT_ARGS, start, metadata).await?)
+    }
+
+    /// Asks ffprobe for the tags and the length, so they are known before the track plays.
+    /// The file name is the title if the file has none.
+    async fn metadata(&self, query: &str) -> Result<Metadata, AppError> {
+        ffprobe(OsStr::new(query), link_metadata(query))
+            .await
+            .map_err(|err| {
+                AppError::invalid_input(format!("I can't read `{query}` as an audio file: {err}"))
+            })
+    }
+}
+
+fn link_metadata(url: &str) -> Metadata {
+    let title = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .map(String::from);
+
+    Metadata {
+        title,
+        source_url: Some(url.to_string()),
+        channels: Some(2),
+        ..Default::default()
+    }
+}
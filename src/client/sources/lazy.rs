@@ -0,0 +1,268 @@
+//! Queue entries that are only resolved once their turn comes.
+//!
+//! Queueing a track only needs its metadata, which yt-dlp can print a lot faster than it can
+//! start a stream (and which is often cached). The stream itself is started by songbird
+//! shortly before the track plays, see [`Prefetch`].
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use poise::async_trait;
+use songbird::{
+    input::{
+        error::{Error, Result},
+        restartable::Restart,
+        Codec, Container, Input, Metadata, Restartable,
+    },
+    tracks::{create_player, Track, TrackError, TrackHandle, TrackQueue},
+    typemap::TypeMapKey,
+    Event, EventContext, EventHandler,
+};
+use tracing::{debug, warn, Instrument, Span};
+
+use crate::{client::limits::Limits, error::AppError};
+
+use super::Resolvers;
+
+/// The next track is resolved this long before the current one ends.
+const PREFETCH_LEAD: Duration = Duration::from_secs(20);
+
+/// Why the stream of a track could not be started, kept in the typemap of its handle.
+#[derive(Debug, Clone, Default)]
+pub struct ResolveFailure(Arc<Mutex<Option<String>>>);
+
+impl ResolveFailure {
+    /// The message of the error, if resolving failed.
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn set(&self, err: &AppError) {
+        *self.0.lock().unwrap() = Some(err.message());
+    }
+}
+
+impl TypeMapKey for ResolveFailure {
+    type Value = ResolveFailure;
+}
+
+/// A track that is queued by its query and whatever is known about it so far.
+pub struct QueueEntry {
+    input: Input,
+    failure: ResolveFailure,
+}
+
+impl QueueEntry {
+    /// Queues `metadata.source_url` (or `query` if there is none), which is resolved
+    /// into the track once it is about to play.
+    pub async fn new(
+        resolvers: Arc<Resolvers>,
+        query: &str,
+        mut metadata: Metadata,
+    ) -> std::result::Result<Self, AppError> {
+        let query = metadata.source_url.get_or_insert_with(|| query.to_string());
+        // songbird only reads the channels of lazy sources from their metadata
+        metadata.channels = Some(2);
+
+        let failure = ResolveFailure::default();
+        let source = LazySource {
+            resolvers,
+            query: query.clone(),
+            metadata: metadata.clone(),
+            failure: failure.clone(),
+            span: Span::current(),
+        };
+        let input = Restartable::new(source, true).await?.into();
+
+        Ok(Self { input, failure })
+    }
+
+    /// Looks up `query` and checks what was found against `limits`.
+    pub async fn lookup(
+        resolvers: &Arc<Resolvers>,
+        query: &str,
+        limits: &Limits,
+    ) -> std::result::Result<Self, AppError> {
+        let metadata = resolvers.metadata(query).await?;
+        limits.check_metadata(&metadata)?;
+
+        Self::new(resolvers.clone(), query, metadata).await
+    }
+
+    /// Creates the track of this entry, remembering its [`ResolveFailure`] and prefetching
+    /// the track after it in `queue`, before a `crossfade` to it would start.
+    pub async fn into_player(
+        self,
+        queue: &TrackQueue,
+        crossfade: Duration,
+    ) -> (Track, TrackHandle) {
+        let duration = self.input.metadata.duration;
+        let (track, handle) = create_player(self.input);
+        handle
+            .typemap()
+            .write()
+            .await
+            .insert::<ResolveFailure>(self.failure);
+
+        // tracks without a known duration (streams, plain files) are resolved when they start
+        let lead = PREFETCH_LEAD + crossfade;
+        if let Some(at) = duration.and_then(|duration| duration.checked_sub(lead)) {
+            let prefetch = Prefetch {
+                queue: queue.clone(),
+                track: handle.clone(),
+            };
+            if let Err(err) = handle.add_event(Event::Delayed(at), prefetch) {
+                warn!("Could not prefetch the next track: {err}");
+            }
+        }
+
+        (track, handle)
+    }
+}
+
+/// Why a track could not be played, if it ended because it could not be resolved.
+pub async fn resolve_failure(track: &TrackHandle) -> Option<String> {
+    track
+        .typemap()
+        .read()
+        .await
+        .get::<ResolveFailure>()
+        .and_then(ResolveFailure::get)
+}
+
+/// Started by songbird from its mixer, once the track needs audio or is prefetched.
+struct LazySource {
+    resolvers: Arc<Resolvers>,
+    query: String,
+    metadata: Metadata,
+    failure: ResolveFailure,
+    /// The span of whoever queued the track, the mixer has none
+    span: Span,
+}
+
+#[async_trait]
+impl Restart for LazySource {
+    async fn call_restart(&mut self, time: Option<Duration>) -> Result<Input> {
+        // seeking restarts the stream, so positions stay relative to the start of the track
+        let start = time.unwrap_or_default();
+        debug!(parent: &self.span, query = self.query, ?start, "Starting the stream");
+
+        let resolved = self
+            .resolvers
+            .resolve_from(&self.query, start)
+            .instrument(self.span.clone())
+            .await;
+
+        resolved.map_err(|err| {
+            warn!(parent: &self.span, query = self.query, "Could not resolve a queued track: {err}");
+            self.failure.set(&err);
+
+            Error::Io(std::io::Error::other(err.message()))
+        })
+    }
+
+    async fn lazy_init(&mut self) -> Result<(Option<Metadata>, Codec, Container)> {
+        // every resolver hands out raw f32 pcm, see `ffmpeg::FFMPEG_ARGS`
+        Ok((Some(self.metadata.clone()), Codec::FloatPcm, Container::Raw))
+    }
+}
+
+/// Starts resolving the track after `track` while `track` is still playing.
+///
+/// songbird does this on its own as well, but only five seconds ahead, which is
+/// not enough for yt-dlp.
+struct Prefetch {
+    queue: TrackQueue,
+    track: TrackHandle,
+}
+
+#[async_trait]
+impl EventHandler for Prefetch {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        let tracks = self.queue.current_queue();
+
+        // only the track that is playing right now prefetches
+        if tracks.first().map(TrackHandle::uuid) == Some(self.track.uuid()) {
+            if let Some(next) = tracks.get(1) {
+                debug!(title = next.metadata().title, "Prefetching the next track");
+                match next.make_playable() {
+                    // the next track is gone already
+                    Ok(()) | Err(TrackError::Finished) => {}
+                    Err(err) => warn!("Could not prefetch the next track: {err}"),
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::sources::fake::FakeResolver;
+
+    fn resolvers(fake: &FakeResolver) -> Arc<Resolvers> {
+        Arc::new(Resolvers::new(vec![Box::new(fake.clone())]))
+    }
+
+    #[tokio::test]
+    async fn queueing_does_not_resolve() {
+        let fake = FakeResolver::new("https://");
+        let entry = QueueEntry::lookup(&resolvers(&fake), "https://a", &Limits::default())
+            .await
+            .unwrap();
+
+        assert_eq!(entry.input.metadata.title.as_deref(), Some("https://a"));
+        assert!(fake.resolved().is_empty());
+    }
+
+    #[tokio::test]
+    async fn limits_are_checked_against_the_metadata() {
+        let fake = FakeResolver::new("https://");
+        let limits = Limits {
+            blocked: vec!["rickroll".into()],
+            ..Default::default()
+        };
+
+        let entry = QueueEntry::lookup(&resolvers(&fake), "https://rickroll", &limits).await;
+
+        assert!(entry.is_err());
+    }
+
+    #[tokio::test]
+    async fn failures_are_remembered() {
+        let fake = FakeResolver::new("https://");
+        let failure = ResolveFailure::default();
+        let mut source = LazySource {
+            resolvers: resolvers(&fake),
+            query: "local:gone.mp3".into(),
+            metadata: Metadata::default(),
+            failure: failure.clone(),
+            span: Span::none(),
+        };
+
+        assert!(source.call_restart(Some(Duration::ZERO)).await.is_err());
+        assert!(failure
+            .get()
+            .is_some_and(|reason| reason.contains("local:gone.mp3")));
+    }
+
+    #[tokio::test]
+    async fn restarting_resolves_the_query() {
+        let fake = FakeResolver::new("https://");
+        let mut source = LazySource {
+            resolvers: resolvers(&fake),
+            query: "https://a".into(),
+            metadata: Metadata::default(),
+            failure: ResolveFailure::default(),
+            span: Span::none(),
+        };
+
+        source.call_restart(Some(Duration::ZERO)).await.unwrap();
+
+        assert_eq!(fake.resolved(), ["https://a"]);
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use poise::async_trait;
+use songbird::input::{Input, Metadata};
+
+use tokio::task;
+
+use crate::{error::AppError, library::read_track};
+
+use super::{ffmpeg::ffmpeg_input, SourceResolver};
+
+/// Queries for files in the media directory start with this, e.g. `local:albums/song.flac`.
+pub const LOCAL_PREFIX: &str = "local:";
+
+/// Plays files from the configured media directory.
+#[derive(Debug)]
+pub struct LocalResolver {
+    media_dir: PathBuf,
+}
+
+impl LocalResolver {
+    pub fn new(media_dir: PathBuf) -> Self {
+        Self { media_dir }
+    }
+
+    /// Finds `relative` in the media directory, refusing anything that points outside of it.
+    async fn find(&self, relative: &str) -> Result<PathBuf, AppError> {
+        let media_dir = tokio::fs::canonicalize(&self.media_dir)
+            .await
+            .map_err(|err| {
+                AppError::internal(format!(
+                    "Could not open the media directory {}: {err}",
+                    self.media_dir.display()
+                ))
+            })?;
+
+        let path = tokio::fs::canonicalize(media_dir.join(relative.trim_start_matches('/')))
+            .await
+            .map_err(|_| {
+                AppError::invalid_input(format!("There is no file called `{relative}`"))
+            })?;
+
+        if !path.starts_with(&media_dir) {
+            return Err(AppError::forbidden(
+                "Only files from the media directory can be played",
+            ));
+        }
+        if !path.is_file() {
+            return Err(AppError::invalid_input(format!(
+                "`{relative}` is not a file"
+            )));
+        }
+
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl SourceResolver for LocalResolver {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        query.starts_with(LOCAL_PREFIX)
+    }
+
+    /// Reads the tags of the file, like the library does.
+    async fn metadata(&self, query: &str) -> Result<Metadata, AppError> {
+        let relative = query.strip_prefix(LOCAL_PREFIX).unwrap_or(query);
+        let path = self.find(relative).await?;
+
+        let relative = relative.to_string();
+        let track = task::spawn_blocking(move || read_track(&path, relative, None, 0))
+            .await
+            .map_err(|err| AppError::internal(format!("Could not read the tags: {err}")))?;
+
+        Ok(Metadata {
+            source_url: Some(query.to_string()),
+            ..track.metadata()
+        })
+    }
+
+    async fn resolve(&self, query: &str, start: Duration) -> Result<Input, AppError> {
+        let relative = query.strip_prefix(LOCAL_PREFIX).unwrap_or(query);
+        let path = self.find(relative).await?;
+
+        let metadata = Metadata {
+            title: Path::new(relative)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned()),
+            source_url: Some(query.to_string()),
+            channels: Some(2),
+            ..Default::default()
+        };
+
+        Ok(ffmpeg_input(path.as_os_str(), &[], start, metadata).await?)
+    }
+}
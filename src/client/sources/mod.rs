@@ -0,0 +1,194 @@
+//! Everything that turns what a user asked for into something songbird can play.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use poise::async_trait;
+use songbird::input::{Input, Metadata};
+use tracing::debug;
+
+use crate::{client::chapters::Chapter, config::Config, error::AppError};
+
+pub mod cache;
+pub mod ffmpeg;
+pub mod http;
+pub mod lazy;
+pub mod local;
+pub mod streaming;
+pub mod ytdl;
+
+#[cfg(test)]
+pub mod fake;
+
+/// Files with these extensions are played with ffmpeg directly, without yt-dlp.
+pub const AUDIO_EXTENSIONS: [&str; 8] = ["mp3", "ogg", "opus", "flac", "wav", "m4a", "aac", "oga"];
+
+/// One kind of audio source, e.g. yt-dlp or files in the media directory.
+#[async_trait]
+pub trait SourceResolver: Send + Sync {
+    /// Shown in logs, to tell which resolver handled a query.
+    fn name(&self) -> &'static str;
+
+    /// Whether this resolver is responsible for `query`, only looks at its shape.
+    fn matches(&self, query: &str) -> bool;
+
+    /// Starts streaming `query`, `start` into the track.
+    async fn resolve(&self, query: &str, start: Duration) -> Result<Input, AppError>;
+
+    /// What can be found out about `query` without streaming it, enough to queue it and
+    /// check it against the limits. Only the source url is known by default.
+    async fn metadata(&self, query: &str) -> Result<Metadata, AppError> {
+        Ok(Metadata {
+            source_url: Some(query.to_string()),
+            channels: Some(2),
+            ..Default::default()
+        })
+    }
+
+    /// The chapters of `query`, none by default.
+    async fn chapters(&self, _query: &str) -> Result<Vec<Chapter>, AppError> {
+        Ok(vec![])
+    }
+}
+
+/// All resolvers, a query goes to the first one that matches it.
+pub struct Resolvers(Vec<Box<dyn SourceResolver>>);
+
+impl Resolvers {
+    pub fn new(resolvers: Vec<Box<dyn SourceResolver>>) -> Self {
+        Self(resolvers)
+    }
+
+    /// The resolvers enabled in `config`, yt-dlp takes everything the others don't.
+    pub fn from_config(config: &Config, cache: Arc<cache::MetadataCache>) -> Self {
+        let mut resolvers: Vec<Box<dyn SourceResolver>> = vec![];
+        let ytdl = ytdl::YtdlResolver::new(config.ytdl.clone(), cache);
+
+        if config.sources.streaming_links {
+            resolvers.push(Box::new(streaming::StreamingLinkResolver::new(
+                ytdl.clone(),
+            )));
+        }
+        if let Some(media_dir) = &config.sources.media_dir {
+            resolvers.push(Box::new(local::LocalResolver::new(media_dir.clone())));
+        }
+        resolvers.push(Box::new(http::HttpResolver));
+        resolvers.push(Box::new(ytdl));
+
+        Self::new(resolvers)
+    }
+
+    /// Starts streaming `query`, `start` into the track.
+    pub async fn resolve_from(&self, query: &str, start: Duration) -> Result<Input, AppError> {
+        let resolver = self.find(query)?;
+
+        debug!(query, resolver = resolver.name(), "Resolving track");
+        resolver.resolve(query, start).await
+    }
+
+    /// The metadata of `query`, see [`SourceResolver::metadata`].
+    pub async fn metadata(&self, query: &str) -> Result<Metadata, AppError> {
+        let resolver = self.find(query)?;
+
+        debug!(query, resolver = resolver.name(), "Looking up track");
+        resolver.metadata(query).await
+    }
+
+    /// The chapters of `query`, see [`SourceResolver::chapters`].
+    pub async fn chapters(&self, query: &str) -> Result<Vec<Chapter>, AppError> {
+        self.find(query)?.chapters(query).await
+    }
+
+    fn find(&self, query: &str) -> Result<&dyn SourceResolver, AppError> {
+        self.0
+            .iter()
+            .find(|resolver| resolver.matches(query))
+            .map(|resolver| resolver.as_ref())
+            .ok_or_else(|| match query.starts_with(local::LOCAL_PREFIX) {
+                true => AppError::invalid_input(format!(
+                    "I can't play `{query}`, there is no media directory for local files"
+                )),
+                false => AppError::invalid_input(format!("I don't know how to play `{query}`")),
+            })
+    }
+}
+
+impl fmt::Debug for Resolvers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|resolver| resolver.name()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fake::FakeResolver, *};
+    use crate::storage::Storage;
+
+    #[tokio::test]
+    async fn first_matching_resolver_wins() {
+        let spotify = FakeResolver::new("https://open.spotify.com/");
+        let fallback = FakeResolver::new("");
+        let resolvers = Resolvers::new(vec![Box::new(spotify.clone()), Box::new(fallback.clone())]);
+
+        let input = resolvers
+            .resolve_from("https://open.spotify.com/track/1", Duration::ZERO)
+            .await
+            .unwrap();
+        resolvers
+            .resolve_from("https://youtu.be/dQw4w9WgXcQ", Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            input.metadata.source_url.as_deref(),
+            Some("https://open.spotify.com/track/1")
+        );
+        assert_eq!(spotify.resolved(), ["https://open.spotify.com/track/1"]);
+        assert_eq!(fallback.resolved(), ["https://youtu.be/dQw4w9WgXcQ"]);
+    }
+
+    #[tokio::test]
+    async fn unmatched_queries_are_rejected() {
+        let resolvers = Resolvers::new(vec![Box::new(FakeResolver::new("local:"))]);
+
+        assert!(resolvers
+            .resolve_from("https://example.com", Duration::ZERO)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn direct_links_skip_ytdl() {
+        let config = Config::default();
+        let cache = cache::MetadataCache::new(Storage::new("data"), config.cache.clone());
+        let resolvers = Resolvers::from_config(&config, Arc::new(cache));
+        let name = |query| {
+            resolvers
+                .0
+                .iter()
+                .find(|resolver| resolver.matches(query))
+                .map(|resolver| resolver.name())
+        };
+
+        assert_eq!(name("https://example.com/song.mp3?dl=1"), Some("http"));
+        assert_eq!(
+            name("https://cdn.discordapp.com/attachments/1/2/voice.amr?ex=65"),
+            Some("http")
+        );
+        assert_eq!(
+            name("https://open.spotify.com/track/1"),
+            Some("streaming link")
+        );
+        assert_eq!(name("https://www.youtube.com/watch?v=1"), Some("yt-dlp"));
+        assert_eq!(name("local:song.mp3"), None);
+        assert_eq!(
+            resolvers
+                .find("local:song.mp3")
+                .map(|resolver| resolver.name())
+                .unwrap_err()
+                .message(),
+            "I can't play `local:song.mp3`, there is no media directory for local files"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use poise::async_trait;
+use songbird::input::{Input, Metadata};
+use tracing::debug;
+
+use crate::error::AppError;
+
+use super::{ytdl::YtdlResolver, SourceResolver};
+
+/// Page titles end with one of these, after the title and artist.
+const TITLE_SUFFIXES: [&str; 3] = [" | Spotify", " - Apple Music", " on Apple Music"];
+
+/// Separate the track from the artist in page titles, most specific first.
+const ARTIST_SEPARATORS: [&str; 4] = [
+    " - song and lyrics by ",
+    " - song by ",
+    " - Song by ",
+    " by ",
+];
+
+/// Plays Spotify and Apple Music links by searching YouTube for the same track.
+///
+/// Neither service can be streamed from, but their track pages have a title like
+/// `Song - song and lyrics by Artist | Spotify`, which is all a search needs.
+#[derive(Debug)]
+pub struct StreamingLinkResolver {
+    ytdl: YtdlResolver,
+    client: reqwest::Client,
+}
+
+impl StreamingLinkResolver {
+    pub fn new(ytdl: YtdlResolver) -> Self {
+        Self {
+            ytdl,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn page_title(&self, url: &str) -> Result<String, AppError> {
+        let page = self
+            .client
+            .get(url)
+            .header(reqwest::header::USER_AGENT, "Mozilla/5.0 (compatible; oxo)")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| AppError::internal(format!("Could not load {url}: {err}")))?
+            .text()
+            .await
+            .map_err(|err| AppError::internal(format!("Could not load {url}: {err}")))?;
+
+        html_title(&page)
+            .ok_or_else(|| AppError::invalid_input("Could not find out which track that link is"))
+    }
+
+    /// The yt-dlp query that finds the track behind `url` on YouTube.
+    async fn youtube_search(&self, url: &str) -> Result<String, AppError> {
+        let title = self.page_title(url).await?;
+        let search = search_query(&title).ok_or_else(|| {
+            AppError::invalid_input("Could not find out which track that link is")
+        })?;
+        debug!(url, search, "Searching YouTube for a streaming link");
+
+        Ok(format!("ytsearch1:{search}"))
+    }
+}
+
+#[async_trait]
+impl SourceResolver for StreamingLinkResolver {
+    fn name(&self) -> &'static str {
+        "streaming link"
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let Some(rest) = query.strip_prefix("https://") else {
+            return false;
+        };
+        let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+
+        match host {
+            "open.spotify.com" => path.contains("track/"),
+            "music.apple.com" => path.contains("/song/") || path.contains("?i="),
+            _ => false,
+        }
+    }
+
+    async fn resolve(&self, query: &str, start: Duration) -> Result<Input, AppError> {
+        let search = self.youtube_search(query).await?;
+
+        self.ytdl.resolve(&search, start).await
+    }
+
+    /// The metadata of the YouTube video, its url is what ends up being played.
+    async fn metadata(&self, query: &str) -> Result<Metadata, AppError> {
+        let search = self.youtube_search(query).await?;
+
+        self.ytdl.metadata(&search).await
+    }
+}
+
+/// The content of the `<title>` tag of `html`, with the common entities decoded.
+fn html_title(html: &str) -> Option<String> {
+    let start = html.find("<title")?;
+    let start = start + html[start..].find('>')? + 1;
+    let end = start + html[start..].find("</title>")?;
+
+    let title = html[start..end]
+        .replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">");
+
+    Some(title.trim().to_string()).filter(|title| !title.is_empty())
+}
+
+/// Turns a page title like `Song - Song by Artist - Apple Music` into `Artist Song`.
+fn search_query(page_title: &str) -> Option<String> {
+    // apple music starts its titles with a left-to-right mark
+    let mut title = page_title.trim_matches(|c: char| c.is_whitespace() || c == '\u{200e}');
+    for suffix in TITLE_SUFFIXES {
+        title = title.strip_suffix(suffix).unwrap_or(title);
+    }
+
+    let query = ARTIST_SEPARATORS
+        .iter()
+        .find_map(|separator| title.rsplit_once(separator))
+        .map_or_else(
+            || title.to_string(),
+            |(track, artist)| format!("{artist} {track}"),
+        );
+
+    Some(query).filter(|query| !query.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        client::sources::cache::MetadataCache,
+        config::{CacheConfig, YtdlConfig},
+        storage::Storage,
+    };
+
+    #[test]
+    fn spotify_titles_become_searches() {
+        let html = "<html><head><title>Never Gonna Give You Up - song and lyrics by Rick Astley | Spotify</title></head></html>";
+        let title = html_title(html).unwrap();
+
+        assert_eq!(
+            search_query(&title).as_deref(),
+            Some("Rick Astley Never Gonna Give You Up")
+        );
+    }
+
+    #[test]
+    fn apple_music_titles_become_searches() {
+        assert_eq!(
+            search_query("\u{200e}Take On Me - Song by a-ha - Apple Music").as_deref(),
+            Some("a-ha Take On Me")
+        );
+    }
+
+    #[test]
+    fn matches_only_track_links() {
+        let cache = MetadataCache::new(Storage::new("data"), CacheConfig::default());
+        let ytdl = YtdlResolver::new(YtdlConfig::default(), Arc::new(cache));
+        let resolver = StreamingLinkResolver::new(ytdl);
+
+        assert!(resolver.matches("https://open.spotify.com/track/4cOdK2wGLETKBW3PvgPWqT"));
+        assert!(resolver.matches("https://music.apple.com/us/album/take-on-me/1?i=2"));
+        assert!(!resolver.matches("https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M"));
+        assert!(!resolver.matches("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    ffi::OsStr,
+    io::{self, BufRead, BufReader, Read},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use poise::async_trait;
+use serde_json::Value;
+use songbird::input::{
+    children_to_reader,
+    error::{Error, Result},
+    Codec, Container, Input, Metadata,
+};
+use tokio::{process::Command as TokioCommand, task};
+use tracing::{debug, warn};
+
+use crate::{client::chapters::Chapter, config::YtdlConfig, error::AppError, metrics::METRICS};
+
+use super::{
+    cache::{CachedTrack, MetadataCache},
+    ffmpeg::{ffmpeg_stream, spawn_ffmpeg, RECONNECT_ARGS},
+    local::LOCAL_PREFIX,
+    SourceResolver,
+};
+
+/// The audio format yt-dlp picks, the same one when streaming and when only fetching metadata.
+const FORMAT: &str = "webm[abr>0]/bestaudio/best";
+
+/// The arguments oxo always passes to yt-dlp, the configured ones are added after these.
+const YTDL_ARGS: [&str; 8] = [
+    "-f",
+    FORMAT,
+    "-R",
+    "infinite",
+    "--no-playlist",
+    "--ignore-config",
+    "--no-warnings",
+    "--print-json",
+];
+
+/// Plays everything yt-dlp can extract (YouTube, SoundCloud, Bandcamp, ...).
+///
+/// yt-dlp supports far more sites than are worth listing, so this is the resolver
+/// everything ends up at that no other resolver claimed.
+///
+/// Urls that were resolved recently are streamed straight from the [`MetadataCache`].
+#[derive(Debug, Clone)]
+pub struct YtdlResolver {
+    config: YtdlConfig,
+    cache: Arc<MetadataCache>,
+}
+
+impl YtdlResolver {
+    pub fn new(config: YtdlConfig, cache: Arc<MetadataCache>) -> Self {
+        Self { config, cache }
+    }
+
+    fn stream_cached(&self, track: &CachedTrack, start: Duration) -> Option<Input> {
+        let stream = track.live_stream()?;
+        let headers = stream
+            .headers
+            .iter()
+            .map(|(key, value)| format!("{key}: {value}\r\n"))
+            .collect::<String>();
+
+        let mut input_args = RECONNECT_ARGS.to_vec();
+        if !headers.is_empty() {
+            input_args.extend(["-headers", &headers]);
+        }
+
+        match ffmpeg_stream(
+            OsStr::new(&stream.url),
+            &input_args,
+            start,
+            track.metadata(),
+        ) {
+            Ok(input) => Some(input),
+            Err(err) => {
+                warn!("Could not play the cached stream, asking yt-dlp again: {err}");
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SourceResolver for YtdlResolver {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    /// Everything but files from the media directory, which yt-dlp would take for a url.
+    fn matches(&self, query: &str) -> bool {
+        !query.starts_with(LOCAL_PREFIX)
+    }
+
+    async fn resolve(&self, query: &str, start: Duration) -> std::result::Result<Input, AppError> {
+        if let Some(track) = self.cache.get(query).await {
+            if let Some(input) = self.stream_cached(&track, start) {
+                debug!(query, "Streaming a cached url");
+                return Ok(input);
+            }
+        }
+
+        let (input, output) = ytdl_from(&self.config, query, start).await?;
+        let track = CachedTrack::from_ytdl_output(&output, self.cache.stream_ttl());
+        self.cache.insert(query, track).await;
+
+        Ok(input)
+    }
+
+    /// Also caches the stream url yt-dlp picked, so playing the track later skips yt-dlp.
+    async fn metadata(&self, query: &str) -> std::result::Result<Metadata, AppError> {
+        if let Some(track) = self.cache.get(query).await {
+            return Ok(track.metadata());
+        }
+
+        let output = ytdl_json(&self.config, query).await?;
+        let track = CachedTrack::from_ytdl_output(&output, self.cache.stream_ttl());
+        let metadata = track.metadata();
+        self.cache.insert(query, track).await;
+
+        Ok(metadata)
+    }
+
+    async fn chapters(&self, query: &str) -> std::result::Result<Vec<Chapter>, AppError> {
+        if let Some(track) = self.cache.get(query).await {
+            return Ok(track.chapters);
+        }
+
+        let output = ytdl_json(&self.config, query).await?;
+        let track = CachedTrack::from_ytdl_output(&output, self.cache.stream_ttl());
+        let chapters = track.chapters.clone();
+        self.cache.insert(query, track).await;
+
+        Ok(chapters)
+    }
+}
+
+/// Creates a streamed audio source with the configured yt-dlp and ffmpeg, starting `start` into
+/// the track. The json yt-dlp printed about the track is returned as well.
+///
+/// Works like [`songbird::ytdl`], except that the yt-dlp executable and
+/// its extra arguments come from the config.
+pub async fn ytdl_from(config: &YtdlConfig, uri: &str, start: Duration) -> Result<(Input, Value)> {
+    let started = Instant::now();
+    let input = spawn_ytdl(config, uri, start).await;
+    METRICS.observe_resolve(started, &input);
+
+    input
+}
+
+async fn spawn_ytdl(config: &YtdlConfig, uri: &str, start: Duration) -> Result<(Input, Value)> {
+    let mut youtube_dl = Command::new(&config.path)
+        .args(YTDL_ARGS)
+        .args(&config.args)
+        // the query may start with a dash, it must not be taken for an option
+        .args(["-o", "-", "--", uri])
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // yt-dlp prints the metadata to stderr before it starts streaming to stdout
+    let stderr = youtube_dl.stderr.take();
+    let (returned_stderr, value) = task::spawn_blocking(move || {
+        let mut stderr = stderr.expect("stderr is piped");
+        let mut line = vec![];
+        let value = match BufReader::new(stderr.by_ref()).read_until(b'\n', &mut line) {
+            Ok(_) => parse_json(&line),
+            Err(_) => Err(Error::Metadata),
+        };
+
+        (stderr, value)
+    })
+    .await
+    .map_err(|_| Error::Metadata)?;
+
+    youtube_dl.stderr = Some(returned_stderr);
+
+    let taken_stdout = youtube_dl.stdout.take().ok_or(Error::Stdout)?;
+
+    let ffmpeg = spawn_ffmpeg(OsStr::new("-"), &[], taken_stdout, start)?;
+
+    let value = value?;
+    let input = Input::new(
+        true,
+        children_to_reader::<f32>(vec![youtube_dl, ffmpeg]),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(Metadata::from_ytdl_output(value.clone())),
+    );
+
+    Ok((input, value))
+}
+
+/// Searches for `query` and returns the urls of up to `count` results.
+pub async fn ytdl_search(config: &YtdlConfig, query: &str, count: usize) -> Result<Vec<String>> {
+    let output = TokioCommand::new(&config.path)
+        .args([
+            "--flat-playlist",
+            "--print",
+            "url",
+            "--ignore-config",
+            "--no-warnings",
+        ])
+        .args(&config.args)
+        .arg("--")
+        .arg(format!("ytsearch{count}:{query}"))
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Fetches only the metadata of `uri`, without streaming any audio.
+pub async fn ytdl_metadata(config: &YtdlConfig, uri: &str) -> Result<Metadata> {
+    ytdl_json(config, uri).await.map(Metadata::from_ytdl_output)
+}
+
+/// Like [`ytdl_metadata`], but returns everything yt-dlp printed about `uri`.
+pub async fn ytdl_json(config: &YtdlConfig, uri: &str) -> Result<Value> {
+    let started = Instant::now();
+    let output = fetch_json(config, uri).await;
+    METRICS.observe_resolve(started, &output);
+
+    output
+}
+
+async fn fetch_json(config: &YtdlConfig, uri: &str) -> Result<Value> {
+    let output = TokioCommand::new(&config.path)
+        .args([
+            "-f",
+            FORMAT,
+            "-j",
+            "--no-playlist",
+            "--ignore-config",
+            "--no-warnings",
+        ])
+        .args(&config.args)
+        .arg("--")
+        .arg(uri)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().last().unwrap_or_default();
+        return Err(Error::Io(io::Error::other(format!(
+            "yt-dlp failed ({}): {message}",
+            output.status
+        ))));
+    }
+
+    parse_json(&output.stdout)
+}
+
+fn parse_json(output: &[u8]) -> Result<Value> {
+    let end = output
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .unwrap_or(output.len());
+
+    serde_json::from_slice(&output[..end]).map_err(|error| Error::Json {
+        error,
+        parsed_text: String::from_utf8_lossy(output).to_string(),
+    })
+}
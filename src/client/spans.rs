@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use poise::{
+    serenity_prelude::{GuildId, Message, User, UserId},
+    ApplicationContext, BoxFuture, ContextMenuCommandAction, FrameworkError,
+};
+use songbird::{tracks::TrackHandle, typemap::TypeMapKey};
+use tracing::{field, info, info_span, warn, Instrument, Span};
+
+use crate::{
+    client::{bot::State, commands::PoiseCommand},
+    error::Error,
+};
+
+type Data = Arc<State>;
+type ActionResult<'a> = BoxFuture<'a, Result<(), FrameworkError<'a, Data, Error>>>;
+type SlashAction = for<'a> fn(ApplicationContext<'a, Data, Error>) -> ActionResult<'a>;
+
+/// The actions poise generated for a command, kept in its `custom_data` by [`instrument`].
+struct OriginalActions {
+    slash: Option<SlashAction>,
+    context_menu: Option<ContextMenuCommandAction<Data, Error>>,
+}
+
+/// Runs `command` and all of its subcommands, whether invoked as slash command or from a
+/// context menu, inside a `command` span, which carries the command name, guild and user
+/// of the invocation.
+pub fn instrument(command: &mut PoiseCommand) {
+    let original = OriginalActions {
+        slash: command.slash_action.replace(instrumented_slash_action),
+        context_menu: command.context_menu_action,
+    };
+    command.context_menu_action = original.context_menu.map(|action| match action {
+        ContextMenuCommandAction::User(_) => {
+            ContextMenuCommandAction::User(instrumented_user_action)
+        }
+        ContextMenuCommandAction::Message(_) => {
+            ContextMenuCommandAction::Message(instrumented_message_action)
+        }
+    });
+    command.custom_data = Box::new(original);
+
+    command.subcommands.iter_mut().for_each(instrument);
+}
+
+fn original_actions<'a>(ctx: &ApplicationContext<'a, Data, Error>) -> &'a OriginalActions {
+    ctx.command
+        .custom_data
+        .downcast_ref()
+        .expect("instrumented commands keep their original actions")
+}
+
+fn instrumented_slash_action(ctx: ApplicationContext<'_, Data, Error>) -> ActionResult<'_> {
+    let action = original_actions(&ctx)
+        .slash
+        .expect("only commands with a slash action get an instrumented one");
+
+    in_command_span(ctx, action(ctx))
+}
+
+fn instrumented_user_action(
+    ctx: ApplicationContext<'_, Data, Error>,
+    user: User,
+) -> ActionResult<'_> {
+    let Some(ContextMenuCommandAction::User(action)) = original_actions(&ctx).context_menu else {
+        unreachable!("only user context menu commands get an instrumented user action")
+    };
+
+    in_command_span(ctx, action(ctx, user))
+}
+
+fn instrumented_message_action(
+    ctx: ApplicationContext<'_, Data, Error>,
+    message: Message,
+) -> ActionResult<'_> {
+    let Some(ContextMenuCommandAction::Message(action)) = original_actions(&ctx).context_menu
+    else {
+        unreachable!("only message context menu commands get an instrumented message action")
+    };
+
+    in_command_span(ctx, action(ctx, message))
+}
+
+fn in_command_span<'a>(
+    ctx: ApplicationContext<'a, Data, Error>,
+    action: ActionResult<'a>,
+) -> ActionResult<'a> {
+    let span = info_span!(
+        "command",
+        command = %ctx.command.qualified_name,
+        guild_id = field::Empty,
+        user_id = %ctx.interaction.user().id,
+    );
+    if let Some(guild_id) = ctx.interaction.guild_id() {
+        span.record("guild_id", field::display(guild_id));
+    }
+
+    Box::pin(
+        async move {
+            info!("Command invoked");
+
+            let result = action.await;
+            match &result {
+                Ok(()) => info!("Command finished"),
+                Err(_) => warn!("Command failed"),
+            }
+
+            result
+        }
+        .instrument(span),
+    )
+}
+
+/// The span everything that happens to a track is logged in, from being queued until it is dropped.
+pub struct TrackSpan;
+
+impl TypeMapKey for TrackSpan {
+    type Value = Span;
+}
+
+/// Opens the lifecycle span of a freshly queued `track`.
+pub async fn start_track_span(track: &TrackHandle, guild_id: GuildId, requester: Option<UserId>) {
+    let span = info_span!(
+        parent: None,
+        "track",
+        %guild_id,
+        url = track.metadata().source_url.as_deref().unwrap_or_default(),
+        title = track.metadata().title.as_deref().unwrap_or_default(),
+        requester = field::Empty,
+    );
+    if let Some(requester) = requester {
+        span.record("requester", field::display(requester));
+    }
+
+    span.in_scope(|| info!("Track queued"));
+    track.typemap().write().await.insert::<TrackSpan>(span);
+}
+
+/// The lifecycle span of `track`, or a disabled span if it never got one.
+pub async fn track_span(track: &TrackHandle) -> Span {
+    track
+        .typemap()
+        .read()
+        .await
+        .get::<TrackSpan>()
+        .cloned()
+        .unwrap_or_else(Span::none)
+}
@@ -0,0 +1,77 @@
+use poise::serenity_prelude::{Cache, Channel, ChannelId, ChannelType, GuildChannel, Http};
+use tracing::{debug, warn};
+
+/// Discord allows at most this many characters in stage topics.
+const MAX_TOPIC_LEN: usize = 120;
+
+/// The stage channel `channel_id`, or `None` for any other kind of channel. Only asks discord
+/// if the channel is not cached.
+pub async fn stage_channel(
+    cache: &Cache,
+    http: &Http,
+    channel_id: ChannelId,
+) -> Option<GuildChannel> {
+    let channel = match channel_id.to_channel_cached(cache) {
+        Some(channel) => Ok(channel),
+        None => channel_id.to_channel(http).await,
+    };
+
+    match channel {
+        Ok(Channel::Guild(channel)) if channel.kind == ChannelType::Stage => Some(channel),
+        Ok(_) => None,
+        Err(err) => {
+            warn!(%channel_id, "Could not look up the channel: {err}");
+            None
+        }
+    }
+}
+
+/// Becomes a speaker on `stage`, or asks to become one without the permissions for that.
+pub async fn take_the_stage(http: &Http, stage: &GuildChannel) {
+    // unsuppressing yourself needs Mute Members, asking to speak only Request to Speak
+    if stage
+        .edit_own_voice_state(http, |state| state.suppress(false))
+        .await
+        .is_ok()
+    {
+        debug!(channel_id = %stage.id, "Speaking on the stage");
+        return;
+    }
+
+    match stage
+        .edit_own_voice_state(http, |state| state.request_to_speak(true))
+        .await
+    {
+        Ok(()) => debug!(channel_id = %stage.id, "Asked to speak on the stage"),
+        Err(err) => warn!(channel_id = %stage.id, "Could not ask to speak on the stage: {err}"),
+    }
+}
+
+/// Sets the topic of `stage`, starting the stage if nobody has yet.
+pub async fn set_topic(http: &Http, stage: &GuildChannel, topic: &str) {
+    let topic = truncate_topic(topic);
+
+    if stage
+        .edit_stage_instance(http, |instance| instance.topic(&topic))
+        .await
+        .is_ok()
+    {
+        return;
+    }
+
+    let started = stage
+        .create_stage_instance(http, |instance| {
+            instance.channel_id(stage.id.0).topic(&topic)
+        })
+        .await;
+    if let Err(err) = started {
+        warn!(channel_id = %stage.id, "Could not set the stage topic: {err}");
+    }
+}
+
+fn truncate_topic(topic: &str) -> String {
+    match topic.chars().count() > MAX_TOPIC_LEN {
+        true => topic.chars().take(MAX_TOPIC_LEN - 1).chain(['…']).collect(),
+        false => topic.to_string(),
+    }
+}
@@ -0,0 +1,71 @@
+//! Hands the voice events of the gateway to songbird, remembering which shards it was
+//! registered with so the readiness check can tell whether oxo is able to join calls.
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+};
+
+use poise::{
+    async_trait,
+    serenity_prelude::{futures::channel::mpsc::UnboundedSender, GuildId, UserId, VoiceState},
+};
+use serenity::{client::bridge::voice::VoiceGatewayManager, gateway::InterMessage};
+use songbird::Songbird;
+
+/// Registered with serenity in place of songbird itself, it passes everything on.
+#[derive(Debug)]
+pub struct VoiceGateway {
+    songbird: Arc<Songbird>,
+    initialised: AtomicBool,
+    shards: StdMutex<BTreeSet<u64>>,
+}
+
+impl VoiceGateway {
+    pub fn new(songbird: Arc<Songbird>) -> Self {
+        Self {
+            songbird,
+            initialised: Default::default(),
+            shards: Default::default(),
+        }
+    }
+
+    /// Whether songbird knows the bot, without that it can't join any call.
+    pub fn is_initialised(&self) -> bool {
+        self.initialised.load(Ordering::SeqCst)
+    }
+
+    /// The shards songbird can send voice updates through right now.
+    pub fn shards(&self) -> Vec<u64> {
+        self.shards.lock().unwrap().iter().copied().collect()
+    }
+}
+
+#[async_trait]
+impl VoiceGatewayManager for VoiceGateway {
+    async fn initialise(&self, shard_count: u64, user_id: UserId) {
+        self.songbird.initialise(shard_count, user_id).await;
+        self.initialised.store(true, Ordering::SeqCst);
+    }
+
+    async fn register_shard(&self, shard_id: u64, sender: UnboundedSender<InterMessage>) {
+        self.songbird.register_shard(shard_id, sender).await;
+        self.shards.lock().unwrap().insert(shard_id);
+    }
+
+    async fn deregister_shard(&self, shard_id: u64) {
+        self.songbird.deregister_shard(shard_id).await;
+        self.shards.lock().unwrap().remove(&shard_id);
+    }
+
+    async fn server_update(&self, guild_id: GuildId, endpoint: &Option<String>, token: &str) {
+        self.songbird.server_update(guild_id, endpoint, token).await;
+    }
+
+    async fn state_update(&self, guild_id: GuildId, voice_state: &VoiceState) {
+        self.songbird.state_update(guild_id, voice_state).await;
+    }
+}
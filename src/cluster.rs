@@ -0,0 +1,205 @@
+//! Lets several oxo processes (nodes) run next to each other, one per bot token or range of
+//! shards.
+//!
+//! Nodes share the storage directory: guild settings are read from there, and every node keeps
+//! a document in there saying which calls it is in. That is how the api knows where to send
+//! requests for a guild, see [`crate::api::routing`].
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    client::bot::State,
+    config::{ClusterConfig, ShardRange},
+    error::Error,
+    storage::Storage,
+};
+
+/// Every node keeps its document as `node-<node_id>`.
+const NODE_PREFIX: &str = "node-";
+/// A node that missed this many heartbeats in a row is considered gone.
+const MISSED_HEARTBEATS: i32 = 3;
+
+/// What a node tells the others about itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    pub node_id: String,
+    pub api_url: String,
+    /// The bot user the node runs as, unknown until it connected to discord
+    pub bot_id: Option<UserId>,
+    pub shards: Option<ShardRange>,
+    /// The voice channel of every guild the node is in a call in
+    pub calls: HashMap<GuildId, ChannelId>,
+    /// Set a few heartbeats ahead, the node is gone if it didn't announce itself again by then
+    pub expires_at: DateTime<Utc>,
+}
+
+/// This node, as one of several. Only exists if `cluster.node_id` is configured.
+#[derive(Debug)]
+pub struct Cluster {
+    config: ClusterConfig,
+    storage: Storage,
+    node_id: String,
+    api_url: String,
+}
+
+impl Cluster {
+    pub fn new(config: &ClusterConfig, storage: Storage) -> Option<Self> {
+        Some(Self {
+            node_id: config.node_id.clone()?,
+            api_url: config.api_url.clone()?,
+            config: config.clone(),
+            storage,
+        })
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Tells the other nodes which calls this one is in right now.
+    pub async fn announce(&self, state: &State) {
+        let mut calls = HashMap::new();
+        for guild_id in state.queues.guild_ids() {
+            let Some(call) = state.songbird_instance.get(guild_id) else {
+                continue;
+            };
+            let channel_id = call.lock().await.current_channel();
+            if let Some(channel_id) = channel_id {
+                calls.insert(guild_id, ChannelId(channel_id.0));
+            }
+        }
+
+        let heartbeat = chrono::Duration::from_std(self.config.heartbeat())
+            .expect("the heartbeat is a few seconds");
+        let node = Node {
+            node_id: self.node_id.clone(),
+            api_url: self.api_url.clone(),
+            bot_id: state.bot_id().await,
+            shards: self.config.shards,
+            calls,
+            expires_at: Utc::now() + heartbeat * MISSED_HEARTBEATS,
+        };
+
+        if let Err(err) = self.storage.save(&self.document(), &node).await {
+            warn!("Could not announce this node: {err}");
+        }
+    }
+
+    /// Every node that is running, including this one.
+    pub async fn nodes(&self) -> Result<Vec<Node>, Error> {
+        let mut nodes = vec![];
+        for name in self.storage.names(NODE_PREFIX).await? {
+            let node: Option<Node> = self.storage.load(&name).await?;
+            nodes.extend(node.filter(|node| node.expires_at > Utc::now()));
+        }
+
+        Ok(nodes)
+    }
+
+    /// The other nodes that are in a call in `guild_id`.
+    pub async fn owners(&self, guild_id: GuildId) -> Result<Vec<Node>, Error> {
+        let mut nodes = self.nodes().await?;
+        nodes.retain(|node| node.node_id != self.node_id && node.calls.contains_key(&guild_id));
+
+        Ok(nodes)
+    }
+
+    /// Announces this node every heartbeat and picks up settings the other nodes changed,
+    /// until oxo shuts down.
+    pub async fn keep_announcing(self: Arc<Self>, state: Arc<State>) {
+        let mut heartbeat = tokio::time::interval(self.config.heartbeat());
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {}
+                () = state.shutdown.wait() => break,
+            }
+
+            self.announce(&state).await;
+            if let Err(err) = state.settings.reload().await {
+                warn!("Could not reload the guild settings: {err}");
+            }
+        }
+
+        debug!("Removing this node from the cluster");
+        if let Err(err) = self.storage.remove(&self.document()).await {
+            warn!("Could not remove this node from the cluster: {err}");
+        }
+    }
+
+    /// The name of a document only this node writes, starting with `name`.
+    pub fn own_document(&self, name: &str) -> String {
+        self.config.own_document(name)
+    }
+
+    fn document(&self) -> String {
+        format!("{NODE_PREFIX}{}", self.node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(storage: &Storage, node_id: &str) -> Cluster {
+        let config = ClusterConfig {
+            node_id: Some(node_id.into()),
+            api_url: Some(format!("http://{node_id}:8080")),
+            ..Default::default()
+        };
+
+        Cluster::new(&config, storage.clone()).unwrap()
+    }
+
+    fn node(node_id: &str, calls: &[u64], expires_in: chrono::Duration) -> Node {
+        Node {
+            node_id: node_id.into(),
+            api_url: format!("http://{node_id}:8080"),
+            bot_id: None,
+            shards: None,
+            calls: calls
+                .iter()
+                .map(|guild_id| (GuildId(*guild_id), ChannelId(1)))
+                .collect(),
+            expires_at: Utc::now() + expires_in,
+        }
+    }
+
+    #[tokio::test]
+    async fn owners_are_the_other_running_nodes_in_the_guild() {
+        let dir = std::env::temp_dir().join(format!("oxo-cluster-tests-{}", std::process::id()));
+        let storage = Storage::new(&dir);
+        let this = &cluster(&storage, "a");
+
+        let minute = chrono::Duration::minutes(1);
+        for node in [
+            node("a", &[1, 2], minute),
+            node("b", &[1], minute),
+            node("c", &[2], minute),
+            node("d", &[1], -minute),
+        ] {
+            storage
+                .save(&format!("{NODE_PREFIX}{}", node.node_id), &node)
+                .await
+                .unwrap();
+        }
+
+        let owners = |guild_id| async move {
+            let owners = this.owners(GuildId(guild_id)).await.unwrap();
+            owners
+                .into_iter()
+                .map(|node| node.node_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(owners(1).await, ["b"]);
+        assert_eq!(owners(2).await, ["c"]);
+        assert!(owners(3).await.is_empty());
+        assert_eq!(this.nodes().await.unwrap().len(), 3);
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
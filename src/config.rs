@@ -0,0 +1,915 @@
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use poise::serenity_prelude::GuildId;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+use crate::client::settings::GuildSettings;
+
+/// Used when neither `--config` nor `OXO_CONFIG` point somewhere else.
+const DEFAULT_CONFIG_PATH: &str = "oxo.toml";
+
+/// Everything that can be configured about the oxo process.
+///
+/// Values are read from a toml file first and can then be overridden by environment variables,
+/// see [`Config::apply_env`] for the full list.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discord: DiscordConfig,
+    pub api: ApiConfig,
+    pub logging: LoggingConfig,
+    pub ytdl: YtdlConfig,
+    pub sources: SourcesConfig,
+    pub cache: CacheConfig,
+    pub lyrics: LyricsConfig,
+    pub segments: SegmentsConfig,
+    pub storage: StorageConfig,
+    pub shutdown: ShutdownConfig,
+    pub reconnect: ReconnectConfig,
+    pub cluster: ClusterConfig,
+    pub guild_defaults: GuildSettings,
+    pub stations: Stations,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub token: Option<String>,
+    /// Read the token from this file instead, e.g. a docker secret
+    pub token_file: Option<PathBuf>,
+    /// Register the slash commands globally on startup
+    pub register_globally: bool,
+    /// Also register the slash commands in these guilds, where changes show up right away
+    pub dev_guilds: Vec<GuildId>,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            token_file: None,
+            register_globally: true,
+            dev_guilds: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    /// Origins allowed to call the api from a browser, empty allows every origin
+    pub cors_origins: Vec<String>,
+    /// Needed to change guild settings through the api, which is impossible without one
+    pub token: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            host: "0.0.0.0".into(),
+            port: 8080,
+            cors_origins: vec![],
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// A level like `info`, optionally followed by per-module levels like `info,oxo::api=debug`
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            format: LogFormat::Full,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Pretty,
+    /// One json object per line, including the fields of all active spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(LogFormat::Full),
+            "compact" => Ok(LogFormat::Compact),
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "`{s}` is not a log format, expected full, compact, pretty or json"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YtdlConfig {
+    pub path: String,
+    /// Passed to yt-dlp in addition to the arguments oxo needs, e.g. `["--cookies", "..."]`
+    pub args: Vec<String>,
+}
+
+impl Default for YtdlConfig {
+    fn default() -> Self {
+        Self {
+            path: "yt-dlp".into(),
+            args: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesConfig {
+    /// Files in this directory make up the library and can be played with `local:<path>`,
+    /// disabled when unset
+    pub media_dir: Option<PathBuf>,
+    /// Play Spotify and Apple Music links by searching for the track on YouTube
+    pub streaming_links: bool,
+    pub attachments: AttachmentConfig,
+}
+
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        Self {
+            media_dir: None,
+            streaming_links: true,
+            attachments: Default::default(),
+        }
+    }
+}
+
+/// Which files uploaded to discord can be played.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentConfig {
+    pub max_size_mb: u64,
+    /// MIME types like `audio/mpeg`, `audio/*` allows every audio type
+    pub types: Vec<String>,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: 25,
+            types: vec!["audio/*".into()],
+        }
+    }
+}
+
+impl AttachmentConfig {
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn allows_type(&self, mime_type: &str) -> bool {
+        // e.g. `audio/ogg; codecs=opus`
+        let mime_type = mime_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        self.types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix('*') {
+                Some(prefix) => mime_type.starts_with(&prefix.to_lowercase()),
+                None => mime_type == allowed.to_lowercase(),
+            })
+    }
+}
+
+/// The cache of what yt-dlp resolved, see [`crate::client::sources::cache::MetadataCache`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub max_entries: usize,
+    /// How long titles, durations and thumbnails are reused
+    pub metadata_ttl_secs: u64,
+    /// How long stream urls are reused, YouTube lets them expire after about 6 hours
+    pub stream_ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_entries: 2000,
+            metadata_ttl_secs: 7 * 24 * 60 * 60,
+            stream_ttl_secs: 60 * 60,
+        }
+    }
+}
+
+/// Where `/lyrics` looks, see [`crate::client::lyrics`]. Files in the media directory are
+/// always checked first.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LyricsConfig {
+    /// Subtitles uploaded with a video, looked up with yt-dlp
+    pub subtitles: bool,
+    /// An LRCLIB instance, disabled when unset
+    pub lrclib_url: Option<String>,
+}
+
+impl Default for LyricsConfig {
+    fn default() -> Self {
+        Self {
+            subtitles: true,
+            lrclib_url: Some("https://lrclib.net".into()),
+        }
+    }
+}
+
+/// Where the segments guilds skip with `skip_segments` come from, see
+/// [`crate::client::segments`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SegmentsConfig {
+    /// A json file of segments, asked before the SponsorBlock server
+    pub database: Option<PathBuf>,
+    /// A SponsorBlock server, disabled when unset
+    pub sponsorblock_url: Option<String>,
+}
+
+impl Default for SegmentsConfig {
+    fn default() -> Self {
+        Self {
+            database: None,
+            sponsorblock_url: Some("https://sponsor.ajay.app".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub path: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            path: "data".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// How long leaving calls and stopping the servers may take before oxo exits anyway
+    pub timeout_secs: u64,
+    /// Posted in every guild that is playing something when oxo shuts down
+    pub notice: Option<String>,
+    /// Save the queues on shutdown and pick them up again after the next start
+    pub resume: bool,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 10,
+            notice: None,
+            resume: true,
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    /// How often oxo tries to rejoin after its voice connection dropped, 0 gives up right away
+    pub attempts: u32,
+    /// The wait before the first attempt, doubled after every failed one
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            initial_backoff_secs: 1,
+            max_backoff_secs: 30,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// How long to wait before the `attempt`th attempt, starting at 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let secs = self
+            .initial_backoff_secs
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)));
+
+        Duration::from_secs(secs.min(self.max_backoff_secs))
+    }
+}
+
+/// Running several oxo processes (nodes) next to each other, e.g. one per bot token or per
+/// range of shards. Nodes share the storage directory and find each other through it, see
+/// [`crate::cluster::Cluster`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// Unique per node, oxo runs on its own when unset
+    pub node_id: Option<String>,
+    /// Where the other nodes reach the api of this one, e.g. `http://oxo-1:8080`
+    pub api_url: Option<String>,
+    /// Only start these shards instead of a single one
+    pub shards: Option<ShardRange>,
+    /// How often a node tells the others which calls it is in
+    pub heartbeat_secs: u64,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            node_id: None,
+            api_url: None,
+            shards: None,
+            heartbeat_secs: 10,
+        }
+    }
+}
+
+impl ClusterConfig {
+    /// The name of a document only this node writes, starting with `name`. Just `name` if
+    /// oxo runs on its own.
+    pub fn own_document(&self, name: &str) -> String {
+        match &self.node_id {
+            Some(node_id) => format!("{name}-{node_id}"),
+            None => name.to_string(),
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if let Some(node_id) = &self.node_id {
+            // it ends up in a file name
+            let valid = node_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if node_id.is_empty() || !valid {
+                problems.push(format!(
+                    "cluster.node_id: `{node_id}` may only contain letters, digits, - and _"
+                ));
+            }
+            if self.api_url.is_none() {
+                problems.push(
+                    "cluster.api_url: missing, the other nodes need it to reach this one".into(),
+                );
+            }
+        }
+        if let Some(ShardRange { first, last, total }) = self.shards {
+            if first > last || last >= total {
+                problems.push(format!(
+                    "cluster.shards: {first} to {last} are not shards out of {total}"
+                ));
+            }
+        }
+        if self.heartbeat_secs == 0 {
+            problems.push("cluster.heartbeat_secs: must be at least 1".into());
+        }
+
+        problems
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_secs)
+    }
+}
+
+/// The shards `first` to `last` (inclusive) out of `total`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShardRange {
+    pub first: u64,
+    pub last: u64,
+    pub total: u64,
+}
+
+/// The stations `/lofi` can play.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Stations(pub Vec<Station>);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub is_24_7: bool,
+}
+
+impl Stations {
+    pub fn find(&self, name: &str) -> Option<&Station> {
+        self.0
+            .iter()
+            .find(|station| station.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Default for Stations {
+    fn default() -> Self {
+        let station = |name: &str, url: &str, is_24_7| Station {
+            name: name.into(),
+            url: url.into(),
+            is_24_7,
+        };
+
+        Self(vec![
+            station(
+                "LofiGirl",
+                "https://www.youtube.com/watch?v=jfKfPfyJRdk",
+                true,
+            ),
+            station(
+                "Undertale",
+                "https://www.youtube.com/watch?v=A7vMrjsBMTI",
+                false,
+            ),
+            station(
+                "Zelda",
+                "https://www.youtube.com/watch?v=-z3RRwk2rdU",
+                false,
+            ),
+            station(
+                "AnimeOps",
+                "https://www.youtube.com/watch?v=GNWLILeztaI",
+                false,
+            ),
+            station(
+                "Metal",
+                "https://www.youtube.com/watch?v=83PnFc6eh-4",
+                false,
+            ),
+            station(
+                "Djent",
+                "https://www.youtube.com/watch?v=1XFtipo7v0Y",
+                false,
+            ),
+            station(
+                "Berserk",
+                "https://www.youtube.com/watch?v=gnKZqk-CqBs",
+                false,
+            ),
+            station(
+                "BigIron",
+                "https://www.youtube.com/watch?v=ZBGui2nZ0c0",
+                false,
+            ),
+        ])
+    }
+}
+
+/// Everything that is wrong with a config, so it can be fixed in one go.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads the config file (`path`, `OXO_CONFIG` or `oxo.toml`), applies environment
+    /// overrides and validates the result. A missing token is only a problem if `needs_token`,
+    /// e.g. to check a config before the token is deployed.
+    pub fn load(path: Option<&Path>, needs_token: bool) -> Result<Self, ConfigError> {
+        let explicit_path = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os("OXO_CONFIG").map(PathBuf::from));
+
+        let mut config = match &explicit_path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        let mut problems = config.apply_env();
+        problems.extend(config.validate(needs_token));
+
+        match problems.is_empty() {
+            true => Ok(config),
+            false => Err(ConfigError(problems)),
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            ConfigError(vec![format!("Could not read {}: {err}", path.display())])
+        })?;
+
+        toml::from_str(&content)
+            .map_err(|err| ConfigError(vec![format!("{}: {err}", path.display())]))
+    }
+
+    /// Overrides values with the following environment variables, if they are set:
+    /// `DISCORD_TOKEN`, `DISCORD_TOKEN_FILE`, `OXO_DEV_GUILDS` (comma separated), `API_HOST`, `API_PORT`, `API_CORS_ORIGINS`
+    /// (comma separated), `API_TOKEN`, `DISABLE_WEB_API`, `OXO_LOG`, `OXO_LOG_FORMAT`, `OXO_YTDL_PATH`, `OXO_MEDIA_DIR`,
+    /// `OXO_DATA_DIR`, `OXO_NODE_ID` and `OXO_NODE_URL`.
+    fn apply_env(&mut self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if let Ok(token) = env::var("DISCORD_TOKEN") {
+            self.discord.token = Some(token);
+        }
+        if let Some(token_file) = env::var_os("DISCORD_TOKEN_FILE") {
+            self.discord.token_file = Some(token_file.into());
+        }
+        if let Ok(guilds) = env::var("OXO_DEV_GUILDS") {
+            let guilds = guilds
+                .split(',')
+                .map(str::trim)
+                .filter(|guild| !guild.is_empty())
+                .map(|guild| guild.parse().map(GuildId))
+                .collect::<Result<_, _>>();
+
+            match guilds {
+                Ok(guilds) => self.discord.dev_guilds = guilds,
+                Err(_) => {
+                    problems.push("OXO_DEV_GUILDS: expected comma separated guild ids".into())
+                }
+            }
+        }
+        if let Ok(host) = env::var("API_HOST") {
+            self.api.host = host;
+        }
+        if let Ok(port) = env::var("API_PORT") {
+            match port.parse() {
+                Ok(port) => self.api.port = port,
+                Err(_) => problems.push(format!("API_PORT: `{port}` is not a valid port")),
+            }
+        }
+        if let Ok(origins) = env::var("API_CORS_ORIGINS") {
+            self.api.cors_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Ok(token) = env::var("API_TOKEN") {
+            self.api.token = Some(token);
+        }
+        if let Ok(disabled) = env::var("DISABLE_WEB_API") {
+            match parse_bool(&disabled) {
+                Some(disabled) => self.api.enabled = !disabled,
+                None => problems.push(format!(
+                    "DISABLE_WEB_API: `{disabled}` is not a boolean, expected true or false"
+                )),
+            }
+        }
+        if let Ok(level) = env::var("OXO_LOG") {
+            self.logging.level = level;
+        }
+        if let Ok(format) = env::var("OXO_LOG_FORMAT") {
+            match format.parse() {
+                Ok(format) => self.logging.format = format,
+                Err(err) => problems.push(format!("OXO_LOG_FORMAT: {err}")),
+            }
+        }
+        if let Ok(path) = env::var("OXO_YTDL_PATH") {
+            self.ytdl.path = path;
+        }
+        if let Some(path) = env::var_os("OXO_MEDIA_DIR") {
+            self.sources.media_dir = Some(path.into());
+        }
+        if let Some(path) = env::var_os("OXO_DATA_DIR") {
+            self.storage.path = path.into();
+        }
+        if let Ok(node_id) = env::var("OXO_NODE_ID") {
+            self.cluster.node_id = Some(node_id);
+        }
+        if let Ok(url) = env::var("OXO_NODE_URL") {
+            self.cluster.api_url = Some(url);
+        }
+
+        problems
+    }
+
+    /// Checks the config for problems. A token file is read right away, so that
+    /// [`DiscordConfig::token`] is always set afterwards if `needs_token`.
+    fn validate(&mut self, needs_token: bool) -> Vec<String> {
+        let mut problems = vec![];
+
+        if self.discord.token.is_none() {
+            match &self.discord.token_file {
+                Some(path) => match fs::read_to_string(path) {
+                    Ok(token) => self.discord.token = Some(token.trim().to_string()),
+                    Err(err) => problems.push(format!(
+                        "discord.token_file: could not read {}: {err}",
+                        path.display()
+                    )),
+                },
+                None if !needs_token => {}
+                None => problems.push(
+                    "discord.token: missing, set it, discord.token_file or DISCORD_TOKEN".into(),
+                ),
+            }
+        }
+
+        if let Err(err) = EnvFilter::try_new(&self.logging.level) {
+            problems.push(format!(
+                "logging.level: `{}` is not a valid filter ({err}), expected something like `info` or `info,oxo=debug`",
+                self.logging.level
+            ));
+        }
+
+        if self.ytdl.path.trim().is_empty() {
+            problems.push("ytdl.path: must not be empty".into());
+        }
+
+        if let Some(media_dir) = &self.sources.media_dir {
+            if !media_dir.is_dir() {
+                problems.push(format!(
+                    "sources.media_dir: {} is not a directory",
+                    media_dir.display()
+                ));
+            }
+        }
+
+        if let Some(database) = &self.segments.database {
+            if !database.is_file() {
+                problems.push(format!(
+                    "segments.database: {} is not a file",
+                    database.display()
+                ));
+            }
+        }
+
+        if let Err(err) = self.guild_defaults.validate() {
+            problems.push(format!("guild_defaults: {}", err.message()));
+        }
+
+        problems.extend(self.cluster.validate());
+
+        if self.stations.0.is_empty() {
+            problems.push("stations: at least one station is needed for /lofi".into());
+        }
+        for (i, station) in self.stations.0.iter().enumerate() {
+            if self.stations.0[..i]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&station.name))
+            {
+                problems.push(format!("stations: `{}` is defined twice", station.name));
+            }
+        }
+
+        problems
+    }
+
+    pub fn discord_token(&self) -> &str {
+        self.discord
+            .token
+            .as_deref()
+            .expect("the token is checked when loading the config for a command that needs it")
+    }
+}
+
+/// Accepts the usual ways of switching something on or off in an environment variable.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Every variable [`Config::load`] reads, they are shared by all tests running at once.
+    const VARS: [&str; 16] = [
+        "OXO_CONFIG",
+        "DISCORD_TOKEN",
+        "DISCORD_TOKEN_FILE",
+        "OXO_DEV_GUILDS",
+        "API_HOST",
+        "API_PORT",
+        "API_CORS_ORIGINS",
+        "API_TOKEN",
+        "DISABLE_WEB_API",
+        "OXO_LOG",
+        "OXO_LOG_FORMAT",
+        "OXO_YTDL_PATH",
+        "OXO_MEDIA_DIR",
+        "OXO_DATA_DIR",
+        "OXO_NODE_ID",
+        "OXO_NODE_URL",
+    ];
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Loads `toml` with only the environment variables in `vars` set.
+    fn load(toml: &str, vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let path = env::temp_dir().join(format!("oxo-config-{}.toml", std::process::id()));
+        fs::write(&path, toml).unwrap();
+
+        let clear = || {
+            for name in VARS {
+                env::remove_var(name);
+            }
+        };
+        clear();
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        let config = Config::load(Some(&path), true);
+        clear();
+
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn files_are_loaded() {
+        let config = load(
+            r#"
+            [discord]
+            token = "from-file"
+            [api]
+            port = 1234
+            cors_origins = ["https://example.com"]
+            [guild_defaults]
+            default_volume = 50
+            "#,
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(config.discord_token(), "from-file");
+        assert_eq!(config.api.port, 1234);
+        assert_eq!(config.api.cors_origins, ["https://example.com"]);
+        assert_eq!(config.guild_defaults.default_volume, 50);
+        // everything else keeps its default
+        assert!(config.api.enabled);
+        assert_eq!(config.api.host, ApiConfig::default().host);
+
+        let typo = load("[api]\nprot = 1234", &[]).unwrap_err();
+        assert!(typo.0[0].contains("unknown field `prot`"), "{typo}");
+    }
+
+    #[test]
+    fn environment_variables_win_over_the_file() {
+        let toml = "[discord]\ntoken = \"from-file\"\n[api]\nport = 1234\nenabled = true";
+        let config = load(
+            toml,
+            &[
+                ("DISCORD_TOKEN", "from-env"),
+                ("API_PORT", "4321"),
+                ("DISABLE_WEB_API", "true"),
+                ("OXO_DEV_GUILDS", "1, 2"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.discord_token(), "from-env");
+        assert_eq!(config.api.port, 4321);
+        assert!(!config.api.enabled);
+        assert_eq!(config.discord.dev_guilds, [GuildId(1), GuildId(2)]);
+
+        let enabled = |value| load(toml, &[("DISABLE_WEB_API", value)]).map(|c| c.api.enabled);
+        assert!(enabled("0").unwrap());
+        assert!(enabled("false").unwrap());
+        assert!(!enabled("YES").unwrap());
+        assert!(enabled("").is_err());
+
+        let problems = load(toml, &[("API_PORT", "http"), ("OXO_LOG_FORMAT", "xml")])
+            .unwrap_err()
+            .0;
+        assert_eq!(problems.len(), 2, "{problems:?}");
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut config = Config::default();
+        let missing = env::temp_dir().join("oxo-config-tests-missing");
+        config.discord.token_file = Some(missing.clone());
+        config.logging.level = "oxo=loud".into();
+        config.ytdl.path = " ".into();
+        config.sources.media_dir = Some(missing.clone());
+        config.segments.database = Some(missing);
+        config.guild_defaults.default_volume = 255;
+        config.cluster.node_id = Some("oxo-1".into());
+        config.stations.0.clear();
+
+        let problems = config.validate(true);
+        let fields = problems
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                "discord.token_file",
+                "logging.level",
+                "ytdl.path",
+                "sources.media_dir",
+                "segments.database",
+                "guild_defaults",
+                "cluster.api_url",
+                "stations",
+            ]
+        );
+
+        let mut config = Config::default();
+        assert!(config.validate(false).is_empty());
+        assert!(config.validate(true)[0].starts_with("discord.token: missing"));
+
+        let station = config.stations.0[0].clone();
+        config.stations.0.push(station);
+        assert!(config.validate(false)[0].contains("is defined twice"));
+
+        config = Config::default();
+        config.cluster.node_id = Some("../oxo".into());
+        config.cluster.api_url = Some("http://oxo:8080".into());
+        config.cluster.shards = Some(ShardRange {
+            first: 2,
+            last: 1,
+            total: 4,
+        });
+        config.cluster.heartbeat_secs = 0;
+        assert_eq!(config.validate(false).len(), 3);
+    }
+
+    #[test]
+    fn reconnect_backoff_doubles_up_to_the_max() {
+        let config = ReconnectConfig {
+            attempts: 10,
+            initial_backoff_secs: 2,
+            max_backoff_secs: 30,
+        };
+        let backoff = |attempt| config.backoff(attempt).as_secs();
+
+        assert_eq!(backoff(1), 2);
+        assert_eq!(backoff(2), 4);
+        assert_eq!(backoff(4), 16);
+        assert_eq!(backoff(5), 30);
+        assert_eq!(backoff(u32::MAX), 30);
+        // attempts start at 1, 0 is treated like the first one
+        assert_eq!(backoff(0), 2);
+    }
+
+    #[test]
+    fn huge_attachment_limits_do_not_overflow() {
+        let config = AttachmentConfig {
+            max_size_mb: u64::MAX,
+            ..Default::default()
+        };
+
+        assert_eq!(config.max_size(), u64::MAX);
+    }
+}
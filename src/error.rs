@@ -1,19 +1,21 @@
-use std::{fmt, sync::Arc};
+use std::{error::Error as StdError, fmt, sync::Arc};
 
-use poise::{
-    serenity_prelude::{CreateEmbed, Mutex},
-    FrameworkError,
-};
+use poise::{serenity_prelude::CreateEmbed, FrameworkError};
 
-use tracing::{error, Value};
+use tracing::{error, warn, Value};
 
-use crate::client::{bot::State, embed_ext::CreateEmbedExt};
+use crate::client::{bot::State, embed_ext::CreateEmbedExt, limits::Limit};
+use crate::metrics::METRICS;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Debug)]
 pub enum AppErrorType {
     NotFound,
+    InvalidInput,
+    Forbidden,
+    Internal,
+    LimitExceeded(Limit),
     SongbirdError(SongbirdError),
 }
 
@@ -30,6 +32,15 @@ impl fmt::Display for AppError {
     }
 }
 
+impl StdError for AppError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &self.error_type {
+            AppErrorType::SongbirdError(SongbirdError::Input(error)) => Some(error),
+            _ => None,
+        }
+    }
+}
+
 impl AppError {
     pub fn message(&self) -> String {
         match self {
@@ -54,6 +65,38 @@ impl AppError {
             error_type: AppErrorType::NotFound,
         }
     }
+
+    pub fn limit_exceeded(limit: Limit, message: impl Into<String>) -> Self {
+        AppError {
+            cause: None,
+            message: Some(message.into()),
+            error_type: AppErrorType::LimitExceeded(limit),
+        }
+    }
+
+    pub fn internal(cause: impl Into<String>) -> Self {
+        AppError {
+            cause: Some(cause.into()),
+            message: None,
+            error_type: AppErrorType::Internal,
+        }
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        AppError {
+            cause: None,
+            message: Some(message.into()),
+            error_type: AppErrorType::Forbidden,
+        }
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        AppError {
+            cause: None,
+            message: Some(message.into()),
+            error_type: AppErrorType::InvalidInput,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -71,7 +114,14 @@ impl From<songbird::input::error::Error> for AppError {
     }
 }
 
-pub async fn on_error(error: FrameworkError<'_, Arc<Mutex<State>>, Error>) {
+pub async fn on_error(error: FrameworkError<'_, Arc<State>, Error>) {
+    if let Some(ctx) = error.ctx() {
+        METRICS
+            .command_errors
+            .with_label_values(&[&ctx.command().qualified_name])
+            .inc();
+    }
+
     let res = match error {
         FrameworkError::Setup {
             error: _,
@@ -85,12 +135,20 @@ pub async fn on_error(error: FrameworkError<'_, Arc<Mutex<State>>, Error>) {
             event: _,
             framework: _,
         } => todo!(),
-        FrameworkError::Command { error, ctx } => {
-            log_unexpected_error(&error);
-
-            ctx.send(|create| create.embed(|e| error_embed(e, &error)))
-                .await
-        }
+        FrameworkError::Command { error, ctx } => match error.downcast_ref::<AppError>() {
+            Some(app_error) => {
+                warn!(cause = ?app_error.cause, "Command rejected: {}", app_error.message());
+
+                ctx.send(|create| create.embed(|e| rejection_embed(e, app_error)))
+                    .await
+            }
+            None => {
+                log_unexpected_error(&error);
+
+                ctx.send(|create| create.embed(|e| error_embed(e, &error)))
+                    .await
+            }
+        },
         FrameworkError::ArgumentParse {
             error: _,
             input: _,
@@ -109,14 +167,40 @@ pub async fn on_error(error: FrameworkError<'_, Arc<Mutex<State>>, Error>) {
             ctx: _,
         } => todo!(),
         FrameworkError::MissingUserPermissions {
-            missing_permissions: _,
-            ctx: _,
-        } => todo!(),
+            missing_permissions,
+            ctx,
+        } => {
+            let missing =
+                missing_permissions.map_or_else(|| "some permissions".into(), |p| p.to_string());
+            let error = AppError::invalid_input(format!("You are missing {missing} for this"));
+
+            ctx.send(|create| create.embed(|e| rejection_embed(e, &error)))
+                .await
+        }
         FrameworkError::NotAnOwner { ctx: _ } => todo!(),
-        FrameworkError::GuildOnly { ctx: _ } => todo!(),
+        FrameworkError::GuildOnly { ctx } => {
+            let error = AppError::invalid_input("This only works in servers");
+
+            ctx.send(|create| create.embed(|e| rejection_embed(e, &error)))
+                .await
+        }
         FrameworkError::DmOnly { ctx: _ } => todo!(),
         FrameworkError::NsfwOnly { ctx: _ } => todo!(),
-        FrameworkError::CommandCheckFailed { error: _, ctx: _ } => todo!(),
+        FrameworkError::CommandCheckFailed { error, ctx } => {
+            let error = match error {
+                Some(error) => match error.downcast::<AppError>() {
+                    Ok(app_error) => *app_error,
+                    Err(error) => {
+                        log_unexpected_error(&error);
+                        AppError::forbidden("Could not check whether you are allowed to do this")
+                    }
+                },
+                None => AppError::forbidden("You are not allowed to do this"),
+            };
+
+            ctx.send(|create| create.embed(|e| rejection_embed(e, &error)))
+                .await
+        }
         FrameworkError::DynamicPrefix {
             error: _,
             ctx: _,
@@ -155,3 +239,16 @@ fn error_embed<'a>(create: &'a mut CreateEmbed, error: &Error) -> &'a mut Create
         .description("An error occured because of (most likely) your incompetence :)")
         .field("Error", format!("```{:?}```", error), false)
 }
+
+fn rejection_embed<'a>(create: &'a mut CreateEmbed, error: &AppError) -> &'a mut CreateEmbed {
+    create
+        .warn_styling()
+        .title("Nu-uh, can't do that")
+        .description(error.message());
+
+    if let AppErrorType::LimitExceeded(limit) = &error.error_type {
+        create.field("Limit", limit, false);
+    }
+
+    create
+}
@@ -0,0 +1,564 @@
+//! The shared folder of local music, indexed by the tags of its files.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use lofty::{file::TaggedFile, prelude::*, tag::Tag};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use songbird::input::Metadata;
+use tokio::{
+    sync::{mpsc, RwLock},
+    task,
+    time::timeout,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    client::sources::{local::LOCAL_PREFIX, AUDIO_EXTENSIONS},
+    shutdown::Shutdown,
+};
+
+/// Changes are collected until nothing happened for this long, so copying a whole album
+/// into the library only causes a single rescan.
+const RESCAN_DELAY: Duration = Duration::from_secs(3);
+
+/// Every audio file in the media directory, see [`crate::config::SourcesConfig::media_dir`].
+#[derive(Debug)]
+pub struct Library {
+    root: PathBuf,
+    tracks: RwLock<BTreeMap<String, LibraryTrack>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryTrack {
+    /// Relative to the media directory, always separated by `/`
+    pub path: String,
+    /// The title tag, or the file name if there is none
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration_secs: Option<u64>,
+    pub has_cover: bool,
+    #[serde(skip)]
+    modified: Option<SystemTime>,
+    #[serde(skip)]
+    size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Album {
+    pub name: String,
+    /// The artist of most of its tracks
+    pub artist: Option<String>,
+    pub tracks: usize,
+    pub duration_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Artist {
+    pub name: String,
+    pub albums: usize,
+    pub tracks: usize,
+}
+
+pub struct Cover {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl LibraryTrack {
+    /// Plays this track through the local resolver.
+    pub fn query(&self) -> String {
+        format!("{LOCAL_PREFIX}{}", self.path)
+    }
+
+    /// Everything that is known about this track before it is played.
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            title: Some(self.title.clone()),
+            artist: self.artist.clone(),
+            duration: self.duration_secs.map(Duration::from_secs),
+            source_url: Some(self.query()),
+            channels: Some(2),
+            ..Default::default()
+        }
+    }
+
+    /// `Title - Artist`, or only the title if the artist is unknown.
+    pub fn display_name(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{} - {artist}", self.title),
+            None => self.title.clone(),
+        }
+    }
+
+    fn matches(&self, terms: &[String]) -> bool {
+        let haystack = [
+            Some(self.title.as_str()),
+            self.artist.as_deref(),
+            self.album.as_deref(),
+            Some(self.path.as_str()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+        terms.iter().all(|term| haystack.contains(term.as_str()))
+    }
+
+    /// Orders tracks like they appear on their album.
+    fn album_order(&self) -> (Option<&str>, u32, &str) {
+        (
+            self.album.as_deref(),
+            self.track_number.unwrap_or(u32::MAX),
+            &self.path,
+        )
+    }
+}
+
+impl Library {
+    /// An empty library, call [`Library::keep_indexed`] to fill it.
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            tracks: Default::default(),
+        }
+    }
+
+    /// Indexes the library once, then rescans it whenever something in it changes until oxo shuts down.
+    pub async fn keep_indexed(self: Arc<Self>, shutdown: Shutdown) {
+        if let Err(err) = self.scan().await {
+            warn!("Could not index the library: {err}");
+        }
+
+        let (sender, mut changes) = mpsc::unbounded_channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok_and(|event| !event.kind.is_access()) && sender.send(()).is_err() {
+                debug!("The library changed after oxo stopped watching it");
+            }
+        })
+        .and_then(|mut watcher: RecommendedWatcher| {
+            watcher.watch(&self.root, RecursiveMode::Recursive)?;
+            Ok(watcher)
+        });
+        // stops watching once dropped
+        let _watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                warn!("Could not watch the library, changes need a restart to show up: {err}");
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                change = changes.recv() => if change.is_none() {
+                    break;
+                },
+            }
+
+            while let Ok(Some(())) = timeout(RESCAN_DELAY, changes.recv()).await {}
+
+            if let Err(err) = self.scan().await {
+                warn!("Could not rescan the library: {err}");
+            }
+        }
+    }
+
+    /// Brings the index up to date. Tags are only read again for files that changed since the last scan.
+    pub async fn scan(&self) -> io::Result<()> {
+        let root = self.root.clone();
+        let previous = self.tracks.read().await.clone();
+
+        let tracks = task::spawn_blocking(move || scan_dir(&root, &previous))
+            .await
+            .map_err(io::Error::other)??;
+
+        info!(tracks = tracks.len(), "Indexed the library");
+        *self.tracks.write().await = tracks;
+
+        Ok(())
+    }
+
+    pub async fn get(&self, path: &str) -> Option<LibraryTrack> {
+        self.tracks.read().await.get(path).cloned()
+    }
+
+    /// Tracks whose title, artist, album or path contain every word of `query`,
+    /// the ones with `query` in their title first.
+    pub async fn search(&self, query: &str, limit: usize) -> Vec<LibraryTrack> {
+        let query = query.to_lowercase();
+        let terms = query
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        let mut found = self
+            .tracks
+            .read()
+            .await
+            .values()
+            .filter(|track| track.matches(&terms))
+            .cloned()
+            .collect::<Vec<_>>();
+        found.sort_by_key(|track| !track.title.to_lowercase().contains(&query));
+        found.truncate(limit);
+
+        found
+    }
+
+    pub async fn albums(&self) -> Vec<Album> {
+        let tracks = self.tracks.read().await;
+        let mut albums = BTreeMap::<&str, Vec<&LibraryTrack>>::new();
+        for track in tracks.values() {
+            if let Some(album) = &track.album {
+                albums.entry(album).or_default().push(track);
+            }
+        }
+
+        albums
+            .into_iter()
+            .map(|(name, tracks)| Album {
+                name: name.to_string(),
+                artist: most_common(tracks.iter().filter_map(|track| track.artist.as_deref())),
+                tracks: tracks.len(),
+                duration_secs: tracks.iter().filter_map(|track| track.duration_secs).sum(),
+            })
+            .collect()
+    }
+
+    /// The tracks of the album called `name`, in album order.
+    pub async fn album(&self, name: &str) -> Vec<LibraryTrack> {
+        let mut tracks = self
+            .tracks
+            .read()
+            .await
+            .values()
+            .filter(|track| {
+                track
+                    .album
+                    .as_ref()
+                    .is_some_and(|album| album.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        tracks.sort_by(|a, b| a.album_order().cmp(&b.album_order()));
+
+        tracks
+    }
+
+    pub async fn artists(&self) -> Vec<Artist> {
+        let tracks = self.tracks.read().await;
+        let mut artists = BTreeMap::<&str, (BTreeSet<&str>, usize)>::new();
+        for track in tracks.values() {
+            if let Some(artist) = &track.artist {
+                let (albums, count) = artists.entry(artist).or_default();
+                albums.extend(track.album.as_deref());
+                *count += 1;
+            }
+        }
+
+        artists
+            .into_iter()
+            .map(|(name, (albums, tracks))| Artist {
+                name: name.to_string(),
+                albums: albums.len(),
+                tracks,
+            })
+            .collect()
+    }
+
+    /// Every track by the artist called `name`, album by album.
+    pub async fn artist(&self, name: &str) -> Vec<LibraryTrack> {
+        let mut tracks = self
+            .tracks
+            .read()
+            .await
+            .values()
+            .filter(|track| {
+                track
+                    .artist
+                    .as_ref()
+                    .is_some_and(|artist| artist.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        tracks.sort_by(|a, b| a.album_order().cmp(&b.album_order()));
+
+        tracks
+    }
+
+    /// The cover art embedded in the track at `path`, read from the file every time.
+    pub async fn cover(&self, path: &str) -> Option<Cover> {
+        let track = self.get(path).await.filter(|track| track.has_cover)?;
+        let file = self.root.join(&track.path);
+
+        task::spawn_blocking(move || {
+            let tagged = lofty::read_from_path(file).ok()?;
+            let picture = primary_tag(&tagged)?.pictures().first()?.clone();
+
+            Some(Cover {
+                mime_type: picture
+                    .mime_type()
+                    .map_or("application/octet-stream", |mime| mime.as_str())
+                    .to_string(),
+                data: picture.into_data(),
+            })
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// The lyrics of the track at `path`, from a `.lrc` file next to it or its lyrics tag.
+    pub async fn lyrics(&self, path: &str) -> Option<String> {
+        let track = self.get(path).await?;
+        let file = self.root.join(&track.path);
+
+        task::spawn_blocking(move || {
+            if let Ok(lrc) = fs::read_to_string(file.with_extension("lrc")) {
+                return Some(lrc);
+            }
+
+            let tagged = lofty::read_from_path(file).ok()?;
+            primary_tag(&tagged)?
+                .get_string(&ItemKey::Lyrics)
+                .map(String::from)
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
+
+fn scan_dir(
+    root: &Path,
+    previous: &BTreeMap<String, LibraryTrack>,
+) -> io::Result<BTreeMap<String, LibraryTrack>> {
+    let mut tracks = BTreeMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // the library itself has to be readable, subdirectories are skipped
+            Err(err) if dir == root => return Err(err),
+            Err(err) => {
+                warn!("Could not read {}: {err}", dir.display());
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if !is_audio_file(&path) {
+                continue;
+            }
+
+            let Some(relative) = relative_path(root, &path) else {
+                continue;
+            };
+            let modified = metadata.modified().ok();
+            let size = metadata.len();
+
+            let track = match previous.get(&relative) {
+                Some(track) if track.modified == modified && track.size == size => track.clone(),
+                _ => read_track(&path, relative.clone(), modified, size),
+            };
+            tracks.insert(relative, track);
+        }
+    }
+
+    Ok(tracks)
+}
+
+/// Reads the tags of the audio file at `path`, falling back to its file name as title.
+pub fn read_track(
+    path: &Path,
+    relative: String,
+    modified: Option<SystemTime>,
+    size: u64,
+) -> LibraryTrack {
+    let mut track = LibraryTrack {
+        title: path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| relative.clone()),
+        path: relative,
+        artist: None,
+        album: None,
+        track_number: None,
+        duration_secs: None,
+        has_cover: false,
+        modified,
+        size,
+    };
+
+    let tagged = match lofty::read_from_path(path) {
+        Ok(tagged) => tagged,
+        Err(err) => {
+            debug!("Could not read the tags of {}: {err}", path.display());
+            return track;
+        }
+    };
+
+    track.duration_secs = Some(tagged.properties().duration().as_secs()).filter(|secs| *secs > 0);
+
+    if let Some(tag) = primary_tag(&tagged) {
+        let text = |value: Option<Cow<str>>| {
+            value
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        if let Some(title) = text(tag.title()) {
+            track.title = title;
+        }
+        track.artist = text(tag.artist());
+        track.album = text(tag.album());
+        track.track_number = tag.track();
+        track.has_cover = !tag.pictures().is_empty();
+    }
+
+    track
+}
+
+fn primary_tag(tagged: &TaggedFile) -> Option<&Tag> {
+    tagged.primary_tag().or_else(|| tagged.first_tag())
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(parts.join("/"))
+}
+
+fn most_common<'a>(values: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut counts = BTreeMap::<&str, usize>::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(value, _)| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(path: &str, title: &str, album: Option<&str>, number: Option<u32>) -> LibraryTrack {
+        LibraryTrack {
+            path: path.into(),
+            title: title.into(),
+            artist: Some("Daft Punk".into()),
+            album: album.map(String::from),
+            track_number: number,
+            duration_secs: Some(200),
+            has_cover: false,
+            modified: None,
+            size: 0,
+        }
+    }
+
+    fn library(tracks: Vec<LibraryTrack>) -> Library {
+        let library = Library::new(PathBuf::from("/music"));
+        *library.tracks.try_write().unwrap() = tracks
+            .into_iter()
+            .map(|track| (track.path.clone(), track))
+            .collect();
+
+        library
+    }
+
+    fn paths(tracks: &[LibraryTrack]) -> Vec<&str> {
+        tracks.iter().map(|track| track.path.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn search_wants_every_word_and_ranks_titles_first() {
+        let library = library(vec![
+            track("a/one.mp3", "One More Time", Some("Discovery"), Some(1)),
+            track("b/time.mp3", "Aerodynamic", Some("Time Capsule"), None),
+            track("c/digital.mp3", "Digital Love", Some("Discovery"), Some(3)),
+        ]);
+
+        assert_eq!(
+            paths(&library.search("time", 10).await),
+            ["a/one.mp3", "b/time.mp3"]
+        );
+        assert_eq!(
+            paths(&library.search("DISCOVERY love", 10).await),
+            ["c/digital.mp3"]
+        );
+        assert_eq!(paths(&library.search("daft punk", 1).await), ["a/one.mp3"]);
+        assert!(library.search("justice", 10).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn albums_are_in_track_order_with_unnumbered_tracks_last() {
+        let library = library(vec![
+            track("d/bonus.mp3", "Bonus", Some("Discovery"), None),
+            track("c/digital.mp3", "Digital Love", Some("Discovery"), Some(3)),
+            track("a/extra.mp3", "Extra", Some("discovery"), None),
+            track("b/one.mp3", "One More Time", Some("Discovery"), Some(1)),
+            track("e/other.mp3", "Around the World", Some("Homework"), Some(1)),
+        ]);
+
+        assert_eq!(
+            paths(&library.album("Discovery").await),
+            ["b/one.mp3", "c/digital.mp3", "d/bonus.mp3", "a/extra.mp3"]
+        );
+    }
+
+    #[test]
+    fn relative_paths_use_slashes_and_stay_inside_the_root() {
+        let root = Path::new("/music");
+
+        assert_eq!(
+            relative_path(root, Path::new("/music/Daft Punk/Discovery/01.mp3")).as_deref(),
+            Some("Daft Punk/Discovery/01.mp3")
+        );
+        assert_eq!(relative_path(root, Path::new("/other/01.mp3")), None);
+    }
+
+    #[test]
+    fn most_common_counts_values() {
+        assert_eq!(
+            most_common(["a", "b", "c", "b"].into_iter()).as_deref(),
+            Some("b")
+        );
+        assert_eq!(most_common([].into_iter()), None);
+    }
+}
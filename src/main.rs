@@ -1,35 +1,179 @@
 mod api;
+mod cli;
 mod client;
+mod cluster;
+mod config;
 mod error;
+mod library;
 mod mappers;
+mod metrics;
+mod shutdown;
+mod storage;
+
+use std::sync::Arc;
 
 use api::endpoints::api_server;
+use clap::Parser;
+use cli::{Cli, Command};
 use client::bot::start_bot;
 use dotenvy::dotenv;
+use error::AppError;
 
-use client::bot::State;
-
-#[macro_export]
-macro_rules! mugly {
-    ($e:expr) => {
-        std::sync::Arc::new(poise::serenity_prelude::Mutex::new($e))
-    };
-}
+use client::{
+    bot::{Gateway, State},
+    settings::SettingsStore,
+    sources::cache::MetadataCache,
+};
+use config::{Config, LogFormat};
+use storage::Storage;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
 #[actix_web::main]
 async fn main() {
     // Load dotenv
     dotenv().ok();
 
+    let cli = Cli::parse();
+
+    let command = match cli.check_config {
+        true => Command::CheckConfig,
+        false => cli.command.unwrap_or_default(),
+    };
+
+    let config = match Config::load(cli.config.as_deref(), command.needs_token()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprint!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    let result = match command {
+        Command::Run { no_api, no_bot } => {
+            run(config, no_api, no_bot).await;
+            Ok(())
+        }
+        Command::RegisterCommands { guild } => cli::register_commands(&config, guild).await,
+        Command::UnregisterCommands { guild } => cli::unregister_commands(&config, guild).await,
+        Command::CheckConfig => {
+            println!("Configuration is valid");
+            Ok(())
+        }
+        Command::ExportState { output } => {
+            async { cli::export_state(&load_settings(&config).await?, output).await }.await
+        }
+        Command::ImportState { file, force } => {
+            async {
+                let storage = Storage::new(&config.storage.path);
+                cli::import_state(&load_settings(&config).await?, &storage, file, force).await
+            }
+            .await
+        }
+        Command::Resolve { url } => cli::resolve(&config, &url).await,
+    };
+
+    if let Err(err) = result {
+        match err.downcast_ref::<AppError>() {
+            Some(app_error) => eprintln!("{}", app_error.message()),
+            None => eprintln!("{err}"),
+        }
+        std::process::exit(1);
+    }
+}
+
+async fn run(config: Config, no_api: bool, no_bot: bool) {
     // Init Logger
-    tracing_subscriber::fmt::init();
+    init_logging(&config);
+
+    let settings = load_settings(&config)
+        .await
+        .expect("Could not load guild settings");
+
+    let shutdown_timeout = config.shutdown.timeout();
+    let metadata_cache = MetadataCache::load(
+        Storage::new(&config.storage.path),
+        config.cache.clone(),
+        &config.cluster,
+    )
+    .await
+    .expect("Could not load the metadata cache");
 
-    let state = mugly!(State::default());
+    let storage = Storage::new(&config.storage.path);
+    let running = cli::mark_running(&storage, &config.cluster)
+        .await
+        .expect("Could not write to the storage");
+    let state = Arc::new(State::new(config, storage, settings, metadata_cache));
+    let shutdown = state.shutdown.clone();
+    if no_bot {
+        *state.gateway.lock().await = Gateway::Disabled;
+    }
+    tokio::spawn(state.metadata_cache.clone().keep_saved(shutdown.clone()));
+    if let Some(library) = state.library.clone() {
+        tokio::spawn(library.keep_indexed(shutdown.clone()));
+    }
+    if let Some(cluster) = state.cluster.clone() {
+        info!(
+            node_id = cluster.node_id(),
+            "Running as one node of a cluster"
+        );
+        tokio::spawn(cluster.keep_announcing(state.clone()));
+    }
+
+    tokio::spawn(async move {
+        let signal = shutdown::wait_for_signal().await;
+        info!("Received {signal}, shutting down");
+        shutdown.trigger();
+
+        tokio::time::sleep(shutdown_timeout).await;
+        error!("Could not shut down within {shutdown_timeout:?}, exiting anyway");
+        std::process::exit(1);
+    });
 
     tokio::join!(
         // Start API Server
-        api_server(state.clone()),
+        async {
+            match no_api {
+                true => info!("Not starting api-server because of --no-api"),
+                false => api_server(state.clone()).await,
+            }
+        },
         // Start Discord Bot
-        start_bot(state)
+        async {
+            match no_bot {
+                true => info!("Not starting bot because of --no-bot"),
+                false => start_bot(state.clone()).await,
+            }
+        }
     );
+
+    state.metadata_cache.save().await;
+    if let Err(err) = state.storage.remove(&running).await {
+        warn!("Could not remove the {running} document: {err}");
+    }
+}
+
+async fn load_settings(config: &Config) -> Result<SettingsStore, error::Error> {
+    SettingsStore::load(
+        Storage::new(&config.storage.path),
+        config.guild_defaults.clone(),
+    )
+    .await
+}
+
+fn init_logging(config: &Config) {
+    let filter = EnvFilter::try_new(&config.logging.level)
+        .expect("the filter is checked when loading the config");
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.logging.format {
+        LogFormat::Full => builder.init(),
+        LogFormat::Compact => builder.compact().init(),
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
 }
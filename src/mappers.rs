@@ -1,9 +1,14 @@
+use std::time::Duration;
+
 use songbird::tracks::TrackHandle;
 
-use crate::api::types::{Author, Track};
+use crate::{
+    api::types::{Author, Chapter, Lyrics, LyricsLine, Track},
+    client::{chapters, lyrics},
+};
 
-impl From<TrackHandle> for Track {
-    fn from(track: TrackHandle) -> Self {
+impl Track {
+    pub fn new(track: TrackHandle, chapters: &[chapters::Chapter]) -> Self {
         let metadata = track.metadata().clone();
         Self {
             title: metadata.title,
@@ -14,7 +19,38 @@ impl From<TrackHandle> for Track {
                 // TODO: refactor this string from embed_ext.rs to be globally available
                 icon_url: Some("https://raw.githubusercontent.com/Giftzwerg02/oxo/19bdb259f38a0fde3231e9957019b889e5d3280c/resources/music.png".into())
             },
-            length_secs: metadata.duration.map(|d| d.as_secs())
+            length_secs: metadata.duration.map(|d| d.as_secs()),
+            chapters: chapters.iter().map(Chapter::from).collect(),
+        }
+    }
+}
+
+impl From<&chapters::Chapter> for Chapter {
+    fn from(chapter: &chapters::Chapter) -> Self {
+        Self {
+            title: chapter.title.clone(),
+            start_secs: chapter.start,
+            end_secs: chapter.end,
+        }
+    }
+}
+
+impl Lyrics {
+    /// `lyrics` as they are `position` into the track.
+    pub fn at(lyrics: &lyrics::Lyrics, position: Duration) -> Self {
+        Self {
+            source: lyrics.source,
+            synced: lyrics.is_synced(),
+            lines: lyrics
+                .lines
+                .iter()
+                .map(|line| LyricsLine {
+                    time_ms: line.time.map(|time| time.as_millis() as u64),
+                    text: line.text.clone(),
+                })
+                .collect(),
+            current_line: lyrics.line_at(position),
+            position_ms: position.as_millis() as u64,
         }
     }
 }
@@ -0,0 +1,174 @@
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Everything oxo reports on `/metrics`.
+///
+/// Counters are updated where things happen, gauges that describe the current state
+/// (connections, queues, gateway latency) are set right before every scrape.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub voice_connections: IntGauge,
+    pub queued_tracks: IntGaugeVec,
+    pub tracks_played: IntCounter,
+    pub tracks_failed: IntCounter,
+    pub voice_disconnects: IntCounter,
+    pub voice_reconnects: IntCounter,
+    pub resolve_duration: Histogram,
+    pub resolve_failures: IntCounter,
+    pub cache_hits: IntCounter,
+    pub cache_misses: IntCounter,
+    pub cache_entries: IntGauge,
+    pub commands: IntCounterVec,
+    pub command_errors: IntCounterVec,
+    pub gateway_latency: GaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("oxo".into()), None)
+            .expect("the prefix is a valid metric name");
+
+        let metrics = Self {
+            voice_connections: IntGauge::new(
+                "voice_connections",
+                "Voice channels oxo is connected to",
+            )
+            .unwrap(),
+            queued_tracks: IntGaugeVec::new(
+                Opts::new(
+                    "queued_tracks",
+                    "Tracks in the queue, including the current one",
+                ),
+                &["guild"],
+            )
+            .unwrap(),
+            tracks_played: IntCounter::new("tracks_played_total", "Tracks that finished playing")
+                .unwrap(),
+            tracks_failed: IntCounter::new(
+                "tracks_failed_total",
+                "Queued tracks that could not be resolved once it was their turn",
+            )
+            .unwrap(),
+            voice_disconnects: IntCounter::new(
+                "voice_disconnects_total",
+                "Voice connections that dropped without oxo leaving",
+            )
+            .unwrap(),
+            voice_reconnects: IntCounter::new(
+                "voice_reconnects_total",
+                "Dropped voice connections that oxo got back",
+            )
+            .unwrap(),
+            resolve_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "ytdl_resolve_duration_seconds",
+                    "How long yt-dlp took until the metadata of a track was available",
+                )
+                .buckets(exponential_buckets(0.25, 2.0, 8).unwrap()),
+            )
+            .unwrap(),
+            resolve_failures: IntCounter::new(
+                "ytdl_resolve_failures_total",
+                "Tracks yt-dlp could not resolve",
+            )
+            .unwrap(),
+            cache_hits: IntCounter::new(
+                "metadata_cache_hits_total",
+                "Urls whose metadata was still cached",
+            )
+            .unwrap(),
+            cache_misses: IntCounter::new(
+                "metadata_cache_misses_total",
+                "Urls that had to be resolved with yt-dlp",
+            )
+            .unwrap(),
+            cache_entries: IntGauge::new("metadata_cache_entries", "Urls in the metadata cache")
+                .unwrap(),
+            commands: IntCounterVec::new(
+                Opts::new("command_invocations_total", "Invoked commands"),
+                &["command"],
+            )
+            .unwrap(),
+            command_errors: IntCounterVec::new(
+                Opts::new("command_errors_total", "Commands that failed"),
+                &["command"],
+            )
+            .unwrap(),
+            gateway_latency: GaugeVec::new(
+                Opts::new(
+                    "gateway_latency_seconds",
+                    "Latency of the last gateway heartbeat",
+                ),
+                &["shard"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        metrics.register();
+        metrics
+    }
+
+    fn register(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 14] = [
+            Box::new(self.voice_connections.clone()),
+            Box::new(self.queued_tracks.clone()),
+            Box::new(self.tracks_played.clone()),
+            Box::new(self.tracks_failed.clone()),
+            Box::new(self.voice_disconnects.clone()),
+            Box::new(self.voice_reconnects.clone()),
+            Box::new(self.resolve_duration.clone()),
+            Box::new(self.resolve_failures.clone()),
+            Box::new(self.cache_hits.clone()),
+            Box::new(self.cache_misses.clone()),
+            Box::new(self.cache_entries.clone()),
+            Box::new(self.commands.clone()),
+            Box::new(self.command_errors.clone()),
+            Box::new(self.gateway_latency.clone()),
+        ];
+
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .expect("every metric is registered once");
+        }
+    }
+
+    /// Records a single yt-dlp resolve, that started at `start`.
+    pub fn observe_resolve<T, E>(&self, start: Instant, result: &Result<T, E>) {
+        match result {
+            Ok(_) => self.resolve_duration.observe(start.elapsed().as_secs_f64()),
+            Err(_) => self.resolve_failures.inc(),
+        }
+    }
+
+    pub fn set_gateway_latency(&self, shard: u64, latency: Option<Duration>) {
+        let gauge = self
+            .gateway_latency
+            .with_label_values(&[&shard.to_string()]);
+
+        match latency {
+            Some(latency) => gauge.set(latency.as_secs_f64()),
+            None => gauge.set(f64::NAN),
+        }
+    }
+
+    /// Renders all metrics in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics can always be encoded");
+
+        String::from_utf8(buffer).expect("the text format is utf-8")
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Tells every part of oxo that the process is about to exit.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Resolves once [`Shutdown::trigger`] was called, right away if it already was.
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+
+        receiver
+            .wait_for(|triggered| *triggered)
+            .await
+            .expect("the sender lives as long as self");
+    }
+}
+
+/// Resolves with the name of the signal once SIGINT (ctrl+c) or SIGTERM (docker stop) is received.
+pub async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm =
+            signal(SignalKind::terminate()).expect("Could not register SIGTERM handler");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = sigterm.recv() => "SIGTERM",
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Could not register ctrl+c handler");
+
+        "ctrl+c"
+    }
+}
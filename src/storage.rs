@@ -0,0 +1,188 @@
+use std::{
+    io::ErrorKind,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::fs;
+use tracing::warn;
+
+use crate::error::Error;
+
+/// A lock that is older than this was left behind by a process that died while holding it.
+const STALE_LOCK: Duration = Duration::from_secs(10);
+/// How long to wait before checking a lock that someone else holds again.
+const LOCK_RETRY: Duration = Duration::from_millis(20);
+
+/// Tells apart the temporary files of saves running at the same time in this process.
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+/// Persists documents as pretty-printed json files inside a single directory.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    dir: PathBuf,
+}
+
+impl Storage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Loads the document `name`, falling back to its default if it was never saved.
+    pub async fn load<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T, Error> {
+        match fs::read(self.path(name)).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(T::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Saves the document `name`. The file is replaced atomically, so a crash
+    /// can never leave a half-written document behind.
+    pub async fn save<T: Serialize>(&self, name: &str, value: &T) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir).await?;
+
+        // unique per process and save, nodes sharing the directory may save at the same time
+        let path = self.path(name);
+        let save = SAVES.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = path.with_extension(format!("json.{}-{save}.tmp", std::process::id()));
+
+        fs::write(&tmp_path, serde_json::to_vec_pretty(value)?).await?;
+        fs::rename(tmp_path, path).await?;
+
+        Ok(())
+    }
+
+    /// Waits until no other process using this directory changes the document `name`, and
+    /// keeps them from doing so until the returned lock is dropped. Loading, changing and
+    /// saving a shared document under it can't lose the changes of another node.
+    pub async fn lock(&self, name: &str) -> Result<StorageLock, Error> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!("{name}.lock"));
+
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(_) => return Ok(StorageLock { path }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            let modified = fs::metadata(&path).await.and_then(|meta| meta.modified());
+            let age = modified.map(|modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+            });
+            match age {
+                Ok(age) if age > STALE_LOCK => {
+                    warn!(path = %path.display(), "Removing a stale storage lock");
+                    if let Err(err) = fs::remove_file(&path).await {
+                        warn!(path = %path.display(), "Could not remove a stale lock: {err}");
+                    }
+                }
+                // released in the meantime
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+                Ok(_) => tokio::time::sleep(LOCK_RETRY).await,
+            }
+        }
+    }
+
+    /// Removes the document `name`, if it exists.
+    pub async fn remove(&self, name: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(name)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The names of all documents starting with `prefix`.
+    pub async fn names(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut names = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let name = file_name
+                .to_string_lossy()
+                .strip_suffix(".json")
+                .map(String::from);
+            if let Some(name) = name.filter(|name| name.starts_with(prefix)) {
+                names.push(name);
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Makes sure documents can be written, without touching any of them.
+    pub async fn check(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir).await?;
+
+        let probe = self.dir.join(".probe");
+        fs::write(&probe, b"").await?;
+        fs::remove_file(probe).await?;
+
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.json"))
+    }
+}
+
+/// Held while a process changes a document, see [`Storage::lock`].
+#[derive(Debug)]
+pub struct StorageLock {
+    path: PathBuf,
+}
+
+impl Drop for StorageLock {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), "Could not release a storage lock: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn locked_changes_are_never_lost() {
+        let dir = std::env::temp_dir().join(format!("oxo-storage-{}", std::process::id()));
+        // two storages on the same directory, like two nodes
+        let nodes = [Storage::new(&dir), Storage::new(&dir)];
+
+        let increments = (0..40).map(|i| {
+            let storage = nodes[i % 2].clone();
+            tokio::spawn(async move {
+                let _lock = storage.lock("counter").await.unwrap();
+                let count: u32 = storage.load("counter").await.unwrap();
+                storage.save("counter", &(count + 1)).await.unwrap();
+            })
+        });
+        for increment in increments.collect::<Vec<_>>() {
+            increment.await.unwrap();
+        }
+
+        let count: u32 = nodes[0].load("counter").await.unwrap();
+        assert_eq!(count, 40);
+        assert_eq!(nodes[0].names("").await.unwrap(), ["counter"]);
+
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+}